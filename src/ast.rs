@@ -0,0 +1,128 @@
+use crate::command::{Columns, Command, CommandType, Token};
+
+///
+/// 源码中的行范围，首尾均包含在内
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: i32,
+    pub end: i32,
+}
+
+///
+/// 语法树中的阶段节点
+/// - name: 阶段名
+/// - span: 阶段在源码中的行范围，从STAGE所在行到最后一个子句所在行
+/// - columns: STAGE命令在所在行中的列范围
+/// - tokens: STAGE命令的词
+/// - clauses: STAGE之后的子句，按书写顺序排列
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageNode {
+    pub name: String,
+    pub span: Span,
+    pub columns: Columns,
+    pub tokens: Vec<Token>,
+    pub clauses: Vec<Command>,
+}
+
+///
+/// 脚本的抽象语法树，按书写顺序保留脚本中的全部命令，
+/// 供格式化、静态检查及编辑器等工具使用；解析器由它生成DFA状态迁移表
+/// - header: 第一个STAGE之前的命令(脚本头)
+/// - stages: 各阶段节点，重复定义的阶段也会保留
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub header: Vec<Command>,
+    pub stages: Vec<StageNode>,
+}
+
+impl SyntaxTree {
+    ///
+    /// 由扫描得到的命令生成语法树
+    ///
+    /// # 参数
+    /// * commands: 命令向量
+    ///
+    pub fn new(commands: Vec<Command>) -> Self {
+        let mut tree = SyntaxTree::default();
+        for command in commands {
+            if let CommandType::STAGE(name) = &command.ctype {
+                tree.stages.push(StageNode {
+                    name: name.clone(),
+                    span: Span {
+                        start: command.line,
+                        end: command.line,
+                    },
+                    columns: command.columns.clone(),
+                    tokens: command.tokens.clone(),
+                    clauses: Vec::new(),
+                });
+                continue;
+            }
+            match tree.stages.last_mut() {
+                Some(stage) => {
+                    stage.span.end = stage.span.end.max(command.line);
+                    stage.clauses.push(command);
+                }
+                None => tree.header.push(command),
+            }
+        }
+        tree
+    }
+
+    ///
+    /// 获取指定名称的阶段节点，重复定义时返回第一个
+    ///
+    pub fn stage(&self, name: &str) -> Option<&StageNode> {
+        self.stages.iter().find(|stage| stage.name == name)
+    }
+
+    ///
+    /// 将语法树还原为按书写顺序排列的命令
+    ///
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = self.header.clone();
+        for stage in &self.stages {
+            commands.push(
+                Command::new(CommandType::STAGE(stage.name.clone()), stage.span.start)
+                    .with_columns(stage.columns.clone())
+                    .with_tokens(stage.tokens.clone()),
+            );
+            commands.extend(stage.clauses.iter().cloned());
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod ast_tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_script() {
+        let source = r#"
+        NAME "客服"
+
+        STAGE initial
+        SPEAK "你好"
+        GOTO bye
+
+        STAGE bye
+        SPEAK "再见"
+
+        GOTO EXIT
+        "#;
+        let commands = Scanner::new(source.to_string()).scan().unwrap();
+        let tree = SyntaxTree::new(commands.clone());
+        assert_eq!(tree.header.len(), 1);
+        assert_eq!(tree.stages.len(), 2);
+        let bye = tree.stage("bye").unwrap();
+        assert_eq!(bye.span, Span { start: 8, end: 11 });
+        assert_eq!(bye.clauses.len(), 2);
+        assert!(matches!(&bye.clauses[1].ctype, CommandType::GOTO(stage) if stage == "EXIT"));
+        assert_eq!(tree.commands(), commands);
+    }
+}
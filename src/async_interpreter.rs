@@ -0,0 +1,231 @@
+use crate::error::Error;
+use crate::interpreter::{Interpreter, StepResult};
+use std::future::Future;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+///
+/// 异步解释器的输入输出
+///
+pub trait AsyncIo {
+    ///
+    /// 读取一行用户输入
+    ///
+    /// # 返回值
+    /// * 成功返回Some(输入)，输入结束(EOF)时返回None
+    ///
+    fn read_line(&mut self) -> impl Future<Output = io::Result<Option<String>>> + Send;
+
+    ///
+    /// 输出一段内容
+    ///
+    fn write(&mut self, text: &str) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+///
+/// 基于tokio异步读写流的输入输出，每段输出单独占一行
+///
+pub struct TokioIo<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> TokioIo<R, W>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+}
+
+impl TokioIo<BufReader<tokio::io::Stdin>, tokio::io::Stdout> {
+    ///
+    /// 使用标准输入输出
+    ///
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(tokio::io::stdin()), tokio::io::stdout())
+    }
+}
+
+impl<R, W> AsyncIo for TokioIo<R, W>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    async fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    async fn write(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await
+    }
+}
+
+///
+/// 异步解释器
+/// 通过单步解释驱动对话，输入输出均为异步操作，可直接运行在异步服务器中
+///
+pub struct AsyncInterpreter<T: AsyncIo> {
+    /// 单步解释使用的解释器，需事先载入DFA状态迁移表
+    pub interpreter: Interpreter,
+    /// 输入输出
+    pub io: T,
+}
+
+impl<T: AsyncIo> AsyncInterpreter<T> {
+    ///
+    /// 创建一个新的异步解释器
+    ///
+    pub fn new(interpreter: Interpreter, io: T) -> Self {
+        Self { interpreter, io }
+    }
+
+    ///
+    /// 运行对话直到结束
+    /// 输入结束(EOF)时与同步解释相同：当前阶段有DEFAULT分支时转移一次，否则结束对话
+    /// 等待输入超过阶段的TIMEOUT或空闲超时时，按超时设置转移
+    ///
+    /// # 返回值
+    /// * 到达EXIT或输入结束时返回Ok，IO失败或出现运行时错误时返回Error
+    ///
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let mut result = self.interpreter.step(None)?;
+        loop {
+            result = match result {
+                StepResult::Speak(speak) => {
+                    let line = format!("{}{}", self.interpreter.robot_prefix, speak);
+                    self.io.write(&line).await?;
+                    self.interpreter.step(None)?
+                }
+                StepResult::NeedInput => {
+                    let read = self.io.read_line();
+                    let line = match self.interpreter.pending_timeout() {
+                        Some(duration) => tokio::time::timeout(duration, read).await.ok(),
+                        None => Some(read.await),
+                    };
+                    match line.transpose()? {
+                        Some(Some(input)) => self.interpreter.step(Some(&input))?,
+                        Some(None) => self.interpreter.step_end()?,
+                        // 超时仍没有输入
+                        None => self.interpreter.step_timeout()?,
+                    }
+                }
+                StepResult::Finished => return Ok(()),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod async_interpreter_tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn interpreter(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.load(parse_source(source).stages);
+        interpreter
+    }
+
+    const SCRIPT: &str = r#"
+        STAGE initial
+        SPEAK "你叫什么名字"
+        INPUT name
+        NEXT greet
+        STAGE greet
+        SPEAK "你好, " + name
+        GOTO EXIT
+    "#;
+
+    #[tokio::test]
+    async fn test_async_run() {
+        let input: &[u8] = b"floation\n";
+        let mut output = Vec::new();
+        let mut dsl = AsyncInterpreter::new(interpreter(SCRIPT), TokioIo::new(input, &mut output));
+        dsl.run().await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "你叫什么名字\n你好, floation\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_run_eof() {
+        // 没有DEFAULT分支时输入结束即结束对话
+        let input: &[u8] = b"";
+        let mut output = Vec::new();
+        let mut dsl = AsyncInterpreter::new(interpreter(SCRIPT), TokioIo::new(input, &mut output));
+        dsl.run().await.unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "你叫什么名字\n");
+
+        // 有DEFAULT分支时转移一次，之后再需要输入时结束对话
+        let input: &[u8] = b"";
+        let mut output = Vec::new();
+        let mut dsl = AsyncInterpreter::new(
+            interpreter(
+                r#"
+                STAGE initial
+                SPEAK "需要什么帮助"
+                MATCH "退款"
+                NEXT EXIT
+                DEFAULT
+                NEXT unknown
+                STAGE unknown
+                SPEAK "听不懂命令"
+                MATCH "退款"
+                NEXT EXIT
+                DEFAULT
+                NEXT unknown
+                "#,
+            ),
+            TokioIo::new(input, &mut output),
+        );
+        dsl.run().await.unwrap();
+        assert_eq!(dsl.interpreter.global_env.stage, "unknown");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "需要什么帮助\n听不懂命令\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_run_timeout() {
+        // 输入流保持打开但没有数据，等待输入超时后按TIMEOUT转移
+        let (_writer, reader) = tokio::io::duplex(64);
+        let mut output = Vec::new();
+        let mut dsl = AsyncInterpreter::new(
+            interpreter(
+                r#"
+                STAGE initial
+                SPEAK "你叫什么名字"
+                INPUT name
+                NEXT EXIT
+                TIMEOUT 30 NEXT nudge
+                STAGE nudge
+                SPEAK "还在吗"
+                GOTO EXIT
+                "#,
+            ),
+            TokioIo::new(BufReader::new(reader), &mut output),
+        );
+        let start = tokio::time::Instant::now();
+        dsl.run().await.unwrap();
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(30));
+        assert_eq!(String::from_utf8(output).unwrap(), "你叫什么名字\n还在吗\n");
+    }
+
+    #[test]
+    fn test_run_future_is_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let mut dsl = AsyncInterpreter::new(interpreter(SCRIPT), TokioIo::stdio());
+        assert_send(dsl.run());
+    }
+}
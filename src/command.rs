@@ -1,26 +1,167 @@
 use std::fmt;
+use std::ops::Range;
 
 ///
 /// 对命令类型的枚举定义
 ///
 /// - MATCH(String)
+/// - FUZZYMATCH(String)
+/// - INTENT(String)
 /// - INPUT(String)
+/// - SECRETINPUT(String)
+/// - FORM
+/// - FIELD(String, Option<String>)
 /// - SPEAK(String)
+/// - SPEAKLOCALE(String, String)
 /// - NEXT(String)
 /// - STAGE(String)
 /// - DEFAULT
+/// - GOTO(String)
+/// - CONFIRM(String, String)
+/// - SWITCH(String)
+/// - CASE(String, String)
+/// - PRIORITY(u32)
+/// - CLEAR(String)
+/// - SAVE(String)
+/// - LOAD(String)
+/// - TAG(Vec<String>)
+/// - MAXTRIES(u32, String)
+/// - TIMEOUT(u64, String)
+/// - WHILE(String, String, Option<u32>)
+/// - NAME(String)
+/// - VERSION(String)
+/// - AUTHOR(String)
+/// - DSLVERSION(u32)
+/// - MATCHMODE(String)
+/// - PATTERN(String, String)
+/// - ENTRY(String)
+/// - ONERROR(String)
+/// - PROMPT(String)
+/// - ROBOTPREFIX(String)
 #[derive(Debug, Clone, PartialEq)]
 pub enum CommandType {
     MATCH(String),
+    /// 按编辑距离进行模糊匹配(MATCH~)
+    FUZZYMATCH(String),
+    /// 按关键词权重进行意图匹配
+    INTENT(String),
     INPUT(String),
+    /// 敏感输入(INPUT <var> SECRET)，输入时不回显明文，变量值不出现在输出和对话记录中
+    SECRETINPUT(String),
+    /// 表单，依次读取多个FIELD
+    FORM,
+    /// 表单字段，包含变量名和可选的校验表达式
+    FIELD(String, Option<String>),
     SPEAK(String),
+    /// 指定语言的输出(SPEAK.<lang>)，包含语言代码和输出内容
+    SPEAKLOCALE(String, String),
     NEXT(String),
     STAGE(String),
     DEFAULT,
+    /// 不等待输入，无条件转移到指定阶段
+    GOTO(String),
+    /// 是/否确认，包含回答是和回答否时转移的阶段
+    CONFIRM(String, String),
+    /// 按变量值分支
+    SWITCH(String),
+    /// SWITCH的分支，包含变量值(或DEFAULT)和转移的阶段
+    CASE(String, String),
+    /// 匹配分支的优先级
+    PRIORITY(u32),
+    /// 删除变量，参数为ALL时删除所有变量
+    CLEAR(String),
+    /// 将变量保存到文件
+    SAVE(String),
+    /// 从文件读取变量
+    LOAD(String),
+    /// 阶段标签，用于分析时按主题对阶段分组
+    TAG(Vec<String>),
+    /// 匹配失败的最大重试次数及超出后转移的阶段
+    MAXTRIES(u32, String),
+    /// 等待输入的超时秒数及超时后转移的阶段，出现在脚本头时对所有阶段生效
+    TIMEOUT(u64, String),
+    /// 条件成立时循环转移到指定阶段，包含条件、阶段和可选的最大循环次数
+    WHILE(String, String, Option<u32>),
+    /// 脚本头: 机器人名称
+    NAME(String),
+    /// 脚本头: 脚本版本
+    VERSION(String),
+    /// 脚本头: 脚本作者
+    AUTHOR(String),
+    /// 脚本头: 脚本所需的最低DSL语言版本
+    DSLVERSION(u32),
+    /// 脚本头: 默认的匹配方式(MATCH_MODE)，EXACT为整体匹配，CONTAINS为子串匹配
+    MATCHMODE(String),
+    /// 脚本头: 定义命名匹配表达式(PATTERN @name <regex>)，包含表达式名和表达式
+    PATTERN(String, String),
+    /// 指定对话的入口阶段
+    ENTRY(String),
+    /// 指定运行时错误的处理阶段(ON_ERROR)
+    ONERROR(String),
+    /// 读取用户输入前显示的提示符
+    PROMPT(String),
+    /// 机器人输出每行前的前缀
+    ROBOTPREFIX(String),
 }
 
 ///
-/// Command类型，包含命令类型和行号
+/// 命令在所在行中的列范围，列号为从1开始的字节位置，范围不包含end
+/// - keyword: 命令关键字的范围
+/// - argument: 参数的范围，没有参数时为关键字之后的空范围
+///
+/// 不是由扫描器生成的命令(如测试中构造的命令)两个范围均为0..0
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Columns {
+    pub keyword: Range<usize>,
+    pub argument: Range<usize>,
+}
+
+impl Columns {
+    ///
+    /// 是否包含列信息
+    ///
+    pub fn is_known(&self) -> bool {
+        self.keyword.start > 0
+    }
+}
+
+///
+/// 词的种类
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// 命令关键字(STAGE、SPEAK.en等)、子句关键字(NEXT、ELSE等)及保留字(EMPTY、NOT、ALL)
+    Keyword,
+    /// 变量名、阶段名、命名匹配表达式(@email)等
+    Identifier,
+    /// 双引号包裹的字符串，包含引号
+    String,
+    /// /pattern/flags形式的正则表达式
+    Regex,
+    /// 整数或小数
+    Number,
+    /// 运算符及标点，如+、==、%、逗号
+    Operator,
+    /// 其余无法归类的字符
+    Text,
+}
+
+///
+/// 行中的一个词
+/// - kind: 词的种类
+/// - text: 词的原文，字符串保留引号及其中的空白
+/// - span: 词在行中的字节范围，从0开始，不包含end
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Range<usize>,
+}
+
+///
+/// Command类型，包含命令类型、行号、列范围及词
 ///
 #[derive(Debug, Clone, PartialEq)]
 pub struct Command {
@@ -28,6 +169,10 @@ pub struct Command {
     pub ctype: CommandType,
     /// 行号, 在语法分析过程中适用于定位错误位置
     pub line: i32,
+    /// 列范围，用于在诊断信息中指出出错的关键字或参数
+    pub columns: Columns,
+    /// 命令所在行(去除注释、拼接续行并展开宏后)的词，供格式化、编辑器等工具使用
+    pub tokens: Vec<Token>,
 }
 
 impl Command {
@@ -35,7 +180,28 @@ impl Command {
     /// 生成一个新的Command
     ///
     pub fn new(ctype: CommandType, line: i32) -> Self {
-        Command { ctype, line }
+        Command {
+            ctype,
+            line,
+            columns: Columns::default(),
+            tokens: Vec::new(),
+        }
+    }
+
+    ///
+    /// 设置命令的列范围
+    ///
+    pub fn with_columns(mut self, columns: Columns) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    ///
+    /// 设置命令的词
+    ///
+    pub fn with_tokens(mut self, tokens: Vec<Token>) -> Self {
+        self.tokens = tokens;
+        self
     }
 }
 
@@ -43,11 +209,41 @@ impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.ctype {
             CommandType::MATCH(s) => write!(f, "MATCH({})", s),
+            CommandType::FUZZYMATCH(s) => write!(f, "MATCH~({})", s),
+            CommandType::INTENT(s) => write!(f, "INTENT({})", s),
             CommandType::INPUT(s) => write!(f, "INPUT({})", s),
+            CommandType::SECRETINPUT(s) => write!(f, "INPUT({} SECRET)", s),
+            CommandType::FORM => write!(f, "FORM"),
+            CommandType::FIELD(s, Some(v)) => write!(f, "FIELD({}, {})", s, v),
+            CommandType::FIELD(s, None) => write!(f, "FIELD({})", s),
             CommandType::SPEAK(s) => write!(f, "SPEAK({})", s),
+            CommandType::SPEAKLOCALE(lang, s) => write!(f, "SPEAK.{}({})", lang, s),
             CommandType::NEXT(s) => write!(f, "NEXT({})", s),
             CommandType::STAGE(s) => write!(f, "STAGE({})", s),
             CommandType::DEFAULT => write!(f, "DEFAULT"),
+            CommandType::GOTO(s) => write!(f, "GOTO({})", s),
+            CommandType::CONFIRM(y, n) => write!(f, "CONFIRM({}, {})", y, n),
+            CommandType::SWITCH(s) => write!(f, "SWITCH({})", s),
+            CommandType::CASE(v, s) => write!(f, "CASE({}, {})", v, s),
+            CommandType::PRIORITY(n) => write!(f, "PRIORITY({})", n),
+            CommandType::CLEAR(s) => write!(f, "CLEAR({})", s),
+            CommandType::SAVE(s) => write!(f, "SAVE({})", s),
+            CommandType::LOAD(s) => write!(f, "LOAD({})", s),
+            CommandType::TAG(tags) => write!(f, "TAG({})", tags.join(",")),
+            CommandType::MAXTRIES(n, s) => write!(f, "MAXTRIES({}, {})", n, s),
+            CommandType::TIMEOUT(n, s) => write!(f, "TIMEOUT({}, {})", n, s),
+            CommandType::WHILE(c, s, Some(n)) => write!(f, "WHILE({}, {}, {})", c, s, n),
+            CommandType::WHILE(c, s, None) => write!(f, "WHILE({}, {})", c, s),
+            CommandType::NAME(s) => write!(f, "NAME({})", s),
+            CommandType::VERSION(s) => write!(f, "VERSION({})", s),
+            CommandType::AUTHOR(s) => write!(f, "AUTHOR({})", s),
+            CommandType::DSLVERSION(v) => write!(f, "DSLVERSION({})", v),
+            CommandType::MATCHMODE(s) => write!(f, "MATCH_MODE({})", s),
+            CommandType::PATTERN(name, s) => write!(f, "PATTERN(@{}, {})", name, s),
+            CommandType::ENTRY(s) => write!(f, "ENTRY({})", s),
+            CommandType::ONERROR(s) => write!(f, "ON_ERROR({})", s),
+            CommandType::PROMPT(s) => write!(f, "PROMPT({})", s),
+            CommandType::ROBOTPREFIX(s) => write!(f, "ROBOT_PREFIX({})", s),
         }
     }
 }
@@ -0,0 +1,135 @@
+use crate::error::{Diagnostic, Error, Severity};
+use crate::parser::DSLParser;
+
+///
+/// 编译后脚本文件开头的魔数
+///
+pub const COMPILED_MAGIC: &[u8; 4] = b"SRBC";
+
+///
+/// 编译后脚本的格式版本，格式不兼容地改变时递增
+///
+pub const COMPILED_VERSION: u16 = 1;
+
+///
+/// 文件头长度: 魔数(4字节) + 格式版本(2字节) + 校验和(8字节)
+///
+const HEADER_LEN: usize = 14;
+
+///
+/// 计算数据的FNV-1a校验和，用于发现编译后脚本被修改或损坏
+///
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+///
+/// 判断数据是否为编译后的脚本
+///
+pub fn is_compiled(data: &[u8]) -> bool {
+    data.starts_with(COMPILED_MAGIC)
+}
+
+impl DSLParser {
+    ///
+    /// 将解析结果编译为紧凑的二进制格式，载入时无需扫描及解析
+    /// 格式为: 魔数 | 格式版本(小端) | 数据的校验和(小端) | MessagePack编码的解析结果
+    ///
+    /// # 返回值
+    /// * 成功返回编译后的数据，编码失败返回rmp_serde::encode::Error
+    ///
+    pub fn to_compiled(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+        let payload = rmp_serde::to_vec(self)?;
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(COMPILED_MAGIC);
+        data.extend_from_slice(&COMPILED_VERSION.to_le_bytes());
+        data.extend_from_slice(&checksum(&payload).to_le_bytes());
+        data.extend_from_slice(&payload);
+        Ok(data)
+    }
+
+    ///
+    /// 载入to_compiled编译得到的脚本
+    /// 载入后重新编译各阶段的正则表达式，并检查入口阶段及各转移的目标阶段是否存在
+    ///
+    /// # 参数
+    /// * data: 编译后的数据
+    ///
+    /// # 返回值
+    /// * 成功返回DSLParser；魔数、格式版本或校验和不符，数据无法解码或脚本不完整时返回Error::Parse
+    ///
+    pub fn from_compiled(data: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: &str| {
+            let error = Diagnostic::new(Severity::Error, 0, "COMPILED", message)
+                .with_code("E301")
+                .emit();
+            Error::Parse(Box::new(error))
+        };
+        if data.len() < HEADER_LEN || !is_compiled(data) {
+            return Err(invalid("Not a compiled script"));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != COMPILED_VERSION {
+            return Err(invalid(&format!(
+                "Unsupported compiled script version {}",
+                version
+            )));
+        }
+        let mut expected = [0; 8];
+        expected.copy_from_slice(&data[6..HEADER_LEN]);
+        let payload = &data[HEADER_LEN..];
+        if checksum(payload) != u64::from_le_bytes(expected) {
+            return Err(invalid("Checksum mismatch"));
+        }
+        let parser: DSLParser =
+            rmp_serde::from_slice(payload).map_err(|e| invalid(&e.to_string()))?;
+        parser.link()
+    }
+}
+
+#[cfg(test)]
+mod compiled_tests {
+    use super::*;
+    use crate::parser::parse_source as parse;
+
+    #[test]
+    fn test_compiled_round_trip() {
+        let parser = parse(
+            r#"
+            STAGE initial
+            SPEAK "还需要什么"
+            INPUT more
+            NEXT bye
+            WHILE more == "y" NEXT initial MAX 3
+            STAGE bye
+            SPEAK "再见"
+            GOTO EXIT
+            "#,
+        );
+        let data = parser.to_compiled().unwrap();
+        assert!(is_compiled(&data));
+        let loaded = DSLParser::from_compiled(&data).unwrap();
+        assert_eq!(loaded.stages, parser.stages);
+    }
+
+    #[test]
+    fn test_compiled_rejects_invalid_data() {
+        let parser = parse(
+            r#"
+            STAGE initial
+            SPEAK "再见"
+            GOTO EXIT
+            "#,
+        );
+        let data = parser.to_compiled().unwrap();
+        assert!(DSLParser::from_compiled(b"STAGE initial").is_err());
+        let mut version = data.clone();
+        version[4] = 0xff;
+        assert!(DSLParser::from_compiled(&version).is_err());
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(DSLParser::from_compiled(&tampered).is_err());
+    }
+}
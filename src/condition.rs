@@ -0,0 +1,202 @@
+use crate::env::{GlobalEnvironment, Value};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
+
+///
+/// 条件的正则表达式：<变量> <运算符> <常量>
+///
+static CONDITION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\w+)\s*(==|!=|<=|>=|<|>)\s*(.+)$").unwrap());
+
+///
+/// 条件中的比较运算符
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Operator {
+    /// ==
+    Equal,
+    /// !=
+    NotEqual,
+    /// <
+    Less,
+    /// <=
+    LessEqual,
+    /// >
+    Greater,
+    /// >=
+    GreaterEqual,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+///
+/// 变量与常量的比较条件，形如 `count < 3`、`more == "y"` 或 `vip == true`
+///
+/// - var 变量名
+/// - op 比较运算符
+/// - value 比较的常量
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub var: String,
+    pub op: Operator,
+    pub value: Value,
+}
+
+impl Condition {
+    ///
+    /// 解析条件字符串
+    ///
+    /// # 参数
+    /// * source: 条件字符串，如 `more == "y"`
+    ///
+    /// # 返回值
+    /// * 成功返回Some(条件)，格式不正确返回None
+    ///
+    pub fn parse(source: &str) -> Option<Condition> {
+        let caps = CONDITION.captures(source.trim())?;
+        let op = match &caps[2] {
+            "==" => Operator::Equal,
+            "!=" => Operator::NotEqual,
+            "<" => Operator::Less,
+            "<=" => Operator::LessEqual,
+            ">" => Operator::Greater,
+            _ => Operator::GreaterEqual,
+        };
+        let literal = caps[3].trim();
+        let value = if literal.len() >= 2 && literal.starts_with('"') && literal.ends_with('"') {
+            Value::String(literal[1..literal.len() - 1].to_string())
+        } else if let Ok(number) = literal.parse::<f64>() {
+            Value::Number(number)
+        } else if let Ok(b) = literal.parse::<bool>() {
+            Value::Bool(b)
+        } else {
+            return None;
+        };
+        Some(Condition {
+            var: caps[1].to_string(),
+            op,
+            value,
+        })
+    }
+
+    ///
+    /// 在全局环境中求值条件
+    /// 字符串的相等比较忽略大小写，大小比较要求两边均为数值
+    /// 与布尔常量比较时判断变量是否为真；布尔变量与字符串比较时字符串按true/false/yes/no解析
+    ///
+    /// # 参数
+    /// * env: 全局环境变量
+    ///
+    /// # 返回值
+    /// * 成功返回条件是否成立，变量未定义或类型不匹配时返回错误信息
+    ///
+    pub fn eval(&self, env: &GlobalEnvironment) -> Result<bool, String> {
+        let var = env
+            .get(&self.var)
+            .ok_or_else(|| format!("Variable '{}' not defined", self.var))?;
+        match (&var, &self.value, self.op) {
+            (Value::Number(a), Value::Number(b), op) => Ok(match op {
+                Operator::Equal => a == b,
+                Operator::NotEqual => a != b,
+                Operator::Less => a < b,
+                Operator::LessEqual => a <= b,
+                Operator::Greater => a > b,
+                Operator::GreaterEqual => a >= b,
+            }),
+            (a, Value::Bool(b), Operator::Equal) => Ok(a.truthy() == *b),
+            (a, Value::Bool(b), Operator::NotEqual) => Ok(a.truthy() != *b),
+            (Value::Bool(a), Value::String(b), Operator::Equal) => {
+                Ok(Value::parse_bool(b) == Some(*a))
+            }
+            (Value::Bool(a), Value::String(b), Operator::NotEqual) => {
+                Ok(Value::parse_bool(b) != Some(*a))
+            }
+            (a, b, Operator::Equal) => Ok(a.stringify().eq_ignore_ascii_case(&b.stringify())),
+            (a, b, Operator::NotEqual) => Ok(!a.stringify().eq_ignore_ascii_case(&b.stringify())),
+            (_, _, op) => Err(format!("Operator '{}' requires numbers in '{}'", op, self)),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Value::Number(n) => write!(f, "{} {} {}", self.var, self.op, n),
+            Value::String(s) => write!(f, "{} {} \"{}\"", self.var, self.op, s),
+            Value::Bool(b) => write!(f, "{} {} {}", self.var, self.op, b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod condition_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_condition() {
+        let condition = Condition::parse("more == \"y\"").unwrap();
+        assert_eq!(condition.var, "more");
+        assert_eq!(condition.op, Operator::Equal);
+        assert_eq!(condition.value, Value::String("y".to_string()));
+        let condition = Condition::parse("count<=3").unwrap();
+        assert_eq!(condition.op, Operator::LessEqual);
+        assert_eq!(condition.value, Value::Number(3.0));
+        assert_eq!(condition.to_string(), "count <= 3");
+        assert!(Condition::parse("count 3").is_none());
+        assert!(Condition::parse("more == y").is_none());
+        assert!(Condition::parse("== 3").is_none());
+    }
+
+    #[test]
+    fn test_eval_condition() {
+        let mut env = GlobalEnvironment::new();
+        env.define("more".to_string(), "Y").unwrap();
+        env.define("count".to_string(), "2").unwrap();
+        let condition = Condition::parse("more == \"y\"").unwrap();
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("more != \"y\"").unwrap();
+        assert_eq!(condition.eval(&env), Ok(false));
+        let condition = Condition::parse("count < 3").unwrap();
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("count >= 3").unwrap();
+        assert_eq!(condition.eval(&env), Ok(false));
+        let condition = Condition::parse("more > 3").unwrap();
+        assert!(condition.eval(&env).is_err());
+        let condition = Condition::parse("missing == 1").unwrap();
+        assert!(condition.eval(&env).is_err());
+    }
+
+    #[test]
+    fn test_bool_condition() {
+        let mut env = GlobalEnvironment::new();
+        env.define("vip".to_string(), "yes").unwrap();
+        env.define("count".to_string(), "0").unwrap();
+        let condition = Condition::parse("vip == true").unwrap();
+        assert_eq!(condition.to_string(), "vip == true");
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("count == false").unwrap();
+        assert_eq!(condition.value, Value::Bool(false));
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("vip == \"YES\"").unwrap();
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("vip != \"no\"").unwrap();
+        assert_eq!(condition.eval(&env), Ok(true));
+        let condition = Condition::parse("vip > true").unwrap();
+        assert!(condition.eval(&env).is_err());
+    }
+}
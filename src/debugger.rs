@@ -0,0 +1,292 @@
+use crate::env::REDACTED;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, StepResult};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+///
+/// 调试提示符
+///
+pub const DEBUG_PROMPT: &str = "(debug) ";
+
+///
+/// 调试命令的帮助信息
+///
+const DEBUG_HELP: &str = "\
+Commands:
+  s, step              run until the next stage is entered
+  c, continue          run until a breakpoint is hit
+  b, break <stage>     set a breakpoint on a stage
+  d, delete <stage>    remove a breakpoint
+  breakpoints          list breakpoints
+  where                show the current stage
+  env                  dump all variables
+  set <var> <value>    define or modify a variable
+  unset <var>          remove a variable
+  q, quit              stop debugging";
+
+///
+/// 调试命令执行后的去向
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    /// 继续在调试提示符下读取命令
+    Prompt,
+    /// 继续解释，到达下一阶段时暂停
+    Step,
+    /// 继续解释，到达断点时暂停
+    Continue,
+    /// 结束调试
+    Quit,
+}
+
+///
+/// 交互式调试器，基于解释器的单步解释
+/// 进入设置了断点的阶段(或单步执行时进入任一阶段)之前暂停，在调试提示符下检查和修改环境变量
+///
+pub struct Debugger {
+    /// 被调试的解释器，需已载入DFA状态迁移表
+    pub interpreter: Interpreter,
+    /// 断点所在的阶段
+    pub breakpoints: BTreeSet<String>,
+    /// 是否单步执行，为true时进入每个阶段之前都暂停
+    stepping: bool,
+}
+
+impl Debugger {
+    ///
+    /// 创建调试器，开始时处于单步执行状态，以便在进入入口阶段之前设置断点
+    ///
+    /// # 参数
+    /// * interpreter: 已载入DFA状态迁移表的解释器
+    ///
+    pub fn new(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            breakpoints: BTreeSet::new(),
+            stepping: true,
+        }
+    }
+
+    ///
+    /// 运行调试器
+    /// 调试命令和对话中的用户输入均从input读取，输出写入output
+    ///
+    /// # 参数
+    /// * input: 输入流
+    /// * output: 输出流
+    ///
+    /// # 返回值
+    /// * 对话结束、退出调试或输入结束时返回Ok，出现无法处理的错误时返回Error
+    ///
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> Result<(), Error> {
+        let mut reply = None;
+        loop {
+            if self.interpreter.at_stage_entry() && self.should_pause() {
+                writeln!(
+                    output,
+                    "Paused before stage '{}'",
+                    self.interpreter.global_env.stage
+                )?;
+                match self.prompt(&mut input, &mut output)? {
+                    Resume::Quit => return Ok(()),
+                    resume => self.stepping = resume == Resume::Step,
+                }
+            }
+            match self.interpreter.step_transition(reply.take().as_deref())? {
+                None => {}
+                Some(StepResult::Speak(speak)) => writeln!(output, "{}", speak)?,
+                Some(StepResult::NeedInput) => {
+                    write!(output, "> ")?;
+                    output.flush()?;
+                    match read_line(&mut input)? {
+                        Some(line) => reply = Some(line),
+                        None => return Ok(()),
+                    }
+                }
+                Some(StepResult::Finished) => {
+                    writeln!(output, "Conversation finished")?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    ///
+    /// 判断进入当前阶段之前是否需要暂停
+    ///
+    fn should_pause(&self) -> bool {
+        let stage = &self.interpreter.global_env.stage;
+        stage != "EXIT" && (self.stepping || self.breakpoints.contains(stage))
+    }
+
+    ///
+    /// 在调试提示符下反复读取并执行调试命令，直到继续解释或退出调试
+    ///
+    fn prompt(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<Resume> {
+        loop {
+            write!(output, "{}", DEBUG_PROMPT)?;
+            output.flush()?;
+            let Some(line) = read_line(input)? else {
+                return Ok(Resume::Quit);
+            };
+            let (resume, message) = self.execute(&line);
+            if !message.is_empty() {
+                writeln!(output, "{}", message)?;
+            }
+            if resume != Resume::Prompt {
+                return Ok(resume);
+            }
+        }
+    }
+
+    ///
+    /// 执行一条调试命令
+    ///
+    /// # 参数
+    /// * line: 调试命令
+    ///
+    /// # 返回值
+    /// * 命令执行后的去向及需要输出的信息
+    ///
+    fn execute(&mut self, line: &str) -> (Resume, String) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let env = &mut self.interpreter.global_env;
+        let message = match words[..] {
+            ["s" | "step"] => return (Resume::Step, String::new()),
+            ["c" | "continue"] => return (Resume::Continue, String::new()),
+            ["q" | "quit"] => return (Resume::Quit, String::new()),
+            ["b" | "break", stage] => {
+                self.breakpoints.insert(stage.to_string());
+                format!("Breakpoint set on '{}'", stage)
+            }
+            ["d" | "delete", stage] => {
+                if self.breakpoints.remove(stage) {
+                    format!("Breakpoint on '{}' removed", stage)
+                } else {
+                    format!("No breakpoint on '{}'", stage)
+                }
+            }
+            ["breakpoints"] => self
+                .breakpoints
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ["where"] => format!("Stage '{}'", env.stage),
+            ["env"] => {
+                let mut values: Vec<_> = env
+                    .variables()
+                    .into_iter()
+                    .map(|(name, value)| {
+                        if env.is_secret(name) {
+                            format!("{} = {}", name, REDACTED)
+                        } else {
+                            format!("{} = {:?}", name, value)
+                        }
+                    })
+                    .collect();
+                values.sort();
+                values.join("\n")
+            }
+            ["set", var, ..] if words.len() > 2 => {
+                // 变量值可包含空格，取变量名之后的全部内容
+                let value = line.trim()["set".len()..].trim_start()[var.len()..].trim();
+                match env.define(var.to_string(), value) {
+                    Ok(()) => format!("{} = {:?}", var, env.get(var).unwrap()),
+                    Err(message) => message,
+                }
+            }
+            ["unset", var] => match env.remove(var) {
+                Some(_) => format!("Removed '{}'", var),
+                None => format!("Variable '{}' not defined", var),
+            },
+            [] => String::new(),
+            _ => DEBUG_HELP.to_string(),
+        };
+        (Resume::Prompt, message)
+    }
+}
+
+///
+/// 读取一行输入并去除行尾换行符
+///
+/// # 返回值
+/// * 成功返回Some(该行内容)，输入结束返回None
+///
+fn read_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn debugger(source: &str) -> Debugger {
+        let mut interpreter = Interpreter::new();
+        interpreter.load(parse_source(source).stages);
+        Debugger::new(interpreter)
+    }
+
+    #[test]
+    fn test_breakpoints_and_env() {
+        let mut debugger = debugger(
+            r#"
+            STAGE initial
+            SPEAK "你叫什么名字"
+            INPUT name
+            NEXT greet
+            STAGE greet
+            SPEAK "你好, " + name
+            GOTO bye
+            STAGE bye
+            SPEAK "再见"
+            GOTO EXIT
+            "#,
+        );
+        let input = "b bye\nc\nfloation\nenv\nset name Alice Smith\nwhere\nc\n";
+        let mut output = Vec::new();
+        debugger.run(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = "\
+Paused before stage 'initial'
+(debug) Breakpoint set on 'bye'
+(debug) 你叫什么名字
+> 你好, floation
+Paused before stage 'bye'
+(debug) name = String(\"floation\")
+(debug) name = String(\"Alice Smith\")
+(debug) Stage 'bye'
+(debug) 再见
+Conversation finished
+";
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_step_and_quit() {
+        let mut debugger = debugger(
+            r#"
+            STAGE initial
+            SPEAK "第一步"
+            GOTO second
+            STAGE second
+            SPEAK "第二步"
+            GOTO EXIT
+            "#,
+        );
+        let mut output = Vec::new();
+        debugger
+            .run("s\nunset missing\nq\n".as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("第一步\nPaused before stage 'second'"));
+        assert!(output.contains("Variable 'missing' not defined"));
+        assert!(!output.contains("第二步"));
+    }
+}
@@ -1,15 +1,60 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 ///
 /// 定义DSL支持的数据类型
+/// 序列化为JSON时直接对应JSON的数值、字符串和布尔值
 ///
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Value {
     /// 数值
     Number(f64),
     /// 字符串
     String(String),
+    /// 布尔值，由比较产生或来自预设文件、JSON中的布尔值
+    Bool(bool),
+}
+
+///
+/// 数据类型运算的错误
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// 运算不支持两边的类型，如数值与布尔值相加
+    TypeMismatch {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
+    /// 除数或取余的除数为0
+    DivisionByZero,
+    /// 结果超出数值范围(无穷大或NaN)
+    Overflow(&'static str),
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::TypeMismatch { op, left, right } => {
+                write!(f, "Cannot apply '{}' to {} and {}", op, left, right)
+            }
+            ValueError::DivisionByZero => write!(f, "Division by zero"),
+            ValueError::Overflow(op) => write!(f, "Result of '{}' is out of range", op),
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
 }
 
 impl Value {
@@ -23,18 +68,205 @@ impl Value {
         match self {
             Value::Number(n) => n.to_string(),
             Value::String(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+
+    ///
+    /// 类型名，用于错误信息
+    ///
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+        }
+    }
+
+    ///
+    /// 加法: 数值相加；任一边为字符串时拼接两边的字符串形式
+    ///
+    /// # 返回值
+    /// * 成功返回结果，类型不支持或结果超出范围时返回ValueError
+    ///
+    pub fn add(&self, other: &Value) -> Result<Value, ValueError> {
+        match (self, other) {
+            (Value::String(_), _) | (_, Value::String(_)) => Ok(Value::String(format!(
+                "{}{}",
+                self.stringify(),
+                other.stringify()
+            ))),
+            _ => self.arithmetic("+", other, |a, b| a + b),
+        }
+    }
+
+    ///
+    /// 减法，两边均须为数值
+    ///
+    pub fn sub(&self, other: &Value) -> Result<Value, ValueError> {
+        self.arithmetic("-", other, |a, b| a - b)
+    }
+
+    ///
+    /// 乘法，两边均须为数值
+    ///
+    pub fn mul(&self, other: &Value) -> Result<Value, ValueError> {
+        self.arithmetic("*", other, |a, b| a * b)
+    }
+
+    ///
+    /// 除法，两边均须为数值且除数不为0
+    ///
+    pub fn div(&self, other: &Value) -> Result<Value, ValueError> {
+        if *other == Value::Number(0.0) {
+            return Err(ValueError::DivisionByZero);
+        }
+        self.arithmetic("/", other, |a, b| a / b)
+    }
+
+    ///
+    /// 取余，两边均须为数值且除数不为0
+    ///
+    pub fn rem(&self, other: &Value) -> Result<Value, ValueError> {
+        if *other == Value::Number(0.0) {
+            return Err(ValueError::DivisionByZero);
+        }
+        self.arithmetic("%", other, |a, b| a % b)
+    }
+
+    ///
+    /// 数值运算，检查两边的类型及结果是否超出范围
+    ///
+    fn arithmetic(
+        &self,
+        op: &'static str,
+        other: &Value,
+        f: impl Fn(f64, f64) -> f64,
+    ) -> Result<Value, ValueError> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => {
+                let result = f(*a, *b);
+                if result.is_finite() {
+                    Ok(Value::Number(result))
+                } else {
+                    Err(ValueError::Overflow(op))
+                }
+            }
+            _ => Err(ValueError::TypeMismatch {
+                op,
+                left: self.type_name(),
+                right: other.type_name(),
+            }),
+        }
+    }
+
+    ///
+    /// 将true/false/yes/no(忽略大小写)解析为布尔值
+    ///
+    /// # 返回值
+    /// * 成功返回Some(布尔值)，其余字符串返回None
+    ///
+    pub fn parse_bool(s: &str) -> Option<bool> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" => Some(true),
+            "false" | "no" => Some(false),
+            _ => None,
+        }
+    }
+
+    ///
+    /// 判断值在条件中是否为真
+    /// - 布尔值为其本身
+    /// - 数值非0为真，NaN为假
+    /// - 字符串为true/false/yes/no时按布尔值，否则非空为真
+    ///
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => Value::parse_bool(s).unwrap_or(!s.is_empty()),
         }
     }
 }
 
+///
+/// 受保护变量名的前缀，这些变量由系统提供，脚本只能读取
+///
+pub const PROTECTED_PREFIX: &str = "$";
+
+///
+/// 系统提供的当前阶段名变量
+///
+pub const STAGE_VAR: &str = "$stage";
+
+///
+/// 判断变量名是否属于受保护的命名空间
+///
+pub fn is_protected(name: &str) -> bool {
+    name.starts_with(PROTECTED_PREFIX)
+}
+
+///
+/// 解析形如name=value的变量赋值，变量名只能包含字母、数字和下划线，可以$开头
+///
+/// # 返回值
+/// * 成功返回Some((变量名, 变量值))，格式不正确返回None
+///
+pub fn parse_assignment(assignment: &str) -> Option<(&str, &str)> {
+    let (name, value) = assignment.split_once('=')?;
+    let bare = name.strip_prefix(PROTECTED_PREFIX).unwrap_or(name);
+    if bare.is_empty() || !bare.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some((name, value))
+}
+
+///
+/// 敏感变量的值在输出、对话记录及调试信息中的替代文本
+///
+pub const REDACTED: &str = "******";
+
+///
+/// 环境中变量的快照，由GlobalEnvironment::checkpoint生成，用于rollback
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    values: HashMap<String, Value>,
+    scopes: Vec<HashMap<String, Value>>,
+    secrets: HashSet<String>,
+    raw: HashSet<String>,
+}
+
+///
+/// 解释器与其他线程(如Ctrl-C处理函数)共享的环境变量副本，为None时尚无可保存的副本
+///
+pub type SharedEnvironment = Arc<Mutex<Option<GlobalEnvironment>>>;
+
 ///
 /// 定义全局环境变量
+/// 环境由全局变量及其上压入的作用域组成，作用域中定义的变量遮蔽同名的全局变量，
+/// 弹出作用域后全局变量恢复可见
+/// 可整体序列化为JSON，反序列化时缺少的字段取默认值
 ///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GlobalEnvironment {
     /// 全局变量
     pub values: HashMap<String, Value>,
+    /// 压入的作用域，最后一个为最内层
+    pub scopes: Vec<HashMap<String, Value>>,
     /// 当前阶段
+    /// 解释器第一次载入或解释时会按ENTRY覆盖该值，指定初始阶段应使用Interpreter::start_at
+    /// 或Interpreter::builder().stage()，而不是直接赋值
     pub stage: String,
+    /// 各阶段匹配失败的次数
+    pub retries: HashMap<String, u32>,
+    /// 各阶段WHILE循环已执行的次数
+    pub loops: HashMap<String, u32>,
+    /// 敏感变量(INPUT <var> SECRET读取)的名称
+    pub secrets: HashSet<String>,
+    /// 用户输入等运行时捕获的变量名，其值按原文使用，不展开${name}占位符
+    pub raw: HashSet<String>,
 }
 
 impl GlobalEnvironment {
@@ -45,25 +277,236 @@ impl GlobalEnvironment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            scopes: Vec::new(),
             stage: "initial".to_string(),
+            retries: HashMap::new(),
+            loops: HashMap::new(),
+            secrets: HashSet::new(),
+            raw: HashSet::new(),
         }
     }
     ///
-    /// 定义一个全局变量
+    /// 定义一个变量，有作用域时定义在最内层的作用域中
     ///
     /// # 参数
     /// * name: 变量名
     /// * value: 变量值
     ///
     /// # 返回值
-    /// * 无
+    /// * 成功返回Ok，变量名受保护(以$开头)时返回错误信息
+    ///
+    pub fn define(&mut self, name: String, value: &str) -> Result<(), String> {
+        if is_protected(&name) {
+            return Err(format!("Variable '{}' is read-only", name));
+        }
+        self.secrets.remove(&name);
+        self.raw.remove(&name);
+        let value = self.string_convert_to_value(value);
+        self.innermost().insert(name, value);
+        Ok(())
+    }
+
+    ///
+    /// 定义一个系统提供的受保护变量，如部署时注入的门店编号
+    /// 变量定义为全局变量，名称须以$开头
+    ///
+    /// # 参数
+    /// * name: 变量名，如"$store"
+    /// * value: 变量值
+    ///
+    pub fn define_builtin(&mut self, name: &str, value: &str) {
+        debug_assert!(is_protected(name));
+        self.raw.remove(name);
+        let value = self.string_convert_to_value(value);
+        self.values.insert(name.to_string(), value);
+    }
+
+    ///
+    /// 压入一个新的作用域，之后定义的变量遮蔽同名的全局变量
+    ///
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    ///
+    /// 弹出最内层的作用域，其中定义的变量随之丢弃
+    ///
+    /// # 返回值
+    /// * 有作用域时返回Some(作用域中的变量)，否则返回None
+    ///
+    pub fn pop_scope(&mut self) -> Option<HashMap<String, Value>> {
+        self.scopes.pop()
+    }
+
+    ///
+    /// 弹出最内层的作用域，并将其中的变量定义到外一层(没有外层作用域时为全局变量)
+    ///
+    pub fn commit_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            self.innermost().extend(scope);
+        }
+    }
+
+    ///
+    /// 保存当前全部变量(包括作用域及敏感变量标记)的快照
+    /// 当前阶段及各计数不在快照中
+    ///
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            values: self.values.clone(),
+            scopes: self.scopes.clone(),
+            secrets: self.secrets.clone(),
+            raw: self.raw.clone(),
+        }
+    }
+
+    ///
+    /// 将变量恢复到快照时的状态，丢弃之后定义、修改及删除的变量
+    /// 用于放弃中途取消或失败的多步流程中已收集的值
+    ///
+    /// # 参数
+    /// * checkpoint: checkpoint保存的快照
+    ///
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.values = checkpoint.values;
+        self.scopes = checkpoint.scopes;
+        self.secrets = checkpoint.secrets;
+        self.raw = checkpoint.raw;
+    }
+
+    ///
+    /// 获取当前可见的全部变量，内层作用域的变量遮蔽外层的同名变量
+    ///
+    pub fn variables(&self) -> HashMap<&String, &Value> {
+        std::iter::once(&self.values)
+            .chain(&self.scopes)
+            .flatten()
+            .collect()
+    }
+
+    ///
+    /// 最内层的作用域，没有作用域时为全局变量
+    ///
+    fn innermost(&mut self) -> &mut HashMap<String, Value> {
+        self.scopes.last_mut().unwrap_or(&mut self.values)
+    }
+
+    ///
+    /// 在解释前注入变量，如部署时由命令行指定的参数
+    /// 受保护的变量名(以$开头)定义为系统提供的变量，其余定义为全局变量
+    ///
+    /// # 参数
+    /// * name: 变量名
+    /// * value: 变量值
+    ///
+    pub fn inject(&mut self, name: &str, value: &str) {
+        if is_protected(name) {
+            self.define_builtin(name, value);
+        } else {
+            self.raw.remove(name);
+            let value = self.string_convert_to_value(value);
+            self.values.insert(name.to_string(), value);
+        }
+    }
+
+    ///
+    /// 导入以指定前缀开头的操作系统环境变量，去掉前缀并转换为小写后作为变量名，
+    /// 如前缀为BOT_时BOT_HOTLINE导入为hotline
+    /// 去掉前缀后为空或含有字母、数字和下划线以外字符的环境变量被忽略
+    ///
+    /// # 参数
+    /// * prefix: 环境变量名的前缀
+    /// * vars: 环境变量，通常为std::env::vars()
+    ///
+    /// # 返回值
+    /// * 导入的变量个数
+    ///
+    pub fn import_os(
+        &mut self,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> usize {
+        let mut count = 0;
+        for (key, value) in vars {
+            let Some(name) = key.strip_prefix(prefix) else {
+                continue;
+            };
+            let name = name.to_lowercase();
+            if parse_assignment(&format!("{}=", name)).is_none() || is_protected(&name) {
+                continue;
+            }
+            self.inject(&name, &value);
+            count += 1;
+        }
+        count
+    }
+
+    ///
+    /// 定义一个敏感变量，其值不会以明文输出
+    ///
+    /// # 参数
+    /// * name: 变量名
+    /// * value: 变量值
+    ///
+    pub fn define_secret(&mut self, name: String, value: &str) -> Result<(), String> {
+        self.define(name.clone(), value)?;
+        self.secrets.insert(name);
+        Ok(())
+    }
+
+    ///
+    /// 定义一个按原文使用的变量，如用户输入，其值中的${name}不作为占位符展开
+    ///
+    /// # 参数
+    /// * name: 变量名
+    /// * value: 变量值
+    ///
+    pub fn define_raw(&mut self, name: String, value: &str) -> Result<(), String> {
+        self.define(name.clone(), value)?;
+        self.raw.insert(name);
+        Ok(())
+    }
+
+    ///
+    /// 判断变量是否按原文使用
+    ///
+    /// # 参数
+    /// * name: 变量名
+    ///
+    pub fn is_raw(&self, name: &str) -> bool {
+        self.raw.contains(name)
+    }
+
+    ///
+    /// 判断变量是否为敏感变量
+    ///
+    /// # 参数
+    /// * name: 变量名
+    ///
+    pub fn is_secret(&self, name: &str) -> bool {
+        self.secrets.contains(name)
+    }
+
+    ///
+    /// 获取变量用于显示的值，敏感变量返回替代文本
+    ///
+    /// # 参数
+    /// * name: 变量名
+    ///
+    /// # 返回值
+    /// * 变量存在时返回Some(显示的值)，否则返回None
     ///
-    pub fn define(&mut self, name: String, value: &str) {
-        self.values
-            .insert(name, self.string_convert_to_value(value));
+    pub fn display_value(&self, name: &str) -> Option<String> {
+        let value = self.get(name)?;
+        if self.is_secret(name) {
+            Some(REDACTED.to_string())
+        } else {
+            Some(value.stringify())
+        }
     }
     ///
-    /// 获取一个全局变量
+    /// 获取一个变量，从最内层的作用域向外查找
+    /// $stage为当前阶段名
     ///
     /// # 参数
     /// * name: 变量名
@@ -72,11 +515,225 @@ impl GlobalEnvironment {
     /// * 成功返回Some(变量值)，失败返回None
     ///
     pub fn get(&self, name: &str) -> Option<Value> {
-        self.values.get(name).cloned()
+        if name == STAGE_VAR {
+            return Some(Value::String(self.stage.clone()));
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .chain(std::iter::once(&self.values))
+            .find_map(|scope| scope.get(name))
+            .cloned()
+    }
+
+    ///
+    /// 删除一个变量，只删除最内层的同名定义
+    ///
+    /// # 参数
+    /// * name: 变量名
+    ///
+    /// # 返回值
+    /// * 变量存在时返回Some(被删除的变量值)，否则返回None
+    ///
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.secrets.remove(name);
+        self.raw.remove(name);
+        self.scopes
+            .iter_mut()
+            .rev()
+            .chain(std::iter::once(&mut self.values))
+            .find_map(|scope| scope.remove(name))
+    }
+
+    ///
+    /// 删除所有变量，包括作用域中的变量
+    /// 只清空变量，当前阶段和重试次数保持不变
+    ///
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.scopes.iter_mut().for_each(HashMap::clear);
+        self.secrets.clear();
+        self.raw.clear();
+    }
+
+    ///
+    /// 将全局变量以JSON格式保存到文件
+    /// 受保护的系统变量(以$开头)由部署环境提供，敏感变量不能以明文保存，均不写入文件
+    ///
+    /// # 参数
+    /// * path: 文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，IO或序列化失败返回io::Error
+    ///
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let values: HashMap<_, _> = self
+            .values
+            .iter()
+            .filter(|(name, _)| !is_protected(name) && !self.is_secret(name))
+            .collect();
+        let json = serde_json::to_string_pretty(&values)?;
+        std::fs::write(path, json)
+    }
+
+    ///
+    /// 从JSON文件中读取全局变量，与当前变量合并(同名变量被覆盖)
+    /// 文件中的值多为之前收集的用户输入，按原文使用，不展开占位符
+    /// 文件中有受保护的变量(以$开头)时整个文件不载入，变量保持不变
+    ///
+    /// # 参数
+    /// * path: 文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，IO或反序列化失败以及包含受保护的变量时返回io::Error
+    ///
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = std::fs::read_to_string(path)?;
+        let values: HashMap<String, Value> = serde_json::from_str(&json)?;
+        if let Some(name) = values.keys().find(|name| is_protected(name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Variable '{}' is read-only", name),
+            ));
+        }
+        self.raw.extend(values.keys().cloned());
+        self.values.extend(values);
+        Ok(())
+    }
+
+    ///
+    /// 读取预设变量文件(TOML格式)，如各语言的问候语、营业时间等初始变量
+    /// 文件为变量名到值的表，值只能是字符串、数值或布尔值，按文件中的类型定义；
+    /// 以$开头的变量名定义为只读的系统变量
+    ///
+    /// ```toml
+    /// greeting_en = "Welcome"
+    /// open_hours = "9:00-21:00"
+    /// max_items = 3
+    /// "$store" = "A12"
+    /// ```
+    ///
+    /// # 参数
+    /// * path: 文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回定义的变量个数，IO失败、格式不正确或值的类型不支持时返回io::Error
+    ///
+    pub fn load_preset(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let source = std::fs::read_to_string(path)?;
+        self.apply_preset(&source)
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    ///
+    /// 由TOML格式的预设变量定义变量，格式见load_preset
+    /// 任一变量不合法时不定义任何变量
+    ///
+    /// # 返回值
+    /// * 成功返回定义的变量个数，失败返回错误信息
+    ///
+    pub fn apply_preset(&mut self, source: &str) -> Result<usize, String> {
+        let table: toml::Table = source.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut values = Vec::with_capacity(table.len());
+        for (name, value) in table {
+            if parse_assignment(&format!("{}=", name)).is_none() {
+                return Err(format!("Invalid variable name '{}'", name));
+            }
+            let value = match value {
+                toml::Value::String(s) => Value::String(s),
+                toml::Value::Integer(n) => Value::Number(n as f64),
+                toml::Value::Float(n) => Value::Number(n),
+                toml::Value::Boolean(b) => Value::Bool(b),
+                other => {
+                    return Err(format!(
+                        "Preset '{}' must be a string, number or boolean, found {}",
+                        name,
+                        other.type_str()
+                    ))
+                }
+            };
+            values.push((name, value));
+        }
+        let count = values.len();
+        for (name, value) in values {
+            self.secrets.remove(&name);
+            self.raw.remove(&name);
+            self.values.insert(name, value);
+        }
+        Ok(count)
+    }
+
+    ///
+    /// 将完整的环境(变量、当前阶段、计数及敏感变量名)序列化为JSON，
+    /// 用于会话保存、崩溃转储及与外部系统交换状态
+    ///
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    ///
+    /// 由to_json得到的JSON恢复环境
+    ///
+    /// # 返回值
+    /// * 成功返回环境，JSON格式不正确时返回serde_json::Error
+    ///
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    ///
+    /// 记录一次阶段匹配失败
+    ///
+    /// # 参数
+    /// * stage: 阶段名
+    ///
+    /// # 返回值
+    /// * 该阶段累计的匹配失败次数
+    ///
+    pub fn increase_retries(&mut self, stage: &str) -> u32 {
+        let retries = self.retries.entry(stage.to_string()).or_insert(0);
+        *retries += 1;
+        *retries
+    }
+
+    ///
+    /// 清零阶段的匹配失败次数
+    ///
+    /// # 参数
+    /// * stage: 阶段名
+    ///
+    pub fn reset_retries(&mut self, stage: &str) {
+        self.retries.remove(stage);
+    }
+
+    ///
+    /// 记录一次阶段的WHILE循环
+    ///
+    /// # 参数
+    /// * stage: 阶段名
+    ///
+    /// # 返回值
+    /// * 该阶段累计的循环次数
+    ///
+    pub fn increase_loops(&mut self, stage: &str) -> u32 {
+        let loops = self.loops.entry(stage.to_string()).or_insert(0);
+        *loops += 1;
+        *loops
+    }
+
+    ///
+    /// 清零阶段的WHILE循环次数
+    ///
+    /// # 参数
+    /// * stage: 阶段名
+    ///
+    pub fn reset_loops(&mut self, stage: &str) {
+        self.loops.remove(stage);
     }
 
     ///
     /// 将字符串转换为数据类型
+    /// 只识别数值，true/false/yes/no保留原文，在条件中求值时才按布尔值解释
     ///
     /// # 参数
     /// * s: 字符串
@@ -93,9 +750,27 @@ impl GlobalEnvironment {
     }
 }
 
+impl Default for GlobalEnvironment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Display for GlobalEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "values: {:?}", self.values)
+        // 敏感变量以替代文本显示
+        let values: HashMap<&String, Value> = self
+            .variables()
+            .into_iter()
+            .map(|(name, value)| {
+                if self.is_secret(name) {
+                    (name, Value::String(REDACTED.to_string()))
+                } else {
+                    (name, value.clone())
+                }
+            })
+            .collect();
+        write!(f, "values: {:?}", values)
     }
 }
 
@@ -106,8 +781,8 @@ mod env_tests {
     #[test]
     fn test_get_values() {
         let mut env = GlobalEnvironment::new();
-        env.define("a".to_string(), "1");
-        env.define("b".to_string(), "hello");
+        env.define("a".to_string(), "1").unwrap();
+        env.define("b".to_string(), "hello").unwrap();
         assert_eq!(env.get("a"), Some(Value::Number(1.0)));
         assert_eq!(env.get("b"), Some(Value::String("hello".to_string())));
     }
@@ -121,14 +796,315 @@ mod env_tests {
     #[test]
     fn test_get_values_override() {
         let mut env = GlobalEnvironment::new();
-        env.define("a".to_string(), "1");
-        env.define("a".to_string(), "hello");
+        env.define("a".to_string(), "1").unwrap();
+        env.define("a".to_string(), "hello").unwrap();
         assert_eq!(env.get("a"), Some(Value::String("hello".to_string())));
     }
 
+    #[test]
+    fn test_secret_values() {
+        let mut env = GlobalEnvironment::new();
+        env.define_secret("password".to_string(), "hunter2")
+            .unwrap();
+        env.define("name".to_string(), "floation").unwrap();
+        assert!(env.is_secret("password"));
+        assert_eq!(
+            env.get("password"),
+            Some(Value::String("hunter2".to_string()))
+        );
+        assert_eq!(env.display_value("password").unwrap(), REDACTED);
+        assert_eq!(env.display_value("name").unwrap(), "floation");
+        assert!(!env.to_string().contains("hunter2"));
+        // 重新定义为普通变量后不再是敏感变量
+        env.define("password".to_string(), "changed").unwrap();
+        assert!(!env.is_secret("password"));
+    }
+
     #[test]
     fn test_initial_stage() {
         let env = GlobalEnvironment::new();
         assert_eq!(env.stage, "initial");
     }
+
+    #[test]
+    fn test_retries() {
+        let mut env = GlobalEnvironment::new();
+        assert_eq!(env.increase_retries("initial"), 1);
+        assert_eq!(env.increase_retries("initial"), 2);
+        assert_eq!(env.increase_retries("other"), 1);
+        env.reset_retries("initial");
+        assert_eq!(env.increase_retries("initial"), 1);
+    }
+
+    #[test]
+    fn test_loops() {
+        let mut env = GlobalEnvironment::new();
+        assert_eq!(env.increase_loops("ask_more"), 1);
+        assert_eq!(env.increase_loops("ask_more"), 2);
+        env.reset_loops("ask_more");
+        assert_eq!(env.increase_loops("ask_more"), 1);
+        assert!(env.retries.is_empty());
+    }
+
+    #[test]
+    fn test_remove_and_clear() {
+        let mut env = GlobalEnvironment::new();
+        env.define("a".to_string(), "1").unwrap();
+        env.define("b".to_string(), "hello").unwrap();
+        assert_eq!(env.remove("a"), Some(Value::Number(1.0)));
+        assert_eq!(env.remove("a"), None);
+        env.stage = "other".to_string();
+        env.clear();
+        assert_eq!(env.get("b"), None);
+        assert_eq!(env.stage, "other");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join("service_robot_env_test.json");
+        let mut env = GlobalEnvironment::new();
+        env.define("a".to_string(), "1").unwrap();
+        env.define("b".to_string(), "hello").unwrap();
+        env.save(&path).unwrap();
+
+        let mut loaded = GlobalEnvironment::new();
+        loaded.define("b".to_string(), "world").unwrap();
+        loaded.define("c".to_string(), "kept").unwrap();
+        loaded.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get("a"), Some(Value::Number(1.0)));
+        assert_eq!(loaded.get("b"), Some(Value::String("hello".to_string())));
+        assert_eq!(loaded.get("c"), Some(Value::String("kept".to_string())));
+
+        // 受保护的变量不保存，也不能通过载入覆盖
+        env.define_builtin("$store", "A12");
+        env.save(&path).unwrap();
+        env.define_secret("pw".to_string(), "hunter2").unwrap();
+        env.save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("$store"));
+        assert!(!saved.contains("pw") && !saved.contains("hunter2"));
+        std::fs::write(&path, r#"{"$store": "evil", "a": 2}"#).unwrap();
+        let mut loaded = GlobalEnvironment::new();
+        loaded.define_builtin("$store", "A12");
+        assert!(loaded.load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get("$store"), Some(Value::String("A12".to_string())));
+        assert_eq!(loaded.get("a"), None);
+    }
+
+    #[test]
+    fn test_value_serde() {
+        let json = serde_json::to_string(&Value::Number(1.5)).unwrap();
+        assert_eq!(json, "1.5");
+        let value: Value = serde_json::from_str("\"hello\"").unwrap();
+        assert_eq!(value, Value::String("hello".to_string()));
+        let value: Value = serde_json::from_str("true").unwrap();
+        assert_eq!(value, Value::Bool(true));
+    }
+
+    #[test]
+    fn test_protected_variables() {
+        let mut env = GlobalEnvironment::new();
+        assert_eq!(
+            env.define("$stage".to_string(), "other"),
+            Err("Variable '$stage' is read-only".to_string())
+        );
+        assert!(env.define_secret("$token".to_string(), "x").is_err());
+        assert_eq!(
+            env.get("$stage"),
+            Some(Value::String("initial".to_string()))
+        );
+        env.define_builtin("$store", "A12");
+        assert_eq!(env.get("$store"), Some(Value::String("A12".to_string())));
+        assert!(env.define("$store".to_string(), "B34").is_err());
+        assert!(is_protected("$store"));
+        assert!(!is_protected("store"));
+    }
+
+    #[test]
+    fn test_inject_variables() {
+        assert_eq!(parse_assignment("name=Alice"), Some(("name", "Alice")));
+        assert_eq!(parse_assignment("greeting=a=b"), Some(("greeting", "a=b")));
+        assert_eq!(parse_assignment("$store="), Some(("$store", "")));
+        assert_eq!(parse_assignment("name"), None);
+        assert_eq!(parse_assignment("=Alice"), None);
+        assert_eq!(parse_assignment("first name=Alice"), None);
+        let mut env = GlobalEnvironment::new();
+        env.inject("vip", "true");
+        env.inject("$store", "A12");
+        assert_eq!(env.get("vip"), Some(Value::String("true".to_string())));
+        assert_eq!(env.get("$store"), Some(Value::String("A12".to_string())));
+        assert!(env.define("$store".to_string(), "B34").is_err());
+    }
+
+    #[test]
+    fn test_import_os() {
+        let vars = [
+            ("BOT_HOTLINE", "400-123-4567"),
+            ("BOT_MAX_ITEMS", "3"),
+            ("BOT_", "empty"),
+            ("BOT_A-B", "invalid"),
+            ("HOME", "/root"),
+        ];
+        let mut env = GlobalEnvironment::new();
+        let vars = vars.map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(env.import_os("BOT_", vars), 2);
+        assert_eq!(
+            env.get("hotline"),
+            Some(Value::String("400-123-4567".to_string()))
+        );
+        assert_eq!(env.get("max_items"), Some(Value::Number(3.0)));
+        assert_eq!(env.variables().len(), 2);
+    }
+
+    #[test]
+    fn test_preset() {
+        let mut env = GlobalEnvironment::new();
+        let preset = r#"
+            greeting_en = "Welcome"
+            open_hours = "9:00-21:00"
+            max_items = 3
+            discount = 0.5
+            vip = false
+            "$store" = "A12"
+        "#;
+        assert_eq!(env.apply_preset(preset), Ok(6));
+        assert_eq!(
+            env.get("open_hours"),
+            Some(Value::String("9:00-21:00".to_string()))
+        );
+        assert_eq!(env.get("max_items"), Some(Value::Number(3.0)));
+        assert_eq!(env.get("discount"), Some(Value::Number(0.5)));
+        assert_eq!(env.get("vip"), Some(Value::Bool(false)));
+        assert!(env.define("$store".to_string(), "B34").is_err());
+
+        let mut env = GlobalEnvironment::new();
+        assert_eq!(
+            env.apply_preset("greeting = \"Hi\"\nhours = [9, 21]"),
+            Err("Preset 'hours' must be a string, number or boolean, found array".to_string())
+        );
+        assert!(env.variables().is_empty());
+        assert!(env.apply_preset("[greeting]\nen = \"Hi\"").is_err());
+        assert!(env.apply_preset("\"first name\" = \"Alice\"").is_err());
+        assert!(env.apply_preset("name = ").is_err());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut env = GlobalEnvironment::new();
+        env.define("name".to_string(), "Alice").unwrap();
+        env.define("city".to_string(), "Beijing").unwrap();
+        env.push_scope();
+        env.define("name".to_string(), "Bob").unwrap();
+        assert_eq!(env.get("name"), Some(Value::String("Bob".to_string())));
+        assert_eq!(env.get("city"), Some(Value::String("Beijing".to_string())));
+        assert_eq!(env.variables().len(), 2);
+        let scope = env.pop_scope().unwrap();
+        assert_eq!(scope.len(), 1);
+        assert_eq!(env.get("name"), Some(Value::String("Alice".to_string())));
+        assert_eq!(env.pop_scope(), None);
+
+        env.push_scope();
+        env.define("name".to_string(), "Carol").unwrap();
+        assert_eq!(env.remove("name"), Some(Value::String("Carol".to_string())));
+        assert_eq!(env.get("name"), Some(Value::String("Alice".to_string())));
+        env.define("age".to_string(), "30").unwrap();
+        env.commit_scope();
+        assert!(env.scopes.is_empty());
+        assert_eq!(env.values.get("age"), Some(&Value::Number(30.0)));
+    }
+
+    #[test]
+    fn test_checkpoint_and_rollback() {
+        let mut env = GlobalEnvironment::new();
+        env.define("name".to_string(), "Alice").unwrap();
+        env.define("city".to_string(), "Beijing").unwrap();
+        let checkpoint = env.checkpoint();
+        env.define("name".to_string(), "Bob").unwrap();
+        env.define_secret("phone".to_string(), "123").unwrap();
+        env.remove("city");
+        env.stage = "form".to_string();
+        env.rollback(checkpoint);
+        assert_eq!(env.get("name"), Some(Value::String("Alice".to_string())));
+        assert_eq!(env.get("city"), Some(Value::String("Beijing".to_string())));
+        assert_eq!(env.get("phone"), None);
+        assert!(!env.is_secret("phone"));
+        assert_eq!(env.stage, "form");
+    }
+
+    #[test]
+    fn test_environment_json() {
+        let mut env = GlobalEnvironment::new();
+        env.define("count".to_string(), "2").unwrap();
+        env.define("vip".to_string(), "yes").unwrap();
+        env.define_secret("password".to_string(), "hunter2")
+            .unwrap();
+        env.stage = "order".to_string();
+        env.increase_retries("order");
+        env.increase_loops("order");
+        let json = env.to_json().unwrap();
+        assert_eq!(GlobalEnvironment::from_json(&json).unwrap(), env);
+        let partial = GlobalEnvironment::from_json(r#"{"values": {"name": "Alice"}}"#).unwrap();
+        assert_eq!(partial.stage, "initial");
+        assert_eq!(
+            partial.get("name"),
+            Some(Value::String("Alice".to_string()))
+        );
+        assert!(GlobalEnvironment::from_json("[1]").is_err());
+    }
+
+    #[test]
+    fn test_value_operators() {
+        let (two, three) = (Value::Number(2.0), Value::Number(3.0));
+        let name = Value::String("No.".to_string());
+        assert_eq!(two.add(&three), Ok(Value::Number(5.0)));
+        assert_eq!(two.sub(&three), Ok(Value::Number(-1.0)));
+        assert_eq!(two.mul(&three), Ok(Value::Number(6.0)));
+        assert_eq!(three.div(&two), Ok(Value::Number(1.5)));
+        assert_eq!(three.rem(&two), Ok(Value::Number(1.0)));
+        assert_eq!(name.add(&three), Ok(Value::String("No.3".to_string())));
+        assert_eq!(
+            two.add(&Value::Bool(true)),
+            Err(ValueError::TypeMismatch {
+                op: "+",
+                left: "number",
+                right: "bool",
+            })
+        );
+        assert_eq!(
+            name.sub(&two).unwrap_err().to_string(),
+            "Cannot apply '-' to string and number"
+        );
+        assert_eq!(
+            two.div(&Value::Number(0.0)),
+            Err(ValueError::DivisionByZero)
+        );
+        assert_eq!(
+            two.rem(&Value::Number(0.0)),
+            Err(ValueError::DivisionByZero)
+        );
+        assert_eq!(
+            Value::Number(f64::MAX).mul(&two),
+            Err(ValueError::Overflow("*"))
+        );
+    }
+
+    #[test]
+    fn test_bool_value() {
+        let mut env = GlobalEnvironment::new();
+        env.define("vip".to_string(), "Yes").unwrap();
+        env.define("subscribed".to_string(), "false").unwrap();
+        // 输入的yes/no保留原文，只在条件中按布尔值解释
+        assert_eq!(env.get("vip"), Some(Value::String("Yes".to_string())));
+        assert_eq!(env.get("vip").unwrap().stringify(), "Yes");
+        assert!(env.get("vip").unwrap().truthy());
+        assert!(!env.get("subscribed").unwrap().truthy());
+        assert_eq!(Value::Bool(false).stringify(), "false");
+        assert!(!Value::Number(0.0).truthy());
+        assert!(Value::Number(2.0).truthy());
+        assert!(!Value::String(String::new()).truthy());
+        assert!(!Value::String("No".to_string()).truthy());
+        assert!(Value::String("y".to_string()).truthy());
+    }
 }
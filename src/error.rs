@@ -1,5 +1,8 @@
+use serde::Serialize;
 use std::fmt;
 use std::io;
+use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 ///
 /// 输出报错信息
@@ -10,10 +13,194 @@ use std::io;
 /// * message: 报错信息
 ///
 /// # 返回值
-/// * 无
+/// * 输出的错误
 ///
-pub fn error(line: i32, what_: &str, message: &str) {
-    eprintln!("[line {}] Error ({}): {}", line, what_, message);
+pub fn error(line: i32, what_: &str, message: &str) -> Diagnostic {
+    Diagnostic::new(Severity::Error, line, what_, message).emit()
+}
+
+///
+/// 输出警告信息，警告不会中断解析
+///
+/// # 参数列表
+/// * line: 警告行数
+/// * what_: 警告内容
+/// * message: 警告信息
+///
+/// # 返回值
+/// * 输出的警告
+///
+pub fn warning(line: i32, what_: &str, message: &str) -> Diagnostic {
+    Diagnostic::new(Severity::Warning, line, what_, message).emit()
+}
+
+///
+/// 诊断代码及其含义，E开头为错误，W开头为警告；代码一经发布不再改变含义
+///
+pub const CODES: &[(&str, &str)] = &[
+    ("E001", "unknown command"),
+    ("E002", "missing or invalid command argument"),
+    ("E003", "unterminated string"),
+    ("E004", "unterminated block comment"),
+    ("E005", "duplicate macro definition"),
+    ("E006", "undefined macro"),
+    ("E101", "unsupported DSL version"),
+    ("E102", "command in unexpected context"),
+    ("E103", "duplicate header"),
+    ("E104", "duplicate stage definition"),
+    ("E105", "unexpected end of script"),
+    ("E106", "invalid match pattern"),
+    ("E107", "invalid clause argument"),
+    ("E201", "missing next target"),
+    ("E202", "entry stage not found"),
+    ("E203", "error handler stage not found"),
+    ("E204", "no path reaches EXIT"),
+    ("E301", "invalid exported or compiled script"),
+    ("W001", "deprecated syntax"),
+    ("W002", "unreachable stage"),
+    ("W003", "match pattern after DEFAULT"),
+    ("W004", "empty SPEAK"),
+    ("W005", "unused variable"),
+    ("W006", "line skipped in lenient scanning mode"),
+];
+
+///
+/// 诊断信息的严重程度
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// 错误，脚本无法运行
+    Error,
+    /// 警告，不中断解析
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+///
+/// 诊断信息
+/// - severity: 严重程度
+/// - line: 所在行数
+/// - columns: 出错内容在行中的列范围(可选)，列号从1开始，不包含end
+/// - what: 相关的命令或阶段
+/// - message: 诊断信息
+/// - source: 出错行的源码(可选)
+/// - hint: 修改建议(可选)
+/// - code: 稳定的诊断代码(可选)，如E102，用于分类、忽略及在文档中引用，见CODES
+///
+/// 有源码时输出出错的行，并在出错内容下方标出^，与rustc的输出类似
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: i32,
+    pub columns: Option<Range<usize>>,
+    pub what: String,
+    pub message: String,
+    pub source: Option<String>,
+    pub hint: Option<String>,
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    ///
+    /// 生成一条诊断信息，不输出
+    ///
+    pub fn new(severity: Severity, line: i32, what_: &str, message: &str) -> Self {
+        Diagnostic {
+            severity,
+            line,
+            columns: None,
+            what: what_.to_string(),
+            message: message.to_string(),
+            source: None,
+            hint: None,
+            code: None,
+        }
+    }
+
+    ///
+    /// 设置诊断代码
+    ///
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    ///
+    /// 设置出错内容的列范围，为0..0时表示列未知
+    ///
+    pub fn with_columns(mut self, columns: Range<usize>) -> Self {
+        self.columns = Some(columns).filter(|columns| columns.start > 0);
+        self
+    }
+
+    ///
+    /// 设置出错行的源码
+    ///
+    pub fn with_source(mut self, source: Option<&str>) -> Self {
+        self.source = source.map(str::to_string);
+        self
+    }
+
+    ///
+    /// 设置修改建议
+    ///
+    pub fn with_hint(mut self, hint: Option<&str>) -> Self {
+        self.hint = hint.map(str::to_string);
+        self
+    }
+
+    ///
+    /// 将诊断信息输出到标准错误
+    ///
+    pub fn emit(self) -> Self {
+        eprintln!("{}", self);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.severity)?;
+        if let Some(code) = self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, " ({}): {}", self.what, self.message)?;
+        let gutter = " ".repeat(self.line.to_string().len());
+        if let Some(source) = &self.source {
+            write!(f, "\n{} |\n{} | {}", gutter, self.line, source)?;
+            if let Some(columns) = &self.columns {
+                // 按显示宽度对齐，使中文等宽字符下方的^位置正确
+                let start = (columns.start - 1).min(source.len());
+                let end = (columns.end - 1).clamp(start, source.len());
+                if let (Some(before), Some(marked)) = (source.get(..start), source.get(start..end))
+                {
+                    let indent: String = before
+                        .chars()
+                        .map(|c| match c {
+                            '\t' => "\t".to_string(),
+                            c => " ".repeat(c.width().unwrap_or(0)),
+                        })
+                        .collect();
+                    let carets = "^".repeat(marked.width().max(1));
+                    write!(f, "\n{} | {}{}", gutter, indent, carets)?;
+                }
+            }
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+        Ok(())
+    }
 }
 
 ///
@@ -23,29 +210,107 @@ pub fn error(line: i32, what_: &str, message: &str) {
 pub enum Error {
     /// 文件读取错误
     Io(io::Error),
-    /// 词法错误
-    Scan,
-    /// 语法错误
-    Parse,
+    /// 词法错误，内容为第一个出错位置的诊断信息(所在行、命令、诊断代码及信息)
+    Scan(Box<Diagnostic>),
+    /// 语法错误，内容为第一个出错位置的诊断信息(所在行、命令、诊断代码及信息)
+    Parse(Box<Diagnostic>),
     /// 运行时错误
-    Runtime,
+    /// - stage: 出错时所在的阶段
+    /// - what: 出错的命令或操作
+    /// - message: 错误信息
+    Runtime {
+        stage: String,
+        what: String,
+        message: String,
+    },
+    /// 输入未能匹配任何分支(MATCH、INTENT或CONFIRM)，阶段设置了MAXTRIES时据此重试
+    /// - stage: 出错时所在的阶段
+    /// - message: 错误信息
+    NoMatch { stage: String, message: String },
+    /// 等待用户输入超时
+    Timeout,
+    /// 用户按Ctrl-C中断
+    Interrupted,
+    /// 输入已结束(按Ctrl-D或管道输入结束)
+    InputEnd,
+    /// 终端操作(原始模式、光标移动、读取按键等)失败
+    Terminal(io::Error),
+    /// 匹配表达式不合法，内容为该表达式
+    Pattern(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Io(underlying) => write!(f, "IoError {}", underlying),
-            Error::Scan => write!(f, "ScanError"),
-            Error::Parse => write!(f, "ParseError"),
-            Error::Runtime => write!(f, "RuntimeError"),
+            Error::Scan(_) => write!(f, "ScanError"),
+            Error::Parse(_) => write!(f, "ParseError"),
+            Error::Runtime { .. } => write!(f, "RuntimeError"),
+            Error::NoMatch { .. } => write!(f, "NoMatchError"),
+            Error::Timeout => write!(f, "TimeoutError"),
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::InputEnd => write!(f, "InputEnd"),
+            Error::Terminal(underlying) => write!(f, "TerminalError {}", underlying),
+            Error::Pattern(pattern) => write!(f, "PatternError {}", pattern),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    ///
+    /// 获取词法或语法错误的诊断信息，其余错误返回None
+    ///
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match self {
+            Error::Scan(diagnostic) | Error::Parse(diagnostic) => Some(diagnostic),
+            _ => None,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         Error::Io(err)
     }
 }
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn test_render_diagnostic() {
+        let diagnostic = Diagnostic::new(
+            Severity::Error,
+            12,
+            "NEXT(refund)",
+            "Stage 'refund' not found",
+        )
+        .with_columns(8..14)
+        .with_source(Some("  NEXT refund"))
+        .with_hint(Some("define the stage with STAGE refund"));
+        assert_eq!(
+            diagnostic.to_string(),
+            "[line 12] Error (NEXT(refund)): Stage 'refund' not found\n   |\n12 |   NEXT refund\n   |        ^^^^^^\n   = hint: define the stage with STAGE refund"
+        );
+        // 中文按显示宽度对齐
+        let diagnostic = Diagnostic::new(Severity::Error, 3, "MATCH", "Invalid regex pattern")
+            .with_columns(16..17)
+            .with_source(Some("MATCH \"退款\" ("));
+        assert!(diagnostic
+            .to_string()
+            .ends_with("\n3 | MATCH \"退款\" (\n  |              ^"));
+        let diagnostic = Diagnostic::new(Severity::Warning, 1, "STAGE a", "SPEAK is empty");
+        assert_eq!(
+            diagnostic.to_string(),
+            "[line 1] Warning (STAGE a): SPEAK is empty"
+        );
+        let diagnostic = diagnostic.with_code("W004");
+        assert_eq!(
+            diagnostic.to_string(),
+            "[line 1] Warning[W004] (STAGE a): SPEAK is empty"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::parser::{DSLParser, MatchBlock, StageBlock, Transition};
+use std::fmt::Write;
+
+///
+/// 状态迁移图中的一条边
+/// - from: 起始阶段
+/// - to: 目标阶段
+/// - label: 转移条件，如匹配表达式、输入变量等，无条件转移时为空
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub label: String,
+}
+
+///
+/// 获取匹配块在图中的标签
+///
+fn match_label(block: &MatchBlock) -> String {
+    if block.is_default() {
+        return "DEFAULT".to_string();
+    }
+    let not = if block.negated { "NOT " } else { "" };
+    let fuzzy = if block.fuzzy { "~" } else { "" };
+    match &block.flags {
+        Some(flags) => format!("{}{}/{}/{}", not, fuzzy, block.pattern, flags),
+        None => format!("{}{}{}", not, fuzzy, block.pattern),
+    }
+}
+
+///
+/// 获取阶段的所有出边，按权重随机转移的目标展开为多条边，标签加上百分比
+///
+/// # 参数
+/// * stage: 阶段
+///
+/// # 返回值
+/// * 按书写顺序排列的出边
+///
+pub fn edges(stage: &StageBlock) -> Vec<Edge> {
+    let mut targets: Vec<(String, &str)> = match &stage.transition {
+        Transition::Match(blocks) => blocks
+            .iter()
+            .map(|block| (match_label(block), block.next_stage.as_str()))
+            .collect(),
+        Transition::Switch(var, cases) => cases
+            .iter()
+            .map(|case| {
+                let label = if case.is_default() {
+                    "DEFAULT".to_string()
+                } else {
+                    format!("{} = {}", var, case.pattern)
+                };
+                (label, case.next_stage.as_str())
+            })
+            .collect(),
+        Transition::Intent(intents) => intents
+            .iter()
+            .map(|intent| (intent.to_string(), intent.next_stage.as_str()))
+            .collect(),
+        Transition::Input(input) => {
+            vec![(format!("INPUT {}", input.input_var), &input.next_stage)]
+        }
+        Transition::Goto(next_stage) => vec![(String::new(), next_stage.as_str())],
+        Transition::Form(form) => {
+            let fields: Vec<&str> = form
+                .fields
+                .iter()
+                .map(|field| field.name.as_str())
+                .collect();
+            vec![(format!("FORM {}", fields.join(", ")), &form.next_stage)]
+        }
+        Transition::Confirm(yes_stage, no_stage) => vec![
+            ("yes".to_string(), yes_stage.as_str()),
+            ("no".to_string(), no_stage.as_str()),
+        ],
+    };
+    if let Some(max_tries) = &stage.max_tries {
+        targets.push((
+            format!("MAXTRIES {}", max_tries.tries),
+            &max_tries.next_stage,
+        ));
+    }
+    if let Some(timeout) = &stage.timeout {
+        let label = format!("TIMEOUT {}s", timeout.duration.as_secs());
+        targets.push((label, &timeout.next_stage));
+    }
+    if let Some(while_loop) = &stage.while_loop {
+        let label = format!("WHILE {}", while_loop.condition);
+        targets.push((label, &while_loop.next_stage));
+    }
+    let mut edges = Vec::new();
+    for (label, target) in targets {
+        match stage.weighted_next.get(target) {
+            Some(weighted) => {
+                for (to, weight) in weighted {
+                    let label = format!("{} {}%", label, weight);
+                    edges.push(Edge {
+                        from: stage.stage.clone(),
+                        to: to.clone(),
+                        label: label.trim().to_string(),
+                    });
+                }
+            }
+            None => edges.push(Edge {
+                from: stage.stage.clone(),
+                to: target.to_string(),
+                label,
+            }),
+        }
+    }
+    edges
+}
+
+///
+/// 转义DOT中双引号包裹的字符串
+///
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl DSLParser {
+    ///
+    /// 获取状态迁移图的所有边，按起始阶段名排序，同一阶段内按书写顺序排列
+    ///
+    pub fn edges(&self) -> Vec<Edge> {
+        let mut stages: Vec<&StageBlock> = self.stages.values().collect();
+        stages.sort_by(|a, b| a.stage.cmp(&b.stage));
+        stages.into_iter().flat_map(edges).collect()
+    }
+
+    ///
+    /// 将状态迁移图导出为Graphviz DOT格式
+    /// 入口阶段以粗体显示，EXIT以双圆圈显示，边的标签为转移条件
+    ///
+    /// # 返回值
+    /// * DOT格式的图描述，可用`dot -Tsvg`等命令渲染
+    ///
+    pub fn to_dot(&self) -> String {
+        let name = self.meta.name.as_deref().unwrap_or("service_robot");
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph \"{}\" {{", escape_dot(name));
+        let _ = writeln!(dot, "    rankdir=LR;");
+        let _ = writeln!(dot, "    node [shape=box];");
+        let mut stages: Vec<&String> = self.stages.keys().collect();
+        stages.sort();
+        for stage in stages {
+            let style = if stage == self.entry_stage() {
+                " [style=bold]"
+            } else {
+                ""
+            };
+            let _ = writeln!(dot, "    \"{}\"{};", escape_dot(stage), style);
+        }
+        let edges = self.edges();
+        if edges.iter().any(|edge| edge.to == "EXIT") {
+            let _ = writeln!(dot, "    \"EXIT\" [shape=doublecircle];");
+        }
+        for edge in edges {
+            let _ = write!(
+                dot,
+                "    \"{}\" -> \"{}\"",
+                escape_dot(&edge.from),
+                escape_dot(&edge.to)
+            );
+            if !edge.label.is_empty() {
+                let _ = write!(dot, " [label=\"{}\"]", escape_dot(&edge.label));
+            }
+            let _ = writeln!(dot, ";");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    ///
+    /// 将状态迁移图导出为Mermaid状态图(stateDiagram-v2)，可直接嵌入Markdown文档
+    /// 入口阶段由起始状态[*]指向，转移到EXIT的边指向结束状态[*]
+    ///
+    /// # 返回值
+    /// * Mermaid格式的图描述
+    ///
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = String::new();
+        let _ = writeln!(mermaid, "stateDiagram-v2");
+        let _ = writeln!(mermaid, "    [*] --> {}", self.entry_stage());
+        for edge in self.edges() {
+            let to = if edge.to == "EXIT" { "[*]" } else { &edge.to };
+            let _ = write!(mermaid, "    {} --> {}", edge.from, to);
+            if !edge.label.is_empty() {
+                // 标签中的冒号和分号在Mermaid中有特殊含义，使用HTML实体表示
+                let label = edge.label.replace(';', "#59;").replace(':', "#58;");
+                let _ = write!(mermaid, " : {}", label);
+            }
+            let _ = writeln!(mermaid);
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use crate::parser::parse_source as parse;
+
+    #[test]
+    fn test_edges() {
+        let parser = parse(
+            r#"
+            STAGE initial
+            SPEAK "请问需要什么服务"
+            MATCH "退款"
+            NEXT 70% refund, 30% human
+            DEFAULT
+            NEXT initial
+            MAXTRIES 2 NEXT human
+            STAGE refund
+            SPEAK "请输入订单号"
+            INPUT order
+            NEXT EXIT
+            STAGE human
+            SPEAK "正在转接人工客服"
+            GOTO EXIT
+            "#,
+        );
+        let edges = parser.edges();
+        let edges: Vec<(&str, &str, &str)> = edges
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str(), edge.label.as_str()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("human", "EXIT", ""),
+                ("initial", "refund", "\"退款\" 70%"),
+                ("initial", "human", "\"退款\" 30%"),
+                ("initial", "initial", "DEFAULT"),
+                ("initial", "human", "MAXTRIES 2"),
+                ("refund", "EXIT", "INPUT order"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let parser = parse(
+            r#"
+            STAGE initial
+            SPEAK "需要帮助吗"
+            CONFIRM NEXT help ELSE EXIT
+            STAGE help
+            SPEAK "好的"
+            GOTO EXIT
+            "#,
+        );
+        let dot = parser.to_dot();
+        assert!(dot.starts_with("digraph \"service_robot\" {\n"));
+        assert!(dot.contains("    \"initial\" [style=bold];\n"));
+        assert!(dot.contains("    \"EXIT\" [shape=doublecircle];\n"));
+        assert!(dot.contains("    \"initial\" -> \"help\" [label=\"yes\"];\n"));
+        assert!(dot.contains("    \"help\" -> \"EXIT\";\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let parser = parse(
+            r#"
+            STAGE initial
+            SPEAK "需要帮助吗"
+            MATCH "时间:.*"
+            NEXT help
+            DEFAULT
+            NEXT EXIT
+            STAGE help
+            SPEAK "好的"
+            GOTO EXIT
+            "#,
+        );
+        assert_eq!(
+            parser.to_mermaid(),
+            "stateDiagram-v2\n    [*] --> initial\n    help --> [*]\n    initial --> help : \"时间#58;.*\"\n    initial --> [*] : DEFAULT\n"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+///
+/// 解释器读取用户输入的来源
+///
+#[derive(Debug, Default)]
+pub enum InputSource {
+    /// 标准输入: 终端中支持行编辑，管道或重定向的文件按行读取
+    #[default]
+    Stdin,
+    /// 由外部推送的文本，如语音识别(ASR)得到的语句，每条消息为一次输入
+    /// 所有发送端关闭后视为输入结束
+    Channel(Receiver<String>),
+}
+
+impl InputSource {
+    ///
+    /// 创建基于通道的输入来源
+    ///
+    /// # 返回值
+    /// * 发送端及输入来源，外部程序通过发送端推送识别出的语句
+    ///
+    pub fn channel() -> (Sender<String>, Self) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, InputSource::Channel(receiver))
+    }
+
+    ///
+    /// 由预先写好的回答创建输入来源，用于无人值守地重放整段对话
+    /// 文件的每一行依次作为一次输入，全部读取后视为输入结束
+    ///
+    /// # 参数
+    /// * path: 回答文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回输入来源，读取文件失败返回io::Error
+    ///
+    pub fn answers<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let (sender, input) = Self::channel();
+        for line in content.lines() {
+            // 接收端尚未释放，发送不会失败
+            let _ = sender.send(line.to_string());
+        }
+        Ok(input)
+    }
+}
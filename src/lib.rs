@@ -1,9 +1,30 @@
 ///
+/// 脚本的抽象语法树
+///
+pub mod ast;
+///
+/// 基于tokio的异步解释器(需启用tokio特性)
+///
+#[cfg(feature = "tokio")]
+pub mod async_interpreter;
+///
 /// 定义DSL支持的命令
 ///
 pub mod command;
 ///
-/// DSL的环境变量(所有变量均为全局变量)
+/// 编译后脚本的二进制格式
+///
+pub mod compiled;
+///
+/// 定义WHILE等语句使用的比较条件
+///
+pub mod condition;
+///
+/// 基于单步解释的交互式调试器
+///
+pub mod debugger;
+///
+/// DSL的环境变量(全局变量及其上压入的作用域栈，内层作用域遮蔽同名的全局变量)
 ///
 pub mod env;
 ///
@@ -11,14 +32,58 @@ pub mod env;
 ///
 pub mod error;
 ///
+/// 将DFA状态迁移表导出为Graphviz DOT、Mermaid等图形描述
+///
+pub mod graph;
+///
+/// 用户输入的来源(标准输入或外部推送的文本)
+///
+pub mod input;
+///
 /// 定义DSL解释器
 ///
 pub mod interpreter;
 ///
+/// 终端输入的行编辑(光标移动、行内插入删除等)
+///
+pub mod line_editor;
+///
+/// 解释器事件的监听器接口
+///
+pub mod listener;
+///
+/// 匹配前对用户输入的预处理(Unicode规范化、全角转半角等)
+///
+pub mod normalize;
+///
 /// 解析DSL命令向量，得到DSL的DFA状态迁移表
 ///
 pub mod parser;
 ///
+/// 内置及脚本定义的命名匹配表达式(@email等)
+///
+pub mod patterns;
+///
 /// 扫描源代码，进行词法分析，得到DSL的命令向量
 ///
 pub mod scanner;
+///
+/// 共享同一脚本的多会话支持
+///
+pub mod session;
+///
+/// 终端输出的配色方案
+///
+pub mod theme;
+///
+/// 将解释器的转移过程输出到标准错误或文件的跟踪器
+///
+pub mod trace;
+///
+/// 对话记录(机器人的输出及用户的输入)
+///
+pub mod transcript;
+///
+/// 终端原始模式的恢复(RAII守卫及panic钩子)
+///
+pub mod tty;
@@ -0,0 +1,483 @@
+use crossterm::{
+    cursor,
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    terminal::{self, ClearType},
+};
+use std::io::{self, Write};
+use unicode_width::UnicodeWidthChar;
+
+///
+/// 正在编辑的一行输入
+/// 按字符保存内容，光标位置为字符序号，支持在行中任意位置插入和删除
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+    /// 显示时代替每个字符的掩码字符，用于敏感输入
+    mask: Option<char>,
+}
+
+impl LineBuffer {
+    ///
+    /// 创建一个空行
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// 创建一个显示时以掩码字符代替内容的空行
+    ///
+    /// # 参数
+    /// * mask: 掩码字符
+    ///
+    pub fn masked(mask: char) -> Self {
+        Self {
+            mask: Some(mask),
+            ..Self::default()
+        }
+    }
+
+    ///
+    /// 获取当前内容
+    ///
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    ///
+    /// 获取光标位置(字符序号)
+    ///
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    ///
+    /// 获取光标之前内容的显示宽度，中文等全角字符占两列
+    ///
+    pub fn cursor_width(&self) -> usize {
+        self.chars[..self.cursor]
+            .iter()
+            .map(|c| self.mask.unwrap_or(*c).width().unwrap_or(0))
+            .sum()
+    }
+
+    ///
+    /// 获取显示的内容，设置了掩码字符时每个字符均显示为掩码字符
+    ///
+    pub fn display_text(&self) -> String {
+        match self.mask {
+            Some(mask) => self.chars.iter().map(|_| mask).collect(),
+            None => self.text(),
+        }
+    }
+
+    ///
+    /// 在光标处插入字符
+    ///
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    ///
+    /// 删除光标前的字符(Backspace)
+    ///
+    /// # 返回值
+    /// * 删除了字符返回true，光标在行首返回false
+    ///
+    pub fn backspace(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        self.chars.remove(self.cursor);
+        true
+    }
+
+    ///
+    /// 删除光标处的字符(Delete)
+    ///
+    /// # 返回值
+    /// * 删除了字符返回true，光标在行尾返回false
+    ///
+    pub fn delete(&mut self) -> bool {
+        if self.cursor == self.chars.len() {
+            return false;
+        }
+        self.chars.remove(self.cursor);
+        true
+    }
+
+    ///
+    /// 删除光标前的一个单词及其后的空白(Ctrl-W)
+    ///
+    /// # 返回值
+    /// * 删除了字符返回true，否则返回false
+    ///
+    pub fn delete_word(&mut self) -> bool {
+        let end = self.cursor;
+        while self.cursor > 0 && self.chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        while self.cursor > 0 && !self.chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        self.chars.drain(self.cursor..end);
+        self.cursor != end
+    }
+
+    ///
+    /// 以新内容替换整行，光标移动到行尾
+    ///
+    /// # 参数
+    /// * text: 新内容
+    ///
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    ///
+    /// 清空整行(Ctrl-U)
+    ///
+    pub fn clear(&mut self) {
+        self.chars.clear();
+        self.cursor = 0;
+    }
+
+    ///
+    /// 按候选项补全光标之前的内容(Tab)，比较时忽略大小写
+    /// 只有一个候选项以其开头时补全为该候选项，有多个时补全到它们的最长公共前缀
+    ///
+    /// # 参数
+    /// * options: 候选项
+    ///
+    /// # 返回值
+    /// * 内容被补全时返回true，否则返回false
+    ///
+    pub fn complete(&mut self, options: &[String]) -> bool {
+        let prefix: String = self.chars[..self.cursor].iter().collect();
+        let prefix = prefix.to_lowercase();
+        let candidates: Vec<Vec<char>> = options
+            .iter()
+            .filter(|option| option.to_lowercase().starts_with(&prefix))
+            .map(|option| option.chars().collect())
+            .collect();
+        let Some((first, rest)) = candidates.split_first() else {
+            return false;
+        };
+        let common = rest.iter().fold(first.len(), |len, candidate| {
+            first
+                .iter()
+                .zip(candidate)
+                .take(len)
+                .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+                .count()
+        });
+        if common <= self.cursor {
+            return false;
+        }
+        self.chars
+            .splice(..self.cursor, first[..common].iter().copied());
+        self.cursor = common;
+        true
+    }
+
+    ///
+    /// 处理编辑按键
+    /// 支持左右方向键、Home/End、Backspace/Delete(Ctrl-D)、Ctrl-U清空整行、Ctrl-W删除单词及字符输入
+    ///
+    /// # 参数
+    /// * key: 按键事件
+    ///
+    /// # 返回值
+    /// * 按键为编辑按键时返回true，调用者需重新显示该行；其余按键返回false
+    ///
+    pub fn apply(&mut self, key: &KeyEvent) -> bool {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.chars.len(),
+            KeyCode::Backspace => {
+                self.backspace();
+            }
+            KeyCode::Delete => {
+                self.delete();
+            }
+            KeyCode::Char('d') if control => {
+                self.delete();
+            }
+            KeyCode::Char('u') if control => self.clear(),
+            KeyCode::Char('w') if control => {
+                self.delete_word();
+            }
+            KeyCode::Char(_) if control => return false,
+            KeyCode::Char(c) => self.insert(c),
+            _ => return false,
+        }
+        true
+    }
+
+    ///
+    /// 在终端当前行重新显示内容，并将光标移动到编辑位置
+    /// 只重绘输入开始的列之后的部分，同一行中之前输出的提示符保持不变
+    ///
+    /// # 参数
+    /// * output: 输出流
+    /// * origin: 输入开始的列
+    ///
+    pub fn render(&self, output: &mut impl Write, origin: u16) -> io::Result<()> {
+        let column = origin as usize + self.cursor_width();
+        queue!(
+            output,
+            cursor::MoveToColumn(origin),
+            terminal::Clear(ClearType::UntilNewLine)
+        )?;
+        write!(output, "{}", self.display_text())?;
+        queue!(output, cursor::MoveToColumn(column as u16))?;
+        output.flush()
+    }
+}
+
+///
+/// 输入历史，保存之前的输入，编辑时用上下方向键调出
+/// 浏览历史时保留尚未提交的输入，回到最新位置时恢复
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    entries: Vec<String>,
+    /// 浏览位置，等于entries.len()时为正在编辑的新输入
+    position: usize,
+    /// 开始浏览历史前正在编辑的内容
+    draft: String,
+}
+
+impl History {
+    ///
+    /// 创建空的输入历史
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// 获取全部历史输入，按输入顺序排列
+    ///
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    ///
+    /// 追加一条输入并回到最新位置，空输入及与上一条相同的输入不重复保存
+    ///
+    /// # 参数
+    /// * input: 提交的输入
+    ///
+    pub fn push(&mut self, input: &str) {
+        if !input.is_empty() && self.entries.last().map(String::as_str) != Some(input) {
+            self.entries.push(input.to_string());
+        }
+        self.reset();
+    }
+
+    ///
+    /// 回到最新位置，开始读取新的一行时调用
+    ///
+    pub fn reset(&mut self) {
+        self.position = self.entries.len();
+        self.draft.clear();
+    }
+
+    ///
+    /// 调出上一条历史输入(Up)
+    ///
+    /// # 参数
+    /// * current: 当前正在编辑的内容，从最新位置离开时保存
+    ///
+    /// # 返回值
+    /// * 上一条历史输入，已在最早一条时返回None
+    ///
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        if self.position == 0 {
+            return None;
+        }
+        if self.position == self.entries.len() {
+            self.draft = current.to_string();
+        }
+        self.position -= 1;
+        Some(&self.entries[self.position])
+    }
+
+    ///
+    /// 调出下一条历史输入(Down)，越过最新一条时恢复之前正在编辑的内容
+    ///
+    /// # 返回值
+    /// * 下一条历史输入或之前正在编辑的内容，已在最新位置时返回None
+    ///
+    pub fn newer(&mut self) -> Option<&str> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(self.entries.get(self.position).unwrap_or(&self.draft))
+    }
+
+    ///
+    /// 处理上下方向键，将调出的内容填入正在编辑的行
+    ///
+    /// # 参数
+    /// * key: 按键事件
+    /// * line: 正在编辑的行
+    ///
+    /// # 返回值
+    /// * 行内容被替换时返回true，调用者需重新显示该行；其余情况返回false
+    ///
+    pub fn apply(&mut self, key: &KeyEvent, line: &mut LineBuffer) -> bool {
+        let recalled = match key.code {
+            KeyCode::Up => self.older(&line.text()),
+            KeyCode::Down => self.newer(),
+            _ => None,
+        };
+        match recalled {
+            Some(text) => {
+                line.set(text);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod line_editor_tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn typed(text: &str) -> LineBuffer {
+        let mut line = LineBuffer::new();
+        for c in text.chars() {
+            line.apply(&key(KeyCode::Char(c)));
+        }
+        line
+    }
+
+    #[test]
+    fn test_cursor_movement_and_insertion() {
+        let mut line = typed("helo");
+        line.apply(&key(KeyCode::Left));
+        line.apply(&key(KeyCode::Char('l')));
+        assert_eq!(line.text(), "hello");
+        assert_eq!(line.cursor(), 4);
+        line.apply(&key(KeyCode::Home));
+        line.apply(&key(KeyCode::Left));
+        assert_eq!(line.cursor(), 0);
+        line.apply(&key(KeyCode::Delete));
+        assert_eq!(line.text(), "ello");
+        line.apply(&ctrl('d'));
+        assert_eq!(line.text(), "llo");
+        line.insert('e');
+        line.apply(&key(KeyCode::End));
+        line.apply(&key(KeyCode::Right));
+        assert_eq!(line.cursor(), 4);
+        line.apply(&key(KeyCode::Backspace));
+        assert_eq!(line.text(), "ell");
+    }
+
+    #[test]
+    fn test_delete_word_and_clear() {
+        let mut line = typed("order  12345 ");
+        line.apply(&ctrl('w'));
+        assert_eq!(line.text(), "order  ");
+        line.apply(&ctrl('w'));
+        assert_eq!(line.text(), "");
+        assert!(!line.delete_word());
+        let mut line = typed("abc");
+        line.apply(&ctrl('u'));
+        assert_eq!(line, LineBuffer::new());
+        // 其余Ctrl组合键不是编辑按键
+        assert!(!line.apply(&ctrl('x')));
+        assert!(!line.apply(&key(KeyCode::Enter)));
+    }
+
+    #[test]
+    fn test_cursor_width() {
+        let mut line = typed("你好ab");
+        assert_eq!(line.cursor_width(), 6);
+        line.apply(&key(KeyCode::Left));
+        line.apply(&key(KeyCode::Left));
+        line.apply(&key(KeyCode::Left));
+        assert_eq!(line.cursor_width(), 2);
+    }
+
+    #[test]
+    fn test_masked() {
+        let mut line = LineBuffer::masked('*');
+        for c in "密码12".chars() {
+            line.insert(c);
+        }
+        assert_eq!(line.text(), "密码12");
+        assert_eq!(line.display_text(), "****");
+        assert_eq!(line.cursor_width(), 4);
+    }
+
+    #[test]
+    fn test_complete() {
+        let options = [
+            "Refund".to_string(),
+            "refresh".to_string(),
+            "退货".to_string(),
+        ];
+        let mut line = typed("re");
+        // 多个候选项时补全到最长公共前缀
+        assert!(line.complete(&options));
+        assert_eq!(line.text(), "Ref");
+        assert!(!line.complete(&options));
+        line.insert('u');
+        assert!(line.complete(&options));
+        assert_eq!(line.text(), "Refund");
+        assert_eq!(line.cursor(), 6);
+
+        let mut line = typed("退");
+        assert!(line.complete(&options));
+        assert_eq!(line.text(), "退货");
+        assert!(!typed("x").complete(&options));
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let mut history = History::new();
+        history.push("A1001");
+        history.push("");
+        history.push("a@b.com");
+        history.push("a@b.com");
+        assert_eq!(history.entries(), ["A1001", "a@b.com"]);
+
+        let mut line = typed("A10");
+        assert!(history.apply(&key(KeyCode::Up), &mut line));
+        assert_eq!(line.text(), "a@b.com");
+        assert!(history.apply(&key(KeyCode::Up), &mut line));
+        assert_eq!(line.text(), "A1001");
+        assert_eq!(line.cursor(), 5);
+        assert!(!history.apply(&key(KeyCode::Up), &mut line));
+        history.apply(&key(KeyCode::Down), &mut line);
+        history.apply(&key(KeyCode::Down), &mut line);
+        // 越过最新一条时恢复未提交的输入
+        assert_eq!(line.text(), "A10");
+        assert!(!history.apply(&key(KeyCode::Down), &mut line));
+
+        history.push("A1002");
+        assert_eq!(history.older(""), Some("A1002"));
+    }
+}
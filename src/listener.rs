@@ -0,0 +1,72 @@
+use crate::parser::MatchBlock;
+
+///
+/// 解释器事件的监听器，宿主程序注册后可据此更新界面、统计或记录日志
+/// 所有方法均有空的默认实现，只需实现关心的事件
+/// 敏感输入(INPUT <var> SECRET)及敏感变量的插值以替代文本传给监听器
+///
+pub trait EventListener: Send {
+    ///
+    /// 进入阶段时调用，在输出该阶段的内容之前
+    ///
+    /// # 参数
+    /// * stage: 进入的阶段
+    ///
+    fn on_stage_enter(&self, _stage: &str) {}
+
+    ///
+    /// 机器人输出内容(SPEAK、确认提示语、表单字段名等)时调用
+    ///
+    /// # 参数
+    /// * stage: 当前阶段
+    /// * text: 输出内容
+    ///
+    fn on_speak(&self, _stage: &str, _text: &str) {}
+
+    ///
+    /// 读取到用户输入时调用
+    ///
+    /// # 参数
+    /// * stage: 当前阶段
+    /// * input: 用户输入
+    ///
+    fn on_input(&self, _stage: &str, _input: &str) {}
+
+    ///
+    /// 用户输入与匹配块匹配成功时调用
+    ///
+    /// # 参数
+    /// * stage: 当前阶段
+    /// * block: 匹配成功的匹配块
+    ///
+    fn on_match(&self, _stage: &str, _block: &MatchBlock) {}
+
+    ///
+    /// 尝试用匹配块匹配用户输入后调用，按尝试的顺序，匹配成功后不再尝试之后的匹配块
+    ///
+    /// # 参数
+    /// * stage: 当前阶段
+    /// * block: 尝试的匹配块
+    /// * matched: 是否匹配成功
+    ///
+    fn on_pattern(&self, _stage: &str, _block: &MatchBlock, _matched: bool) {}
+
+    ///
+    /// 脚本定义变量(INPUT、FORM字段、error_message等)后调用
+    ///
+    /// # 参数
+    /// * stage: 当前阶段
+    /// * name: 变量名
+    /// * value: 变量值，敏感变量为替代文本
+    ///
+    fn on_variable(&self, _stage: &str, _name: &str, _value: &str) {}
+
+    ///
+    /// 出现运行时错误时调用
+    ///
+    /// # 参数
+    /// * stage: 出错的阶段
+    /// * message: 错误信息
+    ///
+    fn on_error(&self, _stage: &str, _message: &str) {}
+}
@@ -1,21 +1,228 @@
-use service_robot::{error::Error, interpreter::Interpreter, parser::DSLParser, scanner::Scanner};
-use std::io::{self, Write};
+use service_robot::{
+    compiled,
+    debugger::Debugger,
+    env,
+    error::Error,
+    input::InputSource,
+    interpreter::{Interpreter, DEFAULT_MAX_STEPS, DEFAULT_TYPEWRITER_DELAY},
+    normalize::Normalizer,
+    parser::DSLParser,
+    scanner::Scanner,
+    theme::{self, Theme},
+    trace::Tracer,
+    transcript::SharedTranscript,
+    tty,
+};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
 use std::process::exit;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 
-struct DSL {
+struct Dsl {
     interpreter: Interpreter,
+    /// 宽松扫描模式(--lenient)
+    lenient: bool,
+    /// 忽略关键字的大小写(--ignore-case)
+    ignore_case: bool,
+    /// 对话记录的保存路径(--transcript=<path>)
+    transcript: Option<String>,
+    /// 交互式调试模式(--debug)
+    debug: bool,
+    /// 允许进入阶段的最大次数(--max-steps=<n>)
+    max_steps: usize,
+    /// 终端输出的配色方案(--robot-style=<style>、--prompt-style=<style>、--no-color)
+    theme: Theme,
+    /// 逐字输出时每个字符的间隔(--typewriter[=<ms>])
+    typewriter: Option<Duration>,
+    /// 匹配前对用户输入的预处理(--normalize[=<steps>])
+    normalizer: Normalizer,
+    /// 开始的阶段，默认为脚本的入口阶段(--start-stage=<stage>)
+    start_stage: Option<String>,
+    /// 跟踪转移过程，为Some时输出到标准错误(空路径)或指定文件(--trace[=<path>])
+    trace: Option<String>,
+    /// 只扫描、解析并分析脚本，按结果设置退出码，不运行(--check或--lint)，等同于check子命令
+    check_only: bool,
+    /// 将解析时的警告视为错误(--strict)
+    strict: bool,
+    /// 输出Graphviz DOT格式的状态迁移图，不运行(--dot)
+    dot: bool,
+    /// 输出Mermaid格式的状态迁移图，不运行(--mermaid)
+    mermaid: bool,
+    /// 以JSON格式输出解析得到的阶段表及脚本元信息，不运行(--emit-json)
+    emit_json: bool,
+    /// 输出按定义顺序编号并标出所在行的阶段表，不运行(--print-dfa)
+    print_dfa: bool,
+    /// 忽略的警告代码(--allow=<code>[,<code>])
+    allowed: Vec<String>,
+    /// 解析后将错误及警告以JSON格式输出到标准输出(--diagnostics=json)
+    diagnostics_json: bool,
+    /// 变量的持久化文件，开始时读取，结束时保存(--env-file=<path>)
+    env_file: Option<String>,
+    /// 解释前注入的变量(--var <name>=<value>)
+    vars: Vec<(String, String)>,
+    /// 导入为变量的操作系统环境变量的前缀(--env-prefix=<prefix>)
+    env_prefix: Option<String>,
+    /// TOML格式的预设变量文件(--preset <path>)
+    preset: Option<String>,
+    /// 会话闲置超时(--idle-timeout=<seconds>)
+    idle_timeout: Option<Duration>,
+    /// 会话闲置超时后转移到的阶段(--expiry-stage=<stage>)
+    expiry_stage: Option<String>,
+    /// 从标准输入读取脚本(--stdin)，等同于脚本路径为-
+    stdin: bool,
+    /// 依次作为用户输入的回答文件，每行一次输入(--answers <path>)
+    answers: Option<String>,
 }
 
-impl DSL {
+impl Dsl {
     fn new() -> Self {
         Self {
             interpreter: Interpreter::new(),
+            lenient: false,
+            ignore_case: false,
+            transcript: None,
+            debug: false,
+            max_steps: DEFAULT_MAX_STEPS,
+            theme: Theme::colored(),
+            typewriter: None,
+            normalizer: Normalizer::default(),
+            start_stage: None,
+            trace: None,
+            check_only: false,
+            strict: false,
+            dot: false,
+            mermaid: false,
+            emit_json: false,
+            print_dfa: false,
+            allowed: Vec::new(),
+            diagnostics_json: false,
+            env_file: None,
+            vars: Vec::new(),
+            env_prefix: None,
+            preset: None,
+            idle_timeout: None,
+            expiry_stage: None,
+            stdin: false,
+            answers: None,
         }
     }
 
+    ///
+    /// 载入脚本
+    /// 以魔数开头的文件为编译后的脚本，.json文件为--emit-json导出的解析结果，均直接载入，
+    /// 其余文件作为DSL源码扫描并解析
+    /// 路径为-时从标准输入读取脚本直到输入结束，此后对话中读取用户输入时视为输入结束
+    ///
+    /// # 参数
+    /// * path: 脚本文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回解析结果，失败返回Error
+    ///
+    fn load(&self, path: &str) -> Result<DSLParser, Error> {
+        let data = if path == STDIN_PATH {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            data
+        } else {
+            std::fs::read(path)?
+        };
+        if compiled::is_compiled(&data) {
+            return DSLParser::from_compiled(&data);
+        }
+        let source =
+            String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if path.ends_with(".json") {
+            return DSLParser::from_json(&source);
+        }
+        let mut parser = DSLParser::new();
+        parser.set_source(&source);
+        for code in &self.allowed {
+            parser.allow(code);
+        }
+        let mut scanner = Scanner::new(source);
+        scanner.set_lenient(self.lenient);
+        scanner.set_ignore_case(self.ignore_case);
+        let commands = match scanner.scan() {
+            Ok(commands) => commands,
+            Err(e) => {
+                if self.diagnostics_json {
+                    let errors = serde_json::to_string(&scanner.errors());
+                    println!("{}", errors.map_err(io::Error::from)?);
+                }
+                return Err(e);
+            }
+        };
+        let result = parser.parse(commands);
+        if self.diagnostics_json {
+            println!("{}", parser.diagnostics_json().map_err(io::Error::from)?);
+        }
+        result?;
+        Ok(parser)
+    }
+
+    ///
+    /// 检查脚本而不运行(check子命令)，用于持续集成中校验脚本仓库
+    /// 依次扫描、解析各脚本并进行语义分析，解析时已输出错误及警告
+    ///
+    /// # 参数
+    /// * paths: 脚本文件路径
+    ///
+    /// # 返回值
+    /// * 所有脚本均通过检查时返回Ok，否则返回最后一个出错脚本的Error；
+    ///   --strict时有警告的脚本也视为出错
+    ///
+    fn check(&self, paths: &[&String]) -> Result<(), Error> {
+        let mut result = Ok(());
+        for path in paths {
+            match self.load(path) {
+                Ok(parser) => match parser.warnings().len() {
+                    0 => println!("{}: ok", path),
+                    n => {
+                        println!("{}: {} warning(s)", path, n);
+                        if self.strict {
+                            result = Err(strict_error(&parser));
+                        }
+                    }
+                },
+                Err(e) => {
+                    println!("{}: failed", path);
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    ///
+    /// 将DSL脚本编译为二进制格式并写入文件(compile子命令)
+    ///
+    /// # 参数
+    /// * path: DSL脚本文件路径
+    /// * output: 编译结果的保存路径
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，失败返回Error
+    ///
+    fn compile(&self, path: &str, output: &str) -> Result<(), Error> {
+        let parser = self.load(path)?;
+        if self.strict && !parser.warnings().is_empty() {
+            eprintln!(
+                "{} warning(s) treated as errors (--strict)",
+                parser.warnings().len()
+            );
+            return Err(strict_error(&parser));
+        }
+        let data = parser.to_compiled().map_err(io::Error::other)?;
+        std::fs::write(output, data)?;
+        println!("Compiled {} to {}", path, output);
+        Ok(())
+    }
+
     ///
     /// 运行DSL
-    /// 根据DSL脚本文件路径，解释DSL
+    /// 根据DSL脚本文件路径，解释DSL，也可以是--emit-json导出的JSON文件或compile编译后的脚本
     /// # 参数
     /// * path: DSL脚本文件路径
     ///
@@ -23,51 +230,328 @@ impl DSL {
     /// * 成功返回Ok，失败返回Error
     ///
     fn run(&mut self, path: &str) -> Result<(), Error> {
-        let source = std::fs::read_to_string(path)?;
-        let mut scanner = Scanner::new(source);
-        let commands = scanner.scan()?;
-        let mut parser = DSLParser::new();
-        parser.parse(commands)?;
-        self.interpreter.interpret(&parser.stages)
+        let parser = self.load(path)?;
+        // 警告在解析时已输出
+        if self.strict && !parser.warnings().is_empty() {
+            eprintln!(
+                "{} warning(s) treated as errors (--strict)",
+                parser.warnings().len()
+            );
+            return Err(strict_error(&parser));
+        }
+        if self.dot {
+            print!("{}", parser.to_dot());
+            return Ok(());
+        }
+        if self.mermaid {
+            print!("{}", parser.to_mermaid());
+            return Ok(());
+        }
+        if self.emit_json {
+            println!("{}", parser.to_json().map_err(io::Error::from)?);
+            return Ok(());
+        }
+        if self.print_dfa {
+            print!("{}", parser.to_table());
+            return Ok(());
+        }
+        self.interpreter = Interpreter::builder()
+            .on_error(parser.on_error.clone())
+            .input_timeout(parser.timeout.clone())
+            .prompt(parser.prompt.as_deref().unwrap_or_default())
+            .robot_prefix(parser.robot_prefix.as_deref().unwrap_or_default())
+            .max_steps(self.max_steps)
+            .theme(self.theme)
+            .typewriter(self.typewriter)
+            .normalizer(self.normalizer.clone())
+            .idle_timeout(self.idle_timeout)
+            .expiry_stage(self.expiry_stage.clone())
+            .build();
+        if let Some(path) = &self.answers {
+            self.interpreter.input = InputSource::answers(path)?;
+        }
+        if let Some(stage) = &self.start_stage {
+            self.interpreter.start_at(stage, &parser.stages)?;
+        }
+        if parser.meta.name.is_some() {
+            println!("Loaded {}", parser.meta);
+        }
+        if let Some(path) = &self.transcript {
+            self.interpreter.record_transcript();
+            *lock(&PENDING_TRANSCRIPT) = Some((path.clone(), self.interpreter.transcript_sink()));
+        }
+        match self.trace.as_deref() {
+            Some("") => self.interpreter.add_listener(Tracer::stderr()),
+            Some(path) => self.interpreter.add_listener(Tracer::file(path)?),
+            None => {}
+        }
+        // 变量的优先级由低到高: 预设变量、保存的变量、环境变量、命令行注入的变量
+        if let Some(path) = &self.preset {
+            self.interpreter.global_env.load_preset(path)?;
+        }
+        // 文件不存在说明是第一次运行
+        if let Some(path) = self
+            .env_file
+            .as_deref()
+            .filter(|path| Path::new(path).exists())
+        {
+            self.interpreter.global_env.load(path)?;
+        }
+        if let Some(prefix) = &self.env_prefix {
+            self.interpreter
+                .global_env
+                .import_os(prefix, std::env::vars());
+        }
+        for (name, value) in &self.vars {
+            self.interpreter.global_env.inject(name, value);
+        }
+        // 变量全部载入后再共享副本，中断时不会用缺少变量的副本覆盖文件
+        if let Some(path) = &self.env_file {
+            *lock(&PENDING_ENV) = Some((path.clone(), self.interpreter.env_sink()));
+        }
+        let result = if self.debug {
+            self.interpreter.load(parser.stages);
+            let mut debugger = Debugger::new(std::mem::take(&mut self.interpreter));
+            let result = debugger.run(io::stdin().lock(), io::stdout());
+            self.interpreter = debugger.interpreter;
+            result
+        } else {
+            self.interpreter.interpret(&parser.stages)
+        };
+        // 解释出错时也保存已有的对话记录
+        if let (Some(path), Some(transcript)) =
+            (&self.transcript, self.interpreter.take_transcript())
+        {
+            transcript.save(path)?;
+        }
+        // 解释出错时也保存已收集的变量，敏感变量不写入文件
+        if let Some(path) = &self.env_file {
+            lock(&PENDING_ENV).take();
+            self.interpreter.global_env.save(path)?;
+        }
+        result
     }
 }
 
-const USAGE: &str = "Usage: cargo run [dsl_file_path]";
+///
+/// --strict时由警告产生的语法错误，内容为第一个警告
+///
+fn strict_error(parser: &DSLParser) -> Error {
+    Error::Parse(Box::new(parser.warnings()[0].clone()))
+}
+
+const USAGE: &str = "Usage: cargo run [compile <dsl_file_path> <output_path> | check <dsl_file_path>...] [--lenient] [--ignore-case] [--debug] [--trace[=<path>]] [--lint] [--check] [--strict] [--dot] [--mermaid] [--emit-json] [--print-dfa] [--allow=<code>[,<code>]] [--diagnostics=json] [--transcript=<path>] [--max-steps=<n>] [--robot-style=<style>] [--prompt-style=<style>] [--no-color] [--typewriter[=<ms>]] [--normalize[=<steps>]] [--start-stage=<stage>] [--env-file=<path>] [--var <name>=<value>]... [--env-prefix=<prefix>] [--preset <path>] [--idle-timeout=<seconds>] [--expiry-stage=<stage>] [--stdin] [--answers <path>] [dsl_file_path | -]";
+/// 表示从标准输入读取脚本的路径
+const STDIN_PATH: &str = "-";
 const RUNTIME_ERROR: i32 = 70;
 const PARSE_ERROR: i32 = 65;
 const IO_ERROR: i32 = 74;
 const COMMAND_LINE_ERROR: i32 = 64;
 const SCAN_ERROR: i32 = 67;
+const INTERRUPTED: i32 = 130;
 const INPUT_HINT: &str = "Please input the script path you wanna use: ";
 
+///
+/// 正在记录的对话记录及其保存路径，Ctrl-C处理函数退出前据此保存已有的对话记录
+///
+static PENDING_TRANSCRIPT: Mutex<Option<(String, SharedTranscript)>> = Mutex::new(None);
+
+///
+/// 环境变量文件的路径及解释器共享的变量副本，Ctrl-C处理函数退出前据此保存已收集的变量
+///
+static PENDING_ENV: Mutex<Option<(String, env::SharedEnvironment)>> = Mutex::new(None);
+
+///
+/// 锁定共享数据，持有锁的线程退出时数据仍可使用
+///
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///
+/// 保存尚未保存的对话记录，正常结束时对话记录已取出，不会重复保存
+///
+fn flush_transcript() {
+    if let Some((path, sink)) = lock(&PENDING_TRANSCRIPT).as_ref() {
+        if let Some(transcript) = lock(sink).take() {
+            if let Err(e) = transcript.save(path) {
+                eprintln!("IoError: {}", e);
+            }
+        }
+    }
+}
+
+///
+/// 保存最近一次进入阶段时的变量副本，正常结束时已由解释器保存，不会重复保存
+///
+fn flush_env() {
+    if let Some((path, sink)) = lock(&PENDING_ENV).take() {
+        if let Some(env) = lock(&sink).take() {
+            if let Err(e) = env.save(&path) {
+                eprintln!("IoError: {}", e);
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let mut dsl = DSL::new();
-    // 解析命令行传递参数 args[1] 为DSL脚本文件路径
+    // 等待输入时终端处于原始模式，Ctrl-C由解释器处理；其余时候收到的中断信号在恢复终端、
+    // 保存对话记录及变量后退出
+    ctrlc::set_handler(|| {
+        tty::restore();
+        flush_transcript();
+        flush_env();
+        exit(INTERRUPTED);
+    })?;
+    tty::install_panic_hook();
+    // --var <name>=<value>等价于--var=<name>=<value>，--preset <path>、--answers <path>同理
+    let mut args: Vec<String> = Vec::new();
+    let mut raw_args = std::env::args();
+    while let Some(mut arg) = raw_args.next() {
+        if arg == "--var" || arg == "--preset" || arg == "--answers" {
+            if let Some(value) = raw_args.next() {
+                arg = format!("{}={}", arg, value);
+            }
+        }
+        args.push(arg);
+    }
+    let mut dsl = Dsl::new();
+    // 以--开头的参数为选项，其余参数中args[1]为DSL脚本文件路径
     // 通过cargo run [args] 的args参数以args[1]开始
-    match &args[..] {
-        [_, path] => match dsl.run(path) {
-            Ok(_) => (),
-            Err(Error::Parse) => exit(PARSE_ERROR),
-            Err(Error::Io(e)) => {
-                // 格式化输出错误信息
-                eprintln!("IoError: {}", e);
-                exit(IO_ERROR);
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|arg| arg.starts_with("--"));
+    // 设置了NO_COLOR环境变量或输出不是终端时不使用颜色
+    let mut color = std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal();
+    for option in options {
+        match option.as_str() {
+            "--lenient" => dsl.lenient = true,
+            "--ignore-case" => dsl.ignore_case = true,
+            "--debug" => dsl.debug = true,
+            "--trace" => dsl.trace = Some(String::new()),
+            "--check" | "--lint" => dsl.check_only = true,
+            "--strict" => dsl.strict = true,
+            "--dot" => dsl.dot = true,
+            "--mermaid" => dsl.mermaid = true,
+            "--emit-json" => dsl.emit_json = true,
+            "--print-dfa" => dsl.print_dfa = true,
+            "--stdin" => dsl.stdin = true,
+            "--no-color" => color = false,
+            "--typewriter" => dsl.typewriter = Some(DEFAULT_TYPEWRITER_DELAY),
+            "--diagnostics=json" => dsl.diagnostics_json = true,
+            "--normalize" => dsl.normalizer = Normalizer::chinese(),
+            option => {
+                if let Some(path) = option.strip_prefix("--transcript=") {
+                    dsl.transcript = Some(path.to_string());
+                } else if let Some(codes) = option.strip_prefix("--allow=") {
+                    let codes = codes.split(',').map(|code| code.trim().to_uppercase());
+                    dsl.allowed.extend(codes);
+                } else if let Some(Ok(normalizer)) =
+                    option.strip_prefix("--normalize=").map(Normalizer::parse)
+                {
+                    dsl.normalizer = normalizer;
+                } else if let Some((name, value)) = option
+                    .strip_prefix("--var=")
+                    .and_then(env::parse_assignment)
+                {
+                    dsl.vars.push((name.to_string(), value.to_string()));
+                } else if let Some(prefix) = option
+                    .strip_prefix("--env-prefix=")
+                    .filter(|prefix| !prefix.is_empty())
+                {
+                    dsl.env_prefix = Some(prefix.to_string());
+                } else if let Some(Ok(seconds)) =
+                    option.strip_prefix("--idle-timeout=").map(str::parse)
+                {
+                    dsl.idle_timeout = Some(Duration::from_secs(seconds));
+                } else if let Some(stage) = option.strip_prefix("--expiry-stage=") {
+                    dsl.expiry_stage = Some(stage.to_string());
+                } else if let Some(path) = option.strip_prefix("--trace=") {
+                    dsl.trace = Some(path.to_string());
+                } else if let Some(path) = option.strip_prefix("--answers=") {
+                    dsl.answers = Some(path.to_string());
+                } else if let Some(path) = option.strip_prefix("--preset=") {
+                    dsl.preset = Some(path.to_string());
+                } else if let Some(path) = option.strip_prefix("--env-file=") {
+                    dsl.env_file = Some(path.to_string());
+                } else if let Some(stage) = option.strip_prefix("--start-stage=") {
+                    dsl.start_stage = Some(stage.to_string());
+                } else if let Some(Ok(max_steps)) =
+                    option.strip_prefix("--max-steps=").map(str::parse)
+                {
+                    dsl.max_steps = max_steps;
+                } else if let Some(Ok(millis)) =
+                    option.strip_prefix("--typewriter=").map(str::parse)
+                {
+                    dsl.typewriter = Some(Duration::from_millis(millis));
+                } else if let Some(Ok(style)) = option
+                    .strip_prefix("--robot-style=")
+                    .map(theme::parse_style)
+                {
+                    dsl.theme.robot = style;
+                } else if let Some(Ok(style)) = option
+                    .strip_prefix("--prompt-style=")
+                    .map(theme::parse_style)
+                {
+                    dsl.theme.prompt = style;
+                } else {
+                    eprintln!("{}", USAGE);
+                    exit(COMMAND_LINE_ERROR)
+                }
             }
-            Err(Error::Scan) => exit(SCAN_ERROR),
-            Err(Error::Runtime) => exit(RUNTIME_ERROR),
-        },
+        }
+    }
+    if !color {
+        dsl.theme = Theme::plain();
+    }
+    let result = match &args[..] {
+        [_, command, path, output] if command.as_str() == "compile" => dsl.compile(path, output),
+        [_, command, paths @ ..] if command.as_str() == "check" && !paths.is_empty() => {
+            dsl.check(paths)
+        }
+        [_, paths @ ..] if dsl.check_only && !paths.is_empty() => dsl.check(paths),
+        [_, path] => dsl.run(path),
+        [_] if dsl.stdin && dsl.check_only => dsl.check(&[&STDIN_PATH.to_string()]),
+        [_] if dsl.stdin => dsl.run(STDIN_PATH),
+        // --check不能进入对话，没有脚本路径时不提示输入
+        [_] if dsl.check_only => {
+            eprintln!("{}", USAGE);
+            exit(COMMAND_LINE_ERROR)
+        }
         [_] => {
             println!("{}", INPUT_HINT);
             let mut input = String::new();
             io::stdout().flush()?;
             io::stdin().read_line(&mut input)?;
-            dsl.run(input.trim())?;
+            match dsl.run(input.trim()) {
+                Err(Error::Interrupted) => exit(INTERRUPTED),
+                result => result?,
+            }
+            Ok(())
         }
         _ => {
             eprintln!("{}", USAGE);
             exit(COMMAND_LINE_ERROR)
         }
+    };
+    match result {
+        Ok(_) | Err(Error::InputEnd) => (),
+        Err(Error::Parse(_)) => exit(PARSE_ERROR),
+        Err(Error::Io(e)) => {
+            // 格式化输出错误信息
+            eprintln!("IoError: {}", e);
+            exit(IO_ERROR);
+        }
+        Err(Error::Scan(_)) => exit(SCAN_ERROR),
+        Err(Error::Terminal(e)) => {
+            eprintln!("TerminalError: {}", e);
+            exit(IO_ERROR);
+        }
+        Err(Error::Runtime { .. } | Error::NoMatch { .. } | Error::Timeout | Error::Pattern(_)) => {
+            exit(RUNTIME_ERROR)
+        }
+        Err(Error::Interrupted) => exit(INTERRUPTED),
     }
     //  dsl.run("dsl.txt")?;
     Ok(())
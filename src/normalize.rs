@@ -0,0 +1,163 @@
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+///
+/// 匹配前对用户输入的一个预处理步骤
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Unicode NFC规范化，将字母与组合附加符号合并为一个字符
+    Nfc,
+    /// 全角字母、数字及符号转换为半角，全角空格转换为空格，如"１２３"转换为"123"
+    HalfWidth,
+    /// 中文标点转换为对应的ASCII标点，如"。"转换为"."
+    Punctuation,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    ///
+    /// 由步骤名解析，步骤名为nfc、width或punctuation
+    ///
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "nfc" => Ok(Normalization::Nfc),
+            "width" => Ok(Normalization::HalfWidth),
+            "punctuation" => Ok(Normalization::Punctuation),
+            _ => Err(format!("Unknown normalization '{}'", name)),
+        }
+    }
+}
+
+///
+/// 中文标点及对应的ASCII标点，全角形式的标点(如"，")由HalfWidth处理
+///
+const PUNCTUATION: &[(char, &str)] = &[
+    ('。', "."),
+    ('、', ","),
+    ('“', "\""),
+    ('”', "\""),
+    ('‘', "'"),
+    ('’', "'"),
+    ('【', "["),
+    ('】', "]"),
+    ('《', "<"),
+    ('》', ">"),
+    ('〈', "<"),
+    ('〉', ">"),
+    ('—', "-"),
+    ('…', "..."),
+    ('·', "."),
+];
+
+impl Normalization {
+    ///
+    /// 对输入执行该步骤
+    ///
+    pub fn apply(&self, input: &str) -> String {
+        match self {
+            Normalization::Nfc => input.nfc().collect(),
+            Normalization::HalfWidth => input.chars().map(half_width).collect(),
+            Normalization::Punctuation => {
+                let mut output = String::with_capacity(input.len());
+                for c in input.chars() {
+                    match PUNCTUATION
+                        .iter()
+                        .find(|(punctuation, _)| *punctuation == c)
+                    {
+                        Some((_, ascii)) => output.push_str(ascii),
+                        None => output.push(c),
+                    }
+                }
+                output
+            }
+        }
+    }
+}
+
+///
+/// 将全角字符转换为半角，其余字符不变
+///
+fn half_width(c: char) -> char {
+    match c {
+        '\u{3000}' => ' ',
+        // 全角ASCII字符(！到～)与ASCII字符的码位相差0xFEE0
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+///
+/// 用户输入的预处理流水线，按顺序执行各步骤，处理后的输入用于匹配
+/// 默认不做任何处理
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalizer {
+    pub steps: Vec<Normalization>,
+}
+
+impl Normalizer {
+    ///
+    /// 由步骤生成流水线
+    ///
+    pub fn new(steps: Vec<Normalization>) -> Self {
+        Self { steps }
+    }
+
+    ///
+    /// 面向中文用户的流水线: NFC规范化、全角转半角、中文标点转ASCII标点
+    ///
+    pub fn chinese() -> Self {
+        Self::new(vec![
+            Normalization::Nfc,
+            Normalization::HalfWidth,
+            Normalization::Punctuation,
+        ])
+    }
+
+    ///
+    /// 由逗号分隔的步骤名解析流水线，如"nfc,width"
+    ///
+    /// # 返回值
+    /// * 成功返回流水线，有未知的步骤名时返回错误信息
+    ///
+    pub fn parse(names: &str) -> Result<Self, String> {
+        let steps = names
+            .split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(steps))
+    }
+
+    ///
+    /// 对输入依次执行各步骤
+    ///
+    pub fn apply(&self, input: &str) -> String {
+        self.steps
+            .iter()
+            .fold(input.to_string(), |input, step| step.apply(&input))
+    }
+}
+
+#[cfg(test)]
+mod normalize_tests {
+    use super::*;
+
+    #[test]
+    fn test_normalizer() {
+        let normalizer = Normalizer::chinese();
+        assert_eq!(normalizer.apply("１２３　ＡＢｃ"), "123 ABc");
+        assert_eq!(
+            normalizer.apply("好的。“退款”，谢谢！"),
+            "好的.\"退款\",谢谢!"
+        );
+        assert_eq!(normalizer.apply("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(Normalizer::default().apply("１２３"), "１２３");
+        assert_eq!(
+            Normalizer::parse("nfc, width").unwrap(),
+            Normalizer::new(vec![Normalization::Nfc, Normalization::HalfWidth])
+        );
+        assert!(Normalizer::parse("nfkc").is_err());
+    }
+}
@@ -1,39 +1,421 @@
+use crate::ast::SyntaxTree;
 use crate::command::{Command, CommandType};
-use crate::error::{error, Error};
-use std::collections::HashMap;
-use std::fmt;
+use crate::condition::Condition;
+use crate::error::{Diagnostic, Error, Severity};
+use crate::interpreter::{LANG_VAR, SESSION_EXPIRED_STAGE};
+use crate::patterns::PatternLibrary;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::sync::LazyLock;
+use std::time::Duration;
 ///
 /// 表示转移条件及状态，包括匹配块或输入块
 /// - 如果在匹配块中找到匹配项，则转移到下一个阶段
 /// - 如果在输入块中成功接收完字符串输入到变量中，则转移到下一个阶段
+/// - 如果是无条件转移，则不等待输入直接转移到下一个阶段
 ///
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Transition {
     /// 匹配块
     Match(Vec<MatchBlock>),
     /// 输入块
     Input(InputBlock),
+    /// 无条件转移(GOTO)的目标阶段
+    Goto(String),
+    /// 意图块，选择关键词得分最高的分支
+    Intent(Vec<IntentBlock>),
+    /// 按变量值分支(SWITCH)，包含变量名和各CASE对应的匹配块
+    Switch(String, Vec<MatchBlock>),
+    /// 表单块，依次读取多个字段后转移
+    Form(FormBlock),
+    /// 是/否确认(CONFIRM)，包含回答是和回答否时转移的阶段
+    Confirm(String, String),
 }
 
 ///
 /// 匹配块的组成
 /// - pattern: 匹配表达式(可以是正则表达式)
 /// - next_stage: 匹配成功后转移的阶段
+/// - fuzzy: 是否为模糊匹配(MATCH~)，模糊匹配按编辑距离比较输入与pattern字面值
+/// - priority: 匹配优先级(PRIORITY)，数值越小越先尝试，未指定的分支按书写顺序排在最后
+/// - negated: 是否为否定匹配(MATCH NOT)，输入不匹配pattern时该分支成立
+/// - flags: 正则表达式标志(MATCH /pattern/flags)，指定时代替默认的忽略大小写设置
+/// - contains: 是否为子串匹配，子串匹配时输入中包含pattern即成立，否则要求整体匹配
+/// - regex: 解析时编译好的正则表达式，模糊匹配、EMPTY及$var形式的pattern为None
 ///
-#[derive(Debug, PartialEq)]
+/// pattern为$var形式时，匹配时取变量var的值作为字面值进行匹配
+///
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct MatchBlock {
     pub pattern: String,
     pub next_stage: String,
+    pub fuzzy: bool,
+    pub priority: Option<u32>,
+    pub negated: bool,
+    pub flags: Option<String>,
+    pub contains: bool,
+    #[serde(skip)]
+    pub regex: Option<Regex>,
+}
+
+// regex由pattern、flags和contains决定，比较时不再重复比较
+impl PartialEq for MatchBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+            && self.next_stage == other.next_stage
+            && self.fuzzy == other.fuzzy
+            && self.priority == other.priority
+            && self.negated == other.negated
+            && self.flags == other.flags
+            && self.contains == other.contains
+    }
+}
+
+impl MatchBlock {
+    ///
+    /// 编译匹配块的正则表达式
+    ///
+    /// # 返回值
+    /// * 模糊匹配、EMPTY及$var形式的pattern在匹配时才能确定，返回None；
+    ///   否则返回Some(编译结果)
+    ///
+    pub fn compile(&self) -> Option<Result<Regex, regex::Error>> {
+        self.regex_source().map(|source| Regex::new(&source))
+    }
+
+    ///
+    /// 获取匹配块对应的正则表达式源码，标志以内联形式写在开头
+    ///
+    /// # 返回值
+    /// * 模糊匹配、EMPTY及$var形式的pattern返回None，否则返回Some(源码)
+    ///
+    fn regex_source(&self) -> Option<String> {
+        if self.fuzzy || self.pattern.trim() == "EMPTY" || self.pattern_var().is_some() {
+            return None;
+        }
+        Some(pattern_source(
+            self.pattern.trim().trim_matches('"'),
+            self.flags.as_deref(),
+            self.contains,
+        ))
+    }
+
+    ///
+    /// 判断是否为EMPTY分支
+    /// 单独使用时不读取输入直接转移，放在其他匹配块之后时在均不匹配时转移
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.pattern.trim() == "EMPTY"
+    }
+
+    ///
+    /// 判断是否为DEFAULT分支
+    ///
+    pub fn is_default(&self) -> bool {
+        self.pattern == DEFAULT_PATTERN && !self.negated
+    }
+
+    ///
+    /// 获取pattern引用的变量名
+    ///
+    /// # 返回值
+    /// * pattern为$var形式时返回Some(var)，否则返回None
+    ///
+    pub fn pattern_var(&self) -> Option<&str> {
+        self.pattern
+            .trim()
+            .strip_prefix('$')
+            .filter(|var| !var.is_empty() && var.chars().all(|c| c.is_alphanumeric() || c == '_'))
+    }
+
+    ///
+    /// 获取匹配块可直接作为回答的字面选项，用于输入时的Tab补全
+    /// 模糊匹配的pattern整体为一个选项，正则表达式按|拆分后取不含元字符的部分
+    ///
+    /// # 返回值
+    /// * 字面选项，EMPTY、$var形式及子串匹配的pattern没有选项
+    ///
+    pub fn literal_options(&self) -> Vec<&str> {
+        let pattern = self.pattern.trim().trim_matches('"');
+        if pattern == "EMPTY" || self.pattern_var().is_some() || self.contains {
+            return Vec::new();
+        }
+        if self.fuzzy {
+            return vec![pattern];
+        }
+        pattern
+            .split('|')
+            .filter(|option| {
+                !option.is_empty() && !option.chars().any(|c| r"\.+*?()[]{}^$#&-~".contains(c))
+            })
+            .collect()
+    }
+}
+
+///
+/// MATCH /pattern/flags 支持的正则表达式标志
+/// - i: 忽略大小写
+/// - m: 多行模式，^和$匹配每一行的首尾
+/// - s: .匹配换行符
+/// - x: 忽略空白并允许#注释
+///
+pub const REGEX_FLAGS: &str = "imsx";
+
+///
+/// 按权重随机转移的单个目标：<百分比>% <stage>
+///
+static WEIGHTED_TARGET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+)%\s*(\S+)$").unwrap());
+
+///
+/// 将匹配表达式编译为正则表达式
+/// 整体匹配时在前面加上^,在后面加上$; 子串匹配时不加锚点
+/// 指定了标志时完全按标志设置，否则默认忽略大小写
+///
+/// # 参数
+/// * pattern: 去除双引号后的匹配表达式
+/// * flags: 正则表达式标志(可选)
+/// * contains: 是否为子串匹配
+///
+/// # 返回值
+/// * 成功返回正则表达式，表达式不合法时返回regex::Error
+///
+pub fn compile_pattern(
+    pattern: &str,
+    flags: Option<&str>,
+    contains: bool,
+) -> Result<Regex, regex::Error> {
+    Regex::new(&pattern_source(pattern, flags, contains))
+}
+
+///
+/// 生成匹配表达式对应的正则表达式源码，标志以(?flags)的形式写在开头，
+/// 以便同一阶段中标志不同的表达式可以组成一个RegexSet
+///
+fn pattern_source(pattern: &str, flags: Option<&str>, contains: bool) -> String {
+    let flags = match flags.unwrap_or("i") {
+        "" => String::new(),
+        flags => format!("(?{})", flags),
+    };
+    if contains {
+        format!("{}{}", flags, pattern)
+    } else {
+        format!(r"{}^{}$", flags, pattern)
+    }
+}
+
+///
+/// 阶段中已编译的匹配块组成的RegexSet，一次扫描输入即可得到各分支的匹配结果
+/// - set: 各已编译匹配块的正则表达式
+/// - indices: 匹配块在set中的序号，与匹配块一一对应，未编译的匹配块为None
+///
+#[derive(Debug, Clone)]
+pub struct MatchSet {
+    pub set: RegexSet,
+    pub indices: Vec<Option<usize>>,
+}
+
+impl MatchSet {
+    ///
+    /// 由阶段的匹配块生成RegexSet
+    ///
+    /// # 参数
+    /// * blocks: 已按优先级排序的匹配块
+    ///
+    /// # 返回值
+    /// * 至少有两个匹配块可以编译时返回Some(MatchSet)，否则返回None
+    ///
+    pub fn new(blocks: &[MatchBlock]) -> Option<Self> {
+        let mut sources = Vec::new();
+        let indices = blocks
+            .iter()
+            .map(|block| {
+                block.regex_source().map(|source| {
+                    sources.push(source);
+                    sources.len() - 1
+                })
+            })
+            .collect();
+        if sources.len() < 2 {
+            return None;
+        }
+        let set = RegexSet::new(&sources).ok()?;
+        Some(Self { set, indices })
+    }
+
+    ///
+    /// 一次扫描输入，得到各匹配块的正则表达式是否匹配(不考虑MATCH NOT)
+    ///
+    /// # 返回值
+    /// * 与匹配块一一对应，未编译的匹配块为None
+    ///
+    pub fn matches(&self, input: &str) -> Vec<Option<bool>> {
+        let matches = self.set.matches(input);
+        self.indices
+            .iter()
+            .map(|index| index.map(|index| matches.matched(index)))
+            .collect()
+    }
+}
+
+impl PartialEq for MatchSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.set.patterns() == other.set.patterns() && self.indices == other.indices
+    }
+}
+
+///
+/// 意图块的组成
+/// - keywords: 关键词及其权重，输入中每出现一个关键词即累加对应权重
+/// - next_stage: 该分支得分最高时转移的阶段
+/// - keywords为空的意图块为DEFAULT分支，所有分支得分均为0时选择它
+///
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntentBlock {
+    pub keywords: Vec<(String, f64)>,
+    pub next_stage: String,
+}
+
+impl IntentBlock {
+    ///
+    /// 计算输入在该分支上的得分，关键词匹配忽略大小写
+    ///
+    pub fn score(&self, input: &str) -> f64 {
+        let input = input.to_lowercase();
+        self.keywords
+            .iter()
+            .filter(|(keyword, _)| input.contains(&keyword.to_lowercase()))
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+}
+
+impl fmt::Display for IntentBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.keywords.is_empty() {
+            return write!(f, "DEFAULT");
+        }
+        let keywords: Vec<String> = self
+            .keywords
+            .iter()
+            .map(|(keyword, weight)| format!("{}={}", keyword, weight))
+            .collect();
+        write!(f, "{}", keywords.join(", "))
+    }
 }
 
 ///
 /// 输入块的组成
 /// - input_var: 输入变量的名称
 /// - next_stage: 无条件转移到的阶段
-#[derive(Debug, PartialEq)]
+/// - secret: 是否为敏感输入(INPUT <var> SECRET)
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct InputBlock {
     pub input_var: String,
     pub next_stage: String,
+    pub secret: bool,
+}
+
+///
+/// 表单字段的组成
+/// - name: 保存输入的变量名
+/// - validation: 校验输入的匹配表达式(可选)，输入不符合时重新读取该字段
+/// - regex: 解析时编译好的校验表达式，$var形式的校验表达式为None
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormField {
+    pub name: String,
+    pub validation: Option<String>,
+    #[serde(skip)]
+    pub regex: Option<Regex>,
+}
+
+// regex由validation决定，比较时不再重复比较
+impl PartialEq for FormField {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.validation == other.validation
+    }
+}
+
+///
+/// 表单块的组成
+/// - fields: 按顺序读取的字段
+/// - next_stage: 所有字段读取完成后转移的阶段
+///
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FormBlock {
+    pub fields: Vec<FormField>,
+    pub next_stage: String,
+}
+
+///
+/// 最大重试次数的组成
+/// - tries: 允许匹配失败的次数
+/// - next_stage: 匹配失败次数达到上限后转移到的阶段
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaxTries {
+    pub tries: u32,
+    pub next_stage: String,
+}
+
+///
+/// 等待输入超时的组成
+/// - duration: 等待用户输入的最长时间
+/// - next_stage: 超时未输入时转移到的阶段
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputTimeout {
+    pub duration: Duration,
+    pub next_stage: String,
+}
+
+///
+/// WHILE循环的组成
+/// - condition: 循环条件
+/// - next_stage: 条件成立时转移到的阶段(循环体的起点)
+/// - max_iterations: 最大循环次数，达到后不再循环，按原转移继续
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WhileLoop {
+    pub condition: Condition,
+    pub next_stage: String,
+    pub max_iterations: u32,
+}
+
+///
+/// WHILE未指定MAX时的最大循环次数
+///
+pub const DEFAULT_MAX_ITERATIONS: u32 = 100;
+
+///
+/// 阶段中的动作，在输出SPEAK内容之后依次执行
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    /// 删除指定变量(CLEAR <var>)
+    Clear(String),
+    /// 删除所有变量(CLEAR ALL)
+    ClearAll,
+    /// 将变量保存到文件(SAVE "<path>")
+    Save(String),
+    /// 从文件读取变量(LOAD "<path>")
+    Load(String),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Clear(var) => write!(f, "CLEAR {}", var),
+            Action::ClearAll => write!(f, "CLEAR ALL"),
+            Action::Save(path) => write!(f, "SAVE \"{}\"", path),
+            Action::Load(path) => write!(f, "LOAD \"{}\"", path),
+        }
+    }
 }
 
 ///
@@ -41,47 +423,385 @@ pub struct InputBlock {
 /// - stage: 当前阶段
 /// - speak: 当前输出
 /// - transition: 转移方式（匹配或输入）
+/// - max_tries: 匹配失败的最大重试次数(可选)
+/// - timeout: 等待输入的超时设置(可选)，未设置时使用脚本头的全局设置
+/// - speak_locales: 各语言的输出(SPEAK.<lang>), 键为语言代码
+/// - actions: 输出之后依次执行的动作
+/// - while_loop: 转移完成后判断的循环条件(可选)
+/// - weighted_next: 按权重随机转移的目标，键为NEXT或GOTO的原始参数，值为(阶段, 百分比)列表
+/// - tags: 阶段标签(TAG)，按书写顺序去重
+/// - entry: 是否为对话的入口阶段，解释器未指定初始阶段时从该阶段开始
+/// - match_set: 匹配块组成的RegexSet(可选)，用于一次扫描确定匹配的分支
+///
+/// 序列化时不包含编译好的正则表达式，HashMap按键排序输出
 ///
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct StageBlock {
     pub stage: String,
     pub speak: String,
     pub transition: Transition,
+    pub max_tries: Option<MaxTries>,
+    pub timeout: Option<InputTimeout>,
+    #[serde(serialize_with = "sorted")]
+    pub speak_locales: HashMap<String, String>,
+    pub actions: Vec<Action>,
+    pub while_loop: Option<WhileLoop>,
+    #[serde(serialize_with = "sorted")]
+    pub weighted_next: HashMap<String, Vec<(String, u32)>>,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub entry: bool,
+    #[serde(skip)]
+    pub match_set: Option<MatchSet>,
+}
+
+///
+/// 按键排序序列化HashMap，使导出的JSON内容稳定
+///
+fn sorted<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
 }
 
 impl StageBlock {
     ///
     /// 生成一个新的StageBlock
+    /// 转移为匹配块时由匹配块生成RegexSet，匹配块需已按优先级排序
     ///
     pub fn new(stage: &str, speak: &str, transition: Transition) -> Self {
+        let match_set = match &transition {
+            Transition::Match(blocks) => MatchSet::new(blocks),
+            _ => None,
+        };
         StageBlock {
             stage: stage.to_string(),
             speak: speak.to_string(),
             transition,
+            max_tries: None,
+            timeout: None,
+            speak_locales: HashMap::new(),
+            actions: Vec::new(),
+            while_loop: None,
+            weighted_next: HashMap::new(),
+            tags: Vec::new(),
+            entry: false,
+            match_set,
+        }
+    }
+
+    ///
+    /// 根据语言选择输出内容
+    /// 没有指定语言或该语言没有对应的SPEAK.<lang>时，返回默认的SPEAK内容
+    ///
+    pub fn speak_for(&self, lang: Option<&str>) -> &str {
+        lang.and_then(|lang| self.speak_locales.get(lang))
+            .unwrap_or(&self.speak)
+    }
+
+    ///
+    /// 获取本阶段匹配块的全部字面选项，按书写顺序排列并去除重复，用于输入时的Tab补全
+    ///
+    pub fn completions(&self) -> Vec<String> {
+        let Transition::Match(blocks) = &self.transition else {
+            return Vec::new();
+        };
+        let mut options: Vec<String> = Vec::new();
+        for option in blocks.iter().flat_map(MatchBlock::literal_options) {
+            if !options.iter().any(|existing| existing == option) {
+                options.push(option.to_string());
+            }
+        }
+        options
+    }
+
+    ///
+    /// 编译阶段中的正则表达式并生成RegexSet，用于由JSON载入、尚未编译的阶段
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，某个表达式不合法时返回Err(该表达式)
+    ///
+    pub fn compile(&mut self) -> Result<(), String> {
+        match &mut self.transition {
+            Transition::Match(blocks) | Transition::Switch(_, blocks) => {
+                for block in blocks.iter_mut() {
+                    block.regex = block
+                        .compile()
+                        .transpose()
+                        .map_err(|_| block.pattern.clone())?;
+                }
+            }
+            Transition::Form(form) => {
+                for field in form.fields.iter_mut() {
+                    if let Some(validation) = &field.validation {
+                        let block = MatchBlock {
+                            pattern: validation.clone(),
+                            ..Default::default()
+                        };
+                        field.regex = block.compile().transpose().map_err(|_| block.pattern)?;
+                    }
+                }
+            }
+            _ => (),
+        }
+        if let Transition::Match(blocks) = &self.transition {
+            self.match_set = MatchSet::new(blocks);
+        }
+        Ok(())
+    }
+
+    ///
+    /// 获取本阶段可能转移到的所有阶段，包括重试、超时及循环的目标阶段
+    /// 按权重随机转移的NEXT展开为各目标阶段，结果按出现顺序去除重复
+    ///
+    pub fn next_stages(&self) -> Vec<&str> {
+        let mut targets: Vec<&str> = match &self.transition {
+            Transition::Match(blocks) | Transition::Switch(_, blocks) => blocks
+                .iter()
+                .map(|block| block.next_stage.as_str())
+                .collect(),
+            Transition::Intent(intents) => intents
+                .iter()
+                .map(|intent| intent.next_stage.as_str())
+                .collect(),
+            Transition::Input(input) => vec![&input.next_stage],
+            Transition::Goto(next_stage) => vec![next_stage],
+            Transition::Form(form) => vec![&form.next_stage],
+            Transition::Confirm(yes_stage, no_stage) => vec![yes_stage, no_stage],
+        };
+        targets.extend(
+            self.max_tries
+                .iter()
+                .map(|max_tries| max_tries.next_stage.as_str()),
+        );
+        targets.extend(
+            self.timeout
+                .iter()
+                .map(|timeout| timeout.next_stage.as_str()),
+        );
+        targets.extend(
+            self.while_loop
+                .iter()
+                .map(|while_loop| while_loop.next_stage.as_str()),
+        );
+        let mut stages: Vec<&str> = Vec::new();
+        for target in targets {
+            let expanded = match self.weighted_next.get(target) {
+                Some(weighted) => weighted.iter().map(|(stage, _)| stage.as_str()).collect(),
+                None => vec![target],
+            };
+            for stage in expanded {
+                if !stages.contains(&stage) {
+                    stages.push(stage);
+                }
+            }
         }
+        stages
     }
 }
 
 impl fmt::Display for StageBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Stage: {}\n", self.stage)?;
-        write!(f, "  Speak: {}\n", self.speak)?;
+        writeln!(f, "Stage: {}", self.stage)?;
+        writeln!(f, "  Speak: {}", self.speak)?;
+        let mut locales: Vec<_> = self.speak_locales.iter().collect();
+        locales.sort();
+        for (lang, speak) in locales {
+            writeln!(f, "  Speak.{}: {}", lang, speak)?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f, "  Tags: {}", self.tags.join(", "))?;
+        }
+        for action in &self.actions {
+            writeln!(f, "  Action: {}", action)?;
+        }
         match &self.transition {
             Transition::Match(blocks) => {
                 for block in blocks {
-                    write!(f, "  Match: {} -> {}\n", block.pattern, block.next_stage)?;
+                    let kind = if block.fuzzy { "Match~" } else { "Match" };
+                    let not = if block.negated { "NOT " } else { "" };
+                    let contains = if block.contains { "CONTAINS " } else { "" };
+                    match &block.flags {
+                        Some(flags) => write!(
+                            f,
+                            "  {}: {}{}/{}/{} -> {}",
+                            kind, not, contains, block.pattern, flags, block.next_stage
+                        )?,
+                        None => write!(
+                            f,
+                            "  {}: {}{}{} -> {}",
+                            kind, not, contains, block.pattern, block.next_stage
+                        )?,
+                    }
+                    if let Some(priority) = block.priority {
+                        write!(f, " (priority {})", priority)?;
+                    }
+                    writeln!(f)?;
                 }
             }
             Transition::Input(block) => {
-                write!(f, "  Input: {} -> {}\n", block.input_var, block.next_stage)?;
+                let secret = if block.secret { " SECRET" } else { "" };
+                writeln!(
+                    f,
+                    "  Input: {}{} -> {}",
+                    block.input_var, secret, block.next_stage
+                )?;
+            }
+            Transition::Goto(next_stage) => {
+                writeln!(f, "  Goto: {}", next_stage)?;
+            }
+            Transition::Form(block) => {
+                for field in &block.fields {
+                    match &field.validation {
+                        Some(validation) => writeln!(f, "  Field: {} {}", field.name, validation)?,
+                        None => writeln!(f, "  Field: {}", field.name)?,
+                    }
+                }
+                writeln!(f, "  Form -> {}", block.next_stage)?;
+            }
+            Transition::Confirm(yes_stage, no_stage) => {
+                writeln!(f, "  Confirm: yes -> {}, no -> {}", yes_stage, no_stage)?;
+            }
+            Transition::Intent(blocks) => {
+                for block in blocks {
+                    writeln!(f, "  Intent: {} -> {}", block, block.next_stage)?;
+                }
+            }
+            Transition::Switch(var, blocks) => {
+                writeln!(f, "  Switch: {}", var)?;
+                for block in blocks {
+                    writeln!(f, "  Case: {} -> {}", block.pattern, block.next_stage)?;
+                }
             }
         }
+        if let Some(max_tries) = &self.max_tries {
+            writeln!(
+                f,
+                "  MaxTries: {} -> {}",
+                max_tries.tries, max_tries.next_stage
+            )?;
+        }
+        if let Some(timeout) = &self.timeout {
+            writeln!(
+                f,
+                "  Timeout: {}s -> {}",
+                timeout.duration.as_secs(),
+                timeout.next_stage
+            )?;
+        }
+        if let Some(while_loop) = &self.while_loop {
+            writeln!(
+                f,
+                "  While: {} -> {} (max {})",
+                while_loop.condition, while_loop.next_stage, while_loop.max_iterations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// DFA状态迁移表，键为阶段名
+///
+pub type Stages = HashMap<String, StageBlock>;
+
+///
+/// 当前解释器支持的DSL语言版本
+/// 脚本头中DSLVERSION高于此版本的脚本将被拒绝
+///
+/// - 1: 初始版本
+/// - 2: 增加GOTO等语句，MATCH EMPTY不再推荐使用
+///
+pub const DSL_VERSION: u32 = 2;
+
+///
+/// 未声明DSLVERSION的脚本按此版本解析
+///
+pub const LEGACY_DSL_VERSION: u32 = 1;
+
+///
+/// 未使用ENTRY指定入口时的默认入口阶段
+///
+pub const DEFAULT_ENTRY: &str = "initial";
+
+///
+/// DEFAULT分支使用的匹配表达式，匹配任意输入
+///
+pub const DEFAULT_PATTERN: &str = ".*";
+
+///
+/// 脚本的静态分析结果
+/// - unreachable: 从入口阶段无法到达的阶段，按阶段名排序
+/// - terminates: 是否存在到达EXIT的路径，不存在时对话永远无法正常结束
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Analysis {
+    pub unreachable: Vec<String>,
+    pub terminates: bool,
+}
+
+///
+/// 脚本头中的元信息
+/// - name: 机器人名称(NAME)
+/// - version: 脚本版本(VERSION)
+/// - author: 脚本作者(AUTHOR)
+/// - dsl_version: 脚本所需的DSL语言版本(DSLVERSION)
+///
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptMeta {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub dsl_version: Option<u32>,
+}
+
+impl fmt::Display for ScriptMeta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("<unnamed>"))?;
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq)]
+///
+/// 解析阶段过程中收集的可选子句
+///
+#[derive(Default)]
+struct StageOptions {
+    max_tries: Option<MaxTries>,
+    timeout: Option<InputTimeout>,
+    speak_locales: HashMap<String, String>,
+    actions: Vec<Action>,
+    while_loop: Option<WhileLoop>,
+    weighted_next: HashMap<String, Vec<(String, u32)>>,
+    tags: Vec<String>,
+}
+
+///
+/// 解析过程中正在构建的阶段，出错恢复时整体丢弃
+///
+#[derive(Default)]
+struct PendingStage {
+    stage: Option<String>,
+    speak: Option<String>,
+    transition: Option<Transition>,
+    pattern: Option<String>,
+    secret: bool,
+    match_: Option<MatchBlock>,
+    intent: Option<IntentBlock>,
+    options: StageOptions,
+    status: Status,
+}
+
+#[derive(Debug, Default, PartialEq)]
 enum Status {
+    #[default]
     Init,
     Stage,
     Speak,
@@ -90,14 +810,84 @@ enum Status {
     Input,
     InputNext,
     Default,
+    Goto,
+    Intent,
+    IntentNext,
+    IntentDefault,
+    Switch,
+    SwitchCase,
+    Form,
+    FormField,
+    FormNext,
+    Confirm,
+    /// 出错后丢弃了阶段的其余命令，只能开始新的阶段，不能再出现脚本头命令
+    Recovered,
+}
+
+impl Status {
+    ///
+    /// 当前阶段是否已完整(或尚未开始任何阶段)，完整时才能开始新的阶段或结束脚本
+    ///
+    fn is_complete(&self) -> bool {
+        matches!(
+            self,
+            Status::Init
+                | Status::InputNext
+                | Status::MatchNext
+                | Status::IntentNext
+                | Status::SwitchCase
+                | Status::FormNext
+                | Status::Confirm
+                | Status::Goto
+                | Status::Recovered
+        )
+    }
 }
 
 ///
 /// DSLParser的结构体定义
 /// - stages中的StageBlock实现了PartialEq trait,以实现HashMap的比较
+/// - 序列化时只包含解析结果(阶段表及脚本头设置)，不包含命名匹配表达式库及诊断信息
 ///
+#[derive(Serialize, Deserialize)]
 pub struct DSLParser {
+    #[serde(serialize_with = "sorted")]
     pub stages: HashMap<String, StageBlock>,
+    /// 脚本头元信息, 没有脚本头时各字段为None
+    pub meta: ScriptMeta,
+    /// ENTRY指定的入口阶段
+    pub entry: Option<String>,
+    /// ON_ERROR指定的错误处理阶段
+    pub on_error: Option<String>,
+    /// MATCH_MODE指定的默认匹配方式是否为子串匹配，未指定时为整体匹配
+    pub match_contains: Option<bool>,
+    /// 命名匹配表达式库，包含脚本头PATTERN定义的表达式
+    #[serde(skip)]
+    pub patterns: PatternLibrary,
+    /// 脚本头TIMEOUT指定的全局等待输入超时设置
+    pub timeout: Option<InputTimeout>,
+    /// 脚本头PROMPT指定的输入提示符
+    pub prompt: Option<String>,
+    /// 脚本头ROBOT_PREFIX指定的机器人输出前缀
+    pub robot_prefix: Option<String>,
+    /// 解析得到的抽象语法树
+    #[serde(skip)]
+    syntax_tree: SyntaxTree,
+    /// 通过allow忽略的警告代码
+    #[serde(skip)]
+    allowed: HashSet<String>,
+    /// 脚本源码的各行，用于在诊断信息中显示出错的行
+    #[serde(skip)]
+    source: Vec<String>,
+    /// 各阶段定义所在的行
+    #[serde(skip)]
+    stage_lines: HashMap<String, i32>,
+    /// 解析及分析过程中产生的警告
+    #[serde(skip)]
+    warnings: Vec<Diagnostic>,
+    /// 解析过程中产生的错误
+    #[serde(skip)]
+    errors: RefCell<Vec<Diagnostic>>,
 }
 
 impl DSLParser {
@@ -107,286 +897,3091 @@ impl DSLParser {
     pub fn new() -> Self {
         DSLParser {
             stages: HashMap::new(),
+            meta: ScriptMeta::default(),
+            entry: None,
+            on_error: None,
+            match_contains: None,
+            patterns: PatternLibrary::default(),
+            timeout: None,
+            prompt: None,
+            robot_prefix: None,
+            syntax_tree: SyntaxTree::default(),
+            allowed: HashSet::new(),
+            source: Vec::new(),
+            stage_lines: HashMap::new(),
+            warnings: Vec::new(),
+            errors: RefCell::new(Vec::new()),
         }
     }
 
-    fn error(&self, line: i32, what_: &str, message: &str) -> Error {
-        error(line, what_, message);
-        Error::Parse
+    ///
+    /// 获取解析及分析过程中产生的警告，包括已不推荐的旧语法、无法到达的阶段、
+    /// 永远不会尝试的匹配块、空的输出及未使用的变量
+    ///
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
     }
+
     ///
-    /// 将命令向量解析为DFA状态迁移表，存储在DSLParser的哈希表中
-    /// ## 参数列表
-    /// * commands: 命令向量
-    /// ## 返回值
-    /// * 成功返回Ok，失败返回错误
+    /// 忽略指定代码的警告(如"W005")，被忽略的警告既不输出也不计入warnings
+    /// 需在parse之前调用
     ///
-    pub fn parse(&mut self, commands: Vec<Command>) -> Result<(), Error> {
-        let mut current_stage: Option<String> = None;
-        let mut current_speak: Option<String> = None;
-        let mut current_transition: Option<Transition> = None;
-        let mut current_pattern: Option<String> = None;
-        let mut status = Status::Init;
-
-        for command in &commands {
-            match &command.ctype {
-                CommandType::STAGE(stage) => {
-                    if status == Status::Init
-                        || status == Status::InputNext
-                        || status == Status::MatchNext
-                    {
-                        status = Status::Stage;
-                    } else {
-                        // Early return explicitly
-                        return Err(self.error(
-                            command.line,
-                            &format!("STAGE {}", stage),
-                            "Unexpected Context",
-                        ));
-                    }
-                    // 如果当前阶段不为空，则保存当前阶段
-                    if let Some(stage) = current_stage {
-                        if let Some(speak) = current_speak {
-                            self.stages.insert(
-                                stage.clone(),
-                                StageBlock::new(&stage, &speak, current_transition.unwrap()),
-                            );
-                        }
-                    }
-                    // 保存新的阶段
-                    current_stage = Some(stage.clone());
-                    current_speak = None;
-                    current_transition = None;
-                }
-                CommandType::SPEAK(speak) => {
-                    if status == Status::Stage {
-                        status = Status::Speak;
-                    } else {
-                        return Err(self.error(
-                            command.line,
-                            &format!("SPEAK {}", speak),
-                            "Unexpected Context",
-                        ));
-                    }
-                    // 保存当前输出
-                    current_speak = Some(speak.clone());
-                }
-                CommandType::MATCH(pattern) => {
-                    if status == Status::Speak || status == Status::MatchNext {
-                        status = Status::Match;
-                    } else {
-                        return Err(self.error(
-                            command.line,
-                            &format!("MATCH {}", pattern),
-                            "Unexpected Context",
-                        ));
-                    }
-                    // 保存当前匹配表达式
-                    current_pattern = Some(pattern.clone());
-                }
-                CommandType::DEFAULT => {
-                    if status == Status::Speak || status == Status::MatchNext {
-                        status = Status::Default;
-                    } else {
-                        return Err(self.error(command.line, "DEFAULT", "Unexpected Context"));
-                    }
-                    // 保存当前匹配表达式
-                    current_pattern = Some(".*".to_string());
-                }
-                CommandType::INPUT(input_var) => {
-                    if status == Status::Speak {
-                        status = Status::Input;
-                    } else {
-                        return Err(self.error(
-                            command.line,
-                            &format!("INPUT {}", input_var),
-                            "Unexpected Context",
-                        ));
-                    }
-                    // 保存当前输入变量
-                    current_pattern = Some(input_var.clone());
-                }
-                CommandType::NEXT(next_stage) => match status {
-                    Status::Match | Status::Default => {
-                        status = Status::MatchNext;
-                        if let Some(pattern) = &current_pattern {
-                            if let Some(transition) = &mut current_transition {
-                                match transition {
-                                    Transition::Match(blocks) => {
-                                        blocks.push(MatchBlock {
-                                            pattern: pattern.clone(),
-                                            next_stage: next_stage.clone(),
-                                        });
-                                    }
-                                    _ => {}
-                                }
-                            } else {
-                                current_transition = Some(Transition::Match(vec![MatchBlock {
-                                    pattern: pattern.clone(),
-                                    next_stage: next_stage.clone(),
-                                }]));
-                            }
-                        }
-                    }
-                    Status::Input => {
-                        status = Status::InputNext;
-                        if let Some(pattern) = &current_pattern {
-                            current_transition = Some(Transition::Input(InputBlock {
-                                input_var: pattern.clone(),
-                                next_stage: next_stage.clone(),
-                            }));
-                        }
+    pub fn allow(&mut self, code: &str) {
+        self.allowed.insert(code.to_string());
+    }
+
+    ///
+    /// 设置脚本源码，设置后诊断信息中会显示出错的行并标出出错的位置
+    ///
+    pub fn set_source(&mut self, source: &str) {
+        self.source = source.lines().map(str::to_string).collect();
+    }
+
+    ///
+    /// 获取最近一次解析得到的抽象语法树，保留了脚本中全部命令的顺序及所在行
+    ///
+    pub fn syntax_tree(&self) -> &SyntaxTree {
+        &self.syntax_tree
+    }
+
+    ///
+    /// 获取解析过程中产生的所有错误，解析出错后跳到下一个STAGE继续解析，故可能有多个错误
+    ///
+    pub fn errors(&self) -> Vec<Diagnostic> {
+        self.errors.borrow().clone()
+    }
+
+    ///
+    /// 将解析过程中产生的错误及警告导出为JSON数组，每项包含诊断代码，供持续集成等工具分类处理
+    ///
+    /// # 返回值
+    /// * 成功返回JSON字符串，错误在前，警告在后，失败返回serde_json::Error
+    ///
+    pub fn diagnostics_json(&self) -> serde_json::Result<String> {
+        let mut diagnostics = self.errors();
+        diagnostics.extend(self.warnings.iter().cloned());
+        serde_json::to_string(&diagnostics)
+    }
+
+    ///
+    /// 将解析结果导出为JSON，供编辑器、统计面板等外部工具使用
+    /// 输出包含阶段、转移、输出内容及脚本头元信息，网页流程图等工具无需读取源码即可展示脚本结构
+    ///
+    /// # 返回值
+    /// * 成功返回格式化的JSON字符串，失败返回serde_json::Error
+    ///
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    ///
+    /// 由to_json导出的JSON载入解析结果，跳过扫描及解析
+    /// 载入后重新编译各阶段的正则表达式，并检查入口阶段及各转移的目标阶段是否存在
+    ///
+    /// # 参数
+    /// * json: to_json导出的JSON字符串
+    ///
+    /// # 返回值
+    /// * 成功返回DSLParser，JSON格式错误、正则表达式不合法或目标阶段不存在时返回Error::Parse
+    ///
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let parser: DSLParser = serde_json::from_str(json).map_err(|e| {
+            let error = Diagnostic::new(Severity::Error, e.line() as i32, "JSON", &e.to_string())
+                .with_code("E301")
+                .emit();
+            Error::Parse(Box::new(error))
+        })?;
+        parser.link()
+    }
+
+    ///
+    /// 为反序列化得到的解析结果重新编译正则表达式，并检查入口阶段、错误处理阶段
+    /// 及各转移的目标阶段是否存在
+    ///
+    /// # 返回值
+    /// * 成功返回可以运行的DSLParser，否则返回Error::Parse
+    ///
+    pub(crate) fn link(mut self) -> Result<Self, Error> {
+        let mut invalid = Vec::new();
+        for stage in self.stages.values_mut() {
+            if let Err(pattern) = stage.compile() {
+                invalid.push((stage.stage.clone(), pattern));
+            }
+        }
+        let mut result = Ok(());
+        for (name, pattern) in invalid {
+            result = Err(self.error(
+                "E106",
+                0,
+                &format!("STAGE {}", name),
+                &format!("Invalid regex pattern {}", pattern),
+            ));
+        }
+        for (name, stage) in &self.stages {
+            if name != &stage.stage {
+                result =
+                    Err(self.error("E301", 0, &format!("STAGE {}", name), "Stage name mismatch"));
+            }
+            for target in stage.next_stages() {
+                if target != "EXIT" && !self.stages.contains_key(target) {
+                    result = Err(self.error(
+                        "E201",
+                        0,
+                        &format!("STAGE {}", name),
+                        &format!("Stage '{}' not found", target),
+                    ));
+                }
+            }
+        }
+        if !self.stages.contains_key(self.entry_stage()) {
+            result = Err(self.error(
+                "E202",
+                0,
+                &format!("ENTRY {}", self.entry_stage()),
+                "Entry stage not found",
+            ));
+        }
+        self.mark_entry();
+        if let Some(on_error) = &self.on_error {
+            if !self.stages.contains_key(on_error) {
+                result = Err(self.error(
+                    "E203",
+                    0,
+                    &format!("ON_ERROR {}", on_error),
+                    "Error handler stage not found",
+                ));
+            }
+        }
+        if result.is_ok() && !self.analyze().terminates {
+            result = Err(self.error(
+                "E204",
+                0,
+                &format!("STAGE {}", self.entry_stage()),
+                "No path from the entry stage reaches EXIT",
+            ));
+        }
+        result.map(|_| self)
+    }
+
+    ///
+    /// 获取脚本使用的DSL语言版本
+    /// 脚本头声明了DSLVERSION时返回该版本, 否则返回LEGACY_DSL_VERSION
+    ///
+    pub fn dsl_version(&self) -> u32 {
+        self.meta.dsl_version.unwrap_or(LEGACY_DSL_VERSION)
+    }
+
+    ///
+    /// 获取对话的入口阶段
+    /// 脚本使用ENTRY指定时返回该阶段, 否则返回默认的"initial"
+    ///
+    pub fn entry_stage(&self) -> &str {
+        self.entry.as_deref().unwrap_or(DEFAULT_ENTRY)
+    }
+
+    ///
+    /// 在状态迁移表中标记入口阶段，使解释器只凭状态迁移表即可从入口阶段开始
+    ///
+    fn mark_entry(&mut self) {
+        let entry = self.entry_stage().to_string();
+        for stage in self.stages.values_mut() {
+            stage.entry = stage.stage == entry;
+        }
+    }
+
+    ///
+    /// 分析已解析的脚本，找出从入口阶段无法到达的阶段，并判断对话能否到达EXIT
+    /// 错误处理阶段、脚本头TIMEOUT的目标阶段及会话过期阶段可从任意阶段转移到，视为可以到达
+    ///
+    /// # 返回值
+    /// * 分析结果
+    ///
+    pub fn analyze(&self) -> Analysis {
+        let mut reached: HashSet<&str> = HashSet::new();
+        let mut pending = vec![self.entry_stage()];
+        pending.extend(self.on_error.as_deref());
+        pending.extend(
+            self.timeout
+                .iter()
+                .map(|timeout| timeout.next_stage.as_str()),
+        );
+        pending.push(SESSION_EXPIRED_STAGE);
+        while let Some(stage) = pending.pop() {
+            if !reached.insert(stage) {
+                continue;
+            }
+            if let Some(block) = self.stages.get(stage) {
+                pending.extend(block.next_stages());
+            }
+        }
+        let mut unreachable: Vec<String> = self
+            .stages
+            .keys()
+            .filter(|stage| !reached.contains(stage.as_str()))
+            .cloned()
+            .collect();
+        unreachable.sort();
+        Analysis {
+            unreachable,
+            terminates: reached.contains("EXIT"),
+        }
+    }
+
+    ///
+    /// 获取带有指定标签的所有阶段
+    ///
+    /// # 参数
+    /// * tag: 标签
+    ///
+    /// # 返回值
+    /// * 按阶段名排序的阶段列表
+    ///
+    pub fn stages_with_tag(&self, tag: &str) -> Vec<&StageBlock> {
+        let mut stages: Vec<&StageBlock> = self
+            .stages
+            .values()
+            .filter(|stage| stage.tags.iter().any(|t| t == tag))
+            .collect();
+        stages.sort_by(|a, b| a.stage.cmp(&b.stage));
+        stages
+    }
+
+    ///
+    /// 解析脚本头命令(包括ENTRY和ON_ERROR), 脚本头只能出现在第一个STAGE之前, 且每项只能出现一次
+    ///
+    fn parse_header(&mut self, command: &Command, status: &Status) -> Result<(), Error> {
+        let what_ = command.to_string();
+        if *status != Status::Init {
+            return Err(self.command_error("E102", command, &what_, "Unexpected Context"));
+        }
+        let duplicated = match &command.ctype {
+            CommandType::NAME(name) => self.meta.name.replace(name.clone()).is_some(),
+            CommandType::VERSION(version) => self.meta.version.replace(version.clone()).is_some(),
+            CommandType::AUTHOR(author) => self.meta.author.replace(author.clone()).is_some(),
+            CommandType::DSLVERSION(version) => {
+                if *version > DSL_VERSION {
+                    return Err(self.argument_error(
+                        "E101",
+                        command,
+                        &what_,
+                        &format!(
+                            "Script requires DSL version {}, but only version {} is supported",
+                            version, DSL_VERSION
+                        ),
+                    ));
+                }
+                self.meta.dsl_version.replace(*version).is_some()
+            }
+            CommandType::ENTRY(stage) => self.entry.replace(stage.clone()).is_some(),
+            CommandType::ONERROR(stage) => self.on_error.replace(stage.clone()).is_some(),
+            CommandType::PROMPT(prompt) => self.prompt.replace(prompt.clone()).is_some(),
+            CommandType::ROBOTPREFIX(prefix) => self.robot_prefix.replace(prefix.clone()).is_some(),
+            CommandType::PATTERN(name, pattern) => {
+                // 定义时即检查表达式，避免错误到引用它的MATCH中才被发现
+                if compile_pattern(pattern.trim().trim_matches('"'), None, false).is_err() {
+                    return Err(self.argument_error(
+                        "E106",
+                        command,
+                        &what_,
+                        "Invalid regex pattern",
+                    ));
+                }
+                self.patterns.define(name, pattern)
+            }
+            CommandType::MATCHMODE(mode) => {
+                self.match_contains.replace(mode == "CONTAINS").is_some()
+            }
+            CommandType::TIMEOUT(seconds, next_stage) => self
+                .timeout
+                .replace(InputTimeout {
+                    duration: Duration::from_secs(*seconds),
+                    next_stage: next_stage.clone(),
+                })
+                .is_some(),
+            _ => false,
+        };
+        if duplicated {
+            return Err(self.command_error("E103", command, &what_, "Duplicate header"));
+        }
+        Ok(())
+    }
+
+    ///
+    /// 将解析完成的阶段保存到DFA状态迁移表中
+    ///
+    fn save_stage(
+        &mut self,
+        stage: String,
+        speak: String,
+        mut transition: Transition,
+        options: StageOptions,
+    ) {
+        // 按优先级排序匹配块，sort_by_key为稳定排序，故未指定优先级的分支保持书写顺序
+        if let Transition::Match(blocks) = &mut transition {
+            blocks.sort_by_key(|block| (block.priority.is_none(), block.priority));
+        }
+        let mut block = StageBlock::new(&stage, &speak, transition);
+        block.max_tries = options.max_tries;
+        block.timeout = options.timeout;
+        block.speak_locales = options.speak_locales;
+        block.actions = options.actions;
+        block.while_loop = options.while_loop;
+        block.weighted_next = options.weighted_next;
+        block.tags = options.tags;
+        self.stages.insert(stage, block);
+    }
+
+    ///
+    /// 解析按权重随机转移的目标，保存到当前阶段的可选子句中
+    /// 参数中不含%时为普通的转移目标，不做处理
+    ///
+    fn parse_weighted_next(
+        &self,
+        command: &Command,
+        next_stage: &str,
+        options: &mut StageOptions,
+    ) -> Result<(), Error> {
+        if !next_stage.contains('%') {
+            return Ok(());
+        }
+        let targets = parse_weighted_targets(next_stage).ok_or_else(|| {
+            self.argument_error(
+                "E107",
+                command,
+                &command.to_string(),
+                "Expected '<n>% <stage>, ...' with weights adding up to 100%",
+            )
+        })?;
+        options
+            .weighted_next
+            .insert(next_stage.to_string(), targets);
+        Ok(())
+    }
+
+    ///
+    /// 展开@name形式的命名匹配表达式，其余表达式保持不变
+    ///
+    fn expand_pattern(&self, command: &Command, pattern: &str) -> Result<String, Error> {
+        self.patterns
+            .expand(pattern)
+            .map(str::to_string)
+            .map_err(|name| {
+                self.argument_error(
+                    "E106",
+                    command,
+                    &command.to_string(),
+                    &format!("Unknown pattern '@{}'", name),
+                )
+            })
+    }
+
+    ///
+    /// 在解析时编译匹配块的正则表达式，表达式不合法时报告语法错误
+    ///
+    /// # 返回值
+    /// * 编译成功返回Some(正则表达式)，匹配时才能确定的表达式返回None，表达式不合法时返回Error
+    ///
+    fn compile_match(&self, command: &Command, block: &MatchBlock) -> Result<Option<Regex>, Error> {
+        block.compile().transpose().map_err(|_| {
+            self.argument_error(
+                "E106",
+                command,
+                &command.to_string(),
+                "Invalid regex pattern",
+            )
+        })
+    }
+
+    ///
+    /// 检查命令是否使用了脚本所声明版本中已不推荐的旧语法
+    /// 未声明DSLVERSION的旧脚本不产生警告
+    ///
+    /// # 参数
+    /// * command: 命令
+    /// * status: 解析该命令之前的状态
+    ///
+    fn check_deprecated(&mut self, command: &Command, status: &Status) {
+        let deprecated = match &command.ctype {
+            // 跟在其他匹配块之后的EMPTY为兜底分支，不能用GOTO代替
+            CommandType::MATCH(pattern)
+                if pattern.trim() == "EMPTY" && *status != Status::MatchNext =>
+            {
+                Some((2, "MATCH EMPTY is deprecated, use GOTO instead"))
+            }
+            _ => None,
+        };
+        if let Some((since, message)) = deprecated {
+            if self.dsl_version() >= since {
+                let warning = self.warning(
+                    "W001",
+                    command.line,
+                    &command.to_string(),
+                    &format!("{} (since DSL version {})", message, since),
+                );
+                self.warnings.extend(warning);
+            }
+        }
+    }
+
+    fn error(&self, code: &'static str, line: i32, what_: &str, message: &str) -> Error {
+        let error = Diagnostic::new(Severity::Error, line, what_, message)
+            .with_code(code)
+            .emit();
+        self.errors.borrow_mut().push(error.clone());
+        Error::Parse(Box::new(error))
+    }
+
+    ///
+    /// 输出警告，已通过allow忽略的警告不输出
+    ///
+    /// # 返回值
+    /// * 未被忽略时返回Some(警告)
+    ///
+    fn warning(
+        &self,
+        code: &'static str,
+        line: i32,
+        what_: &str,
+        message: &str,
+    ) -> Option<Diagnostic> {
+        if self.allowed.contains(code) {
+            return None;
+        }
+        Some(
+            Diagnostic::new(Severity::Warning, line, what_, message)
+                .with_code(code)
+                .emit(),
+        )
+    }
+
+    ///
+    /// 报告命令出现的位置有误等错误，诊断信息指向命令关键字
+    /// 位置有误(Unexpected Context)时附上该命令应出现的位置作为提示
+    ///
+    fn command_error(
+        &self,
+        code: &'static str,
+        command: &Command,
+        what_: &str,
+        message: &str,
+    ) -> Error {
+        let hint = (message == "Unexpected Context").then(|| context_hint(&command.ctype));
+        let columns = command.columns.keyword.clone();
+        self.report(code, command, columns, what_, message, hint)
+    }
+
+    ///
+    /// 报告命令参数有误的错误，诊断信息指向参数
+    ///
+    fn argument_error(
+        &self,
+        code: &'static str,
+        command: &Command,
+        what_: &str,
+        message: &str,
+    ) -> Error {
+        let columns = command.columns.argument.clone();
+        self.report(code, command, columns, what_, message, None)
+    }
+
+    fn report(
+        &self,
+        code: &'static str,
+        command: &Command,
+        columns: Range<usize>,
+        what_: &str,
+        message: &str,
+        hint: Option<&str>,
+    ) -> Error {
+        let source = usize::try_from(command.line - 1)
+            .ok()
+            .and_then(|index| self.source.get(index));
+        let error = Diagnostic::new(Severity::Error, command.line, what_, message)
+            .with_code(code)
+            .with_columns(columns)
+            .with_source(source.map(String::as_str))
+            .with_hint(hint)
+            .emit();
+        self.errors.borrow_mut().push(error.clone());
+        Error::Parse(Box::new(error))
+    }
+    ///
+    /// 将命令向量解析为DFA状态迁移表，存储在DSLParser的哈希表中
+    /// 先由命令生成抽象语法树，再将语法树转换为状态迁移表
+    /// ## 参数列表
+    /// * commands: 命令向量
+    /// ## 返回值
+    /// * 成功返回Ok，失败返回错误
+    ///
+    pub fn parse(&mut self, commands: Vec<Command>) -> Result<(), Error> {
+        self.parse_tree(SyntaxTree::new(commands))
+    }
+
+    ///
+    /// 将抽象语法树转换为DFA状态迁移表，存储在DSLParser的哈希表中
+    /// ## 参数列表
+    /// * tree: 抽象语法树
+    /// ## 返回值
+    /// * 成功返回Ok，失败返回错误
+    ///
+    pub fn parse_tree(&mut self, tree: SyntaxTree) -> Result<(), Error> {
+        let commands = tree.commands();
+        self.syntax_tree = tree;
+        let mut pending = PendingStage::default();
+
+        // 出错后跳过其余命令直到下一个STAGE，丢弃出错的阶段后继续解析，以便一次报告所有错误
+        let mut index = 0;
+        let mut recovering = false;
+        while let Some(command) = commands.get(index) {
+            let is_stage = matches!(command.ctype, CommandType::STAGE(_));
+            if recovering && !is_stage {
+                index += 1;
+                continue;
+            }
+            if recovering {
+                recovering = false;
+                // 已经开始了阶段，恢复后不能回到脚本头的状态
+                let status = match pending.status {
+                    Status::Init => Status::Init,
+                    _ => Status::Recovered,
+                };
+                pending = PendingStage {
+                    status,
+                    ..PendingStage::default()
+                };
+            }
+            let in_header = pending.status == Status::Init;
+            let interrupted = is_stage && !pending.status.is_complete();
+            match self.parse_command(command, &mut pending) {
+                Ok(()) => {}
+                // 脚本头中的错误不影响其余脚本头命令
+                Err(_) if in_header && !is_stage => {}
+                // 上一阶段不完整时丢弃上一阶段，从该STAGE重新开始
+                Err(_) if interrupted => {
+                    recovering = true;
+                    continue;
+                }
+                Err(_) => recovering = true,
+            }
+            index += 1;
+        }
+        if !recovering && !pending.status.is_complete() {
+            let line = commands.last().map_or(0, |command| command.line);
+            self.error("E105", line, "EOF", "Unexpected end of script");
+        }
+        if let Some(error) = self.errors.borrow().first() {
+            return Err(Error::Parse(Box::new(error.clone())));
+        }
+        // 最后一个阶段保存
+        if let Some(stage) = pending.stage {
+            if let (Some(speak), Some(transition)) = (pending.speak, pending.transition) {
+                self.save_stage(stage, speak, transition, pending.options);
+            }
+        }
+        // 检查入口阶段是否存在
+        if !self.stages.contains_key(self.entry_stage()) {
+            let line = commands
+                .iter()
+                .find(|command| matches!(command.ctype, CommandType::ENTRY(_)))
+                .or(commands.last())
+                .map_or(0, |command| command.line);
+            return Err(self.error(
+                "E202",
+                line,
+                &format!("ENTRY {}", self.entry_stage()),
+                "Entry stage not found",
+            ));
+        }
+        self.mark_entry();
+        // 检查错误处理阶段是否存在
+        if let Some(on_error) = &self.on_error {
+            if !self.stages.contains_key(on_error) {
+                let line = commands
+                    .iter()
+                    .find(|command| matches!(command.ctype, CommandType::ONERROR(_)))
+                    .map_or(0, |command| command.line);
+                return Err(self.error(
+                    "E203",
+                    line,
+                    &format!("ON_ERROR {}", on_error),
+                    "Error handler stage not found",
+                ));
+            }
+        }
+        // 检查转移的目标阶段是否存在
+        self.check_targets(&commands)?;
+        // 检查对话能否正常结束
+        if !self.analyze().terminates {
+            let line = self.stage_lines.get(self.entry_stage()).copied();
+            return Err(self.error(
+                "E204",
+                line.unwrap_or_default(),
+                &format!("STAGE {}", self.entry_stage()),
+                "No path from the entry stage reaches EXIT",
+            ));
+        }
+        self.check_unreachable();
+        self.check_shadowed();
+        self.check_empty_speak();
+        self.check_unused_vars(&commands);
+        Ok(())
+    }
+
+    ///
+    /// 解析一条命令，更新正在构建的阶段
+    ///
+    /// # 参数
+    /// * command: 命令
+    /// * pending: 正在构建的阶段
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，命令出现在错误的上下文中或参数有误时返回错误
+    ///
+    fn parse_command(
+        &mut self,
+        command: &Command,
+        pending: &mut PendingStage,
+    ) -> Result<(), Error> {
+        // 形如 70% stageA, 30% stageB 的NEXT或GOTO按权重随机转移
+        if let CommandType::NEXT(next_stage) | CommandType::GOTO(next_stage) = &command.ctype {
+            self.parse_weighted_next(command, next_stage, &mut pending.options)?;
+        }
+        self.check_deprecated(command, &pending.status);
+        match &command.ctype {
+            CommandType::STAGE(stage) => {
+                if pending.status.is_complete() {
+                    pending.status = Status::Stage;
+                } else {
+                    // Early return explicitly
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("STAGE {}", stage),
+                        "Unexpected Context",
+                    ));
+                }
+                if let Some(line) = self.stage_lines.insert(stage.clone(), command.line) {
+                    return Err(self.argument_error(
+                        "E104",
+                        command,
+                        &format!("STAGE {}", stage),
+                        &format!("Stage already defined at line {}", line),
+                    ));
+                }
+                // 如果当前阶段不为空，则保存当前阶段
+                if let (Some(stage), Some(speak), Some(transition)) = (
+                    pending.stage.take(),
+                    pending.speak.take(),
+                    pending.transition.take(),
+                ) {
+                    self.save_stage(
+                        stage,
+                        speak,
+                        transition,
+                        std::mem::take(&mut pending.options),
+                    );
+                }
+                // 保存新的阶段
+                pending.stage = Some(stage.clone());
+                pending.speak = None;
+                pending.transition = None;
+                pending.options = StageOptions::default();
+            }
+            CommandType::SPEAK(speak) => {
+                if pending.status == Status::Stage {
+                    pending.status = Status::Speak;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("SPEAK {}", speak),
+                        "Unexpected Context",
+                    ));
+                }
+                // 保存当前输出
+                pending.speak = Some(speak.clone());
+            }
+            CommandType::SPEAKLOCALE(lang, speak) => {
+                // 多语言输出只能紧跟在默认的SPEAK之后，且每种语言只能出现一次
+                if pending.status != Status::Speak
+                    || pending
+                        .options
+                        .speak_locales
+                        .insert(lang.clone(), speak.clone())
+                        .is_some()
+                {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("SPEAK.{} {}", lang, speak),
+                        "Unexpected Context",
+                    ));
+                }
+            }
+            CommandType::CLEAR(_) | CommandType::SAVE(_) | CommandType::LOAD(_) => {
+                // 动作只能出现在SPEAK之后，迁移条件之前
+                if pending.status != Status::Speak {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.options.actions.push(match &command.ctype {
+                    CommandType::CLEAR(var) if var == "ALL" => Action::ClearAll,
+                    CommandType::CLEAR(var) => Action::Clear(var.clone()),
+                    CommandType::SAVE(path) => Action::Save(path.clone()),
+                    CommandType::LOAD(path) => Action::Load(path.clone()),
+                    _ => unreachable!(),
+                });
+            }
+            CommandType::TAG(tags) => {
+                // 标签只能出现在SPEAK之后，迁移条件之前
+                if pending.status != Status::Speak {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                for tag in tags {
+                    if !pending.options.tags.contains(tag) {
+                        pending.options.tags.push(tag.clone());
+                    }
+                }
+            }
+            CommandType::MATCH(pattern) | CommandType::FUZZYMATCH(pattern) => {
+                if pending.status == Status::Speak || pending.status == Status::MatchNext {
+                    pending.status = Status::Match;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                let fuzzy = matches!(command.ctype, CommandType::FUZZYMATCH(_));
+                // 以NOT开头的匹配表达式为否定匹配
+                let (pattern, negated) = match pattern.strip_prefix("NOT") {
+                    Some(rest) if rest.starts_with(char::is_whitespace) => {
+                        (rest.trim_start(), true)
+                    }
+                    _ => (pattern.as_str(), false),
+                };
+                // CONTAINS或EXACT前缀指定匹配方式，否则使用脚本头MATCH_MODE的设置
+                let (pattern, contains) = match split_match_mode(pattern) {
+                    Some(_) if fuzzy => {
+                        return Err(self.argument_error(
+                            "E106",
+                            command,
+                            &command.to_string(),
+                            "Match mode is not supported by MATCH~",
+                        ));
                     }
-                    _ => {
-                        return Err(self.error(
-                            command.line,
-                            &format!("NEXT {}", next_stage),
-                            "Unexpected Context",
+                    Some((pattern, contains)) => (pattern, contains),
+                    None => (pattern, !fuzzy && self.match_contains.unwrap_or(false)),
+                };
+                // 以/pattern/flags形式书写的匹配表达式带有正则表达式标志
+                let (pattern, flags) = match split_regex_flags(pattern) {
+                    Some(_) if fuzzy => {
+                        return Err(self.argument_error(
+                            "E106",
+                            command,
+                            &command.to_string(),
+                            "Regex flags are not supported by MATCH~",
                         ));
                     }
+                    Some((pattern, flags)) => {
+                        if let Some(flag) = flags.chars().find(|c| !REGEX_FLAGS.contains(*c)) {
+                            return Err(self.argument_error(
+                                "E106",
+                                command,
+                                &command.to_string(),
+                                &format!("Unknown regex flag '{}'", flag),
+                            ));
+                        }
+                        (pattern, Some(flags.to_string()))
+                    }
+                    None => (pattern, None),
+                };
+                // 保存当前匹配表达式, 同义词列表展开为正则表达式
+                let pattern = match pattern.strip_prefix("ANY") {
+                    Some(list) if !fuzzy && list.trim_start().starts_with('[') => {
+                        expand_synonyms(list).ok_or_else(|| {
+                            self.argument_error(
+                                "E107",
+                                command,
+                                &format!("MATCH {}", pattern),
+                                "Expected a non-empty list of quoted strings",
+                            )
+                        })?
+                    }
+                    _ if !fuzzy => self.expand_pattern(command, pattern)?,
+                    _ => pattern.to_string(),
+                };
+                let mut block = MatchBlock {
+                    pattern,
+                    fuzzy,
+                    negated,
+                    flags,
+                    contains,
+                    ..Default::default()
+                };
+                block.regex = self.compile_match(command, &block)?;
+                pending.match_ = Some(block);
+            }
+            CommandType::DEFAULT => {
+                if pending.status == Status::Speak || pending.status == Status::MatchNext {
+                    pending.status = Status::Default;
+                    // 保存当前匹配表达式
+                    let mut block = MatchBlock {
+                        pattern: DEFAULT_PATTERN.to_string(),
+                        ..Default::default()
+                    };
+                    block.regex = self.compile_match(command, &block)?;
+                    pending.match_ = Some(block);
+                } else if pending.status == Status::IntentNext {
+                    pending.status = Status::IntentDefault;
+                    pending.intent = Some(IntentBlock::default());
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        "DEFAULT",
+                        "Unexpected Context",
+                    ));
+                }
+            }
+            CommandType::SWITCH(var) => {
+                if pending.status == Status::Speak {
+                    pending.status = Status::Switch;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("SWITCH {}", var),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.transition = Some(Transition::Switch(var.clone(), Vec::new()));
+            }
+            CommandType::CASE(value, next_stage) => {
+                let Some(Transition::Switch(_, blocks)) =
+                    pending.transition.as_mut().filter(|_| {
+                        pending.status == Status::Switch || pending.status == Status::SwitchCase
+                    })
+                else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                };
+                pending.status = Status::SwitchCase;
+                // CASE DEFAULT匹配任意值，其余CASE按字面值匹配
+                let pattern = if value == "DEFAULT" {
+                    DEFAULT_PATTERN.to_string()
+                } else {
+                    regex::escape(value.trim_matches('"'))
+                };
+                let mut block = MatchBlock {
+                    pattern,
+                    next_stage: next_stage.clone(),
+                    ..Default::default()
+                };
+                block.regex = self.compile_match(command, &block)?;
+                blocks.push(block);
+            }
+            CommandType::PRIORITY(priority) => {
+                // 优先级只能出现在MATCH或DEFAULT与NEXT之间
+                let Some(block) = pending.match_.as_mut().filter(|_| {
+                    pending.status == Status::Match || pending.status == Status::Default
+                }) else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("PRIORITY {}", priority),
+                        "Unexpected Context",
+                    ));
+                };
+                let duplicated = match &pending.transition {
+                    Some(Transition::Match(blocks)) => {
+                        blocks.iter().any(|block| block.priority == Some(*priority))
+                    }
+                    _ => false,
+                };
+                if duplicated || block.priority.is_some() {
+                    return Err(self.argument_error(
+                        "E107",
+                        command,
+                        &format!("PRIORITY {}", priority),
+                        "Duplicate priority",
+                    ));
+                }
+                block.priority = Some(*priority);
+            }
+            CommandType::INTENT(keywords) => {
+                if pending.status == Status::Speak || pending.status == Status::IntentNext {
+                    pending.status = Status::Intent;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("INTENT {}", keywords),
+                        "Unexpected Context",
+                    ));
+                }
+                // 保存当前意图的关键词
+                let keywords = parse_keywords(keywords).ok_or_else(|| {
+                    self.argument_error(
+                        "E107",
+                        command,
+                        &format!("INTENT {}", keywords),
+                        "Expected a non-empty object of keyword weights",
+                    )
+                })?;
+                pending.intent = Some(IntentBlock {
+                    keywords,
+                    ..Default::default()
+                });
+            }
+            CommandType::INPUT(input_var) | CommandType::SECRETINPUT(input_var) => {
+                if pending.status == Status::Speak {
+                    pending.status = Status::Input;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("INPUT {}", input_var),
+                        "Unexpected Context",
+                    ));
+                }
+                // 保存当前输入变量
+                pending.pattern = Some(input_var.clone());
+                pending.secret = matches!(command.ctype, CommandType::SECRETINPUT(_));
+            }
+            CommandType::CONFIRM(yes_stage, no_stage) => {
+                if pending.status == Status::Speak {
+                    pending.status = Status::Confirm;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.transition = Some(Transition::Confirm(yes_stage.clone(), no_stage.clone()));
+            }
+            CommandType::FORM => {
+                if pending.status == Status::Speak {
+                    pending.status = Status::Form;
+                } else {
+                    return Err(self.command_error("E102", command, "FORM", "Unexpected Context"));
+                }
+                pending.transition = Some(Transition::Form(FormBlock::default()));
+            }
+            CommandType::FIELD(name, validation) => {
+                if pending.status == Status::Form || pending.status == Status::FormField {
+                    pending.status = Status::FormField;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                if let Some(Transition::Form(form)) = &mut pending.transition {
+                    let validation = match validation {
+                        Some(validation) => Some(self.expand_pattern(command, validation)?),
+                        None => None,
+                    };
+                    let regex = match &validation {
+                        Some(validation) => self.compile_match(
+                            command,
+                            &MatchBlock {
+                                pattern: validation.clone(),
+                                ..Default::default()
+                            },
+                        )?,
+                        None => None,
+                    };
+                    form.fields.push(FormField {
+                        name: name.clone(),
+                        validation,
+                        regex,
+                    });
+                }
+            }
+            CommandType::GOTO(next_stage) => {
+                if pending.status == Status::Speak {
+                    pending.status = Status::Goto;
+                } else {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("GOTO {}", next_stage),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.transition = Some(Transition::Goto(next_stage.clone()));
+            }
+            CommandType::MAXTRIES(tries, next_stage) => {
+                // 最大重试次数只能跟在匹配块之后，且每个阶段只能出现一次
+                if !(pending.status == Status::MatchNext
+                    || pending.status == Status::IntentNext
+                    || pending.status == Status::Confirm)
+                    || pending.options.max_tries.is_some()
+                {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.options.max_tries = Some(MaxTries {
+                    tries: *tries,
+                    next_stage: next_stage.clone(),
+                });
+            }
+            CommandType::TIMEOUT(_, _) if pending.status == Status::Init => {
+                self.parse_header(command, &pending.status)?
+            }
+            CommandType::TIMEOUT(seconds, next_stage) => {
+                // 超时设置只能跟在读取输入的转移之后，且每个阶段只能出现一次
+                if !(pending.status == Status::MatchNext
+                    || pending.status == Status::IntentNext
+                    || pending.status == Status::InputNext
+                    || pending.status == Status::FormNext
+                    || pending.status == Status::Confirm)
+                    || pending.options.timeout.is_some()
+                {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                pending.options.timeout = Some(InputTimeout {
+                    duration: Duration::from_secs(*seconds),
+                    next_stage: next_stage.clone(),
+                });
+            }
+            CommandType::WHILE(condition, next_stage, max_iterations) => {
+                // 循环条件只能跟在完整的转移之后，且每个阶段只能出现一次
+                if !(pending.status == Status::MatchNext
+                    || pending.status == Status::IntentNext
+                    || pending.status == Status::InputNext
+                    || pending.status == Status::SwitchCase
+                    || pending.status == Status::FormNext
+                    || pending.status == Status::Confirm
+                    || pending.status == Status::Goto)
+                    || pending.options.while_loop.is_some()
+                {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &command.to_string(),
+                        "Unexpected Context",
+                    ));
+                }
+                let condition = Condition::parse(condition).ok_or_else(|| {
+                    self.argument_error("E107", command, &command.to_string(), "Invalid condition")
+                })?;
+                pending.options.while_loop = Some(WhileLoop {
+                    condition,
+                    next_stage: next_stage.clone(),
+                    max_iterations: max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS),
+                });
+            }
+            CommandType::NAME(_)
+            | CommandType::VERSION(_)
+            | CommandType::AUTHOR(_)
+            | CommandType::DSLVERSION(_)
+            | CommandType::ENTRY(_)
+            | CommandType::ONERROR(_)
+            | CommandType::PROMPT(_)
+            | CommandType::ROBOTPREFIX(_)
+            | CommandType::MATCHMODE(_)
+            | CommandType::PATTERN(_, _) => self.parse_header(command, &pending.status)?,
+            CommandType::NEXT(next_stage) => match pending.status {
+                Status::Match | Status::Default => {
+                    pending.status = Status::MatchNext;
+                    if let Some(mut block) = pending.match_.take() {
+                        block.next_stage = next_stage.clone();
+                        match &mut pending.transition {
+                            Some(Transition::Match(blocks)) => blocks.push(block),
+                            _ => pending.transition = Some(Transition::Match(vec![block])),
+                        }
+                    }
+                }
+                Status::Intent | Status::IntentDefault => {
+                    pending.status = Status::IntentNext;
+                    if let Some(mut block) = pending.intent.take() {
+                        block.next_stage = next_stage.clone();
+                        match &mut pending.transition {
+                            Some(Transition::Intent(blocks)) => blocks.push(block),
+                            _ => pending.transition = Some(Transition::Intent(vec![block])),
+                        }
+                    }
+                }
+                Status::FormField => {
+                    pending.status = Status::FormNext;
+                    if let Some(Transition::Form(form)) = &mut pending.transition {
+                        form.next_stage = next_stage.clone();
+                    }
+                }
+                Status::Input => {
+                    pending.status = Status::InputNext;
+                    if let Some(pattern) = &pending.pattern {
+                        pending.transition = Some(Transition::Input(InputBlock {
+                            input_var: pattern.clone(),
+                            next_stage: next_stage.clone(),
+                            secret: pending.secret,
+                        }));
+                    }
+                }
+                _ => {
+                    return Err(self.command_error(
+                        "E102",
+                        command,
+                        &format!("NEXT {}", next_stage),
+                        "Unexpected Context",
+                    ));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    ///
+    /// 报告从入口阶段无法到达的阶段
+    ///
+    fn check_unreachable(&mut self) {
+        for stage in self.analyze().unreachable {
+            let warning = self.warning(
+                "W002",
+                self.stage_lines.get(&stage).copied().unwrap_or_default(),
+                &format!("STAGE {}", stage),
+                &format!("Stage is unreachable from '{}'", self.entry_stage()),
+            );
+            self.warnings.extend(warning);
+        }
+    }
+
+    ///
+    /// 报告排在DEFAULT之后、永远不会尝试的匹配块
+    ///
+    fn check_shadowed(&mut self) {
+        let mut stages: Vec<&StageBlock> = self.stages.values().collect();
+        stages.sort_by(|a, b| a.stage.cmp(&b.stage));
+        let mut warnings = Vec::new();
+        for stage in stages {
+            let Transition::Match(blocks) = &stage.transition else {
+                continue;
+            };
+            if let Some(position) = blocks.iter().position(MatchBlock::is_default) {
+                if position + 1 < blocks.len() {
+                    warnings.extend(
+                        self.warning(
+                            "W003",
+                            self.stage_lines
+                                .get(&stage.stage)
+                                .copied()
+                                .unwrap_or_default(),
+                            &format!("STAGE {}", stage.stage),
+                            "Match patterns after DEFAULT are never tried",
+                        ),
+                    );
+                }
+            }
+        }
+        self.warnings.extend(warnings);
+    }
+
+    ///
+    /// 报告输出内容为空的阶段，包括各语言的SPEAK.<lang>
+    ///
+    fn check_empty_speak(&mut self) {
+        let mut stages: Vec<&StageBlock> = self.stages.values().collect();
+        stages.sort_by(|a, b| a.stage.cmp(&b.stage));
+        let is_empty = |speak: &str| speak.trim().trim_matches('"').trim().is_empty();
+        let mut warnings = Vec::new();
+        for stage in stages {
+            let line = self
+                .stage_lines
+                .get(&stage.stage)
+                .copied()
+                .unwrap_or_default();
+            if is_empty(&stage.speak) {
+                warnings.extend(self.warning(
+                    "W004",
+                    line,
+                    &format!("STAGE {}", stage.stage),
+                    "SPEAK is empty",
+                ));
+            }
+            let mut langs: Vec<&String> = stage
+                .speak_locales
+                .iter()
+                .filter(|(_, speak)| is_empty(speak))
+                .map(|(lang, _)| lang)
+                .collect();
+            langs.sort();
+            for lang in langs {
+                warnings.extend(self.warning(
+                    "W004",
+                    line,
+                    &format!("STAGE {}", stage.stage),
+                    &format!("SPEAK.{} is empty", lang),
+                ));
+            }
+        }
+        self.warnings.extend(warnings);
+    }
+
+    ///
+    /// 报告INPUT或FIELD读取后从未使用的变量
+    /// 使用SAVE的脚本会保存全部变量，不做检查
+    ///
+    /// # 参数
+    /// * commands: 命令序列
+    ///
+    fn check_unused_vars(&mut self, commands: &[Command]) {
+        let stages = self.stages.values();
+        if stages.clone().any(|stage| {
+            stage
+                .actions
+                .iter()
+                .any(|action| matches!(action, Action::Save(_)))
+        }) {
+            return;
+        }
+        let mut used: HashSet<&str> = HashSet::from([LANG_VAR]);
+        for stage in stages {
+            // SPEAK中+连接的非字符串部分为变量
+            for speak in std::iter::once(&stage.speak).chain(stage.speak_locales.values()) {
+                used.extend(
+                    speak
+                        .split('+')
+                        .map(str::trim)
+                        .filter(|part| !part.starts_with('"')),
+                );
+            }
+            match &stage.transition {
+                Transition::Match(blocks) => {
+                    used.extend(blocks.iter().filter_map(MatchBlock::pattern_var))
+                }
+                Transition::Switch(var, cases) => {
+                    used.insert(var);
+                    used.extend(cases.iter().filter_map(MatchBlock::pattern_var));
+                }
+                Transition::Form(form) => used.extend(
+                    form.fields
+                        .iter()
+                        .filter_map(|field| field.validation.as_deref()?.trim().strip_prefix('$')),
+                ),
+                _ => {}
+            }
+            if let Some(while_loop) = &stage.while_loop {
+                used.insert(&while_loop.condition.var);
+            }
+        }
+        let mut warnings = Vec::new();
+        let mut reported: HashSet<&str> = HashSet::new();
+        for command in commands {
+            let var = match &command.ctype {
+                CommandType::INPUT(var)
+                | CommandType::SECRETINPUT(var)
+                | CommandType::FIELD(var, _) => var.as_str(),
+                _ => continue,
+            };
+            if !used.contains(var) && reported.insert(var) {
+                warnings.extend(self.warning(
+                    "W005",
+                    command.line,
+                    &command.to_string(),
+                    &format!("Variable '{}' is never used", var),
+                ));
+            }
+        }
+        self.warnings.extend(warnings);
+    }
+
+    ///
+    /// 检查各命令转移的目标阶段是否均已定义，报告所有未定义的目标阶段
+    ///
+    /// # 参数
+    /// * commands: 命令序列
+    ///
+    /// # 返回值
+    /// * 均已定义返回Ok，否则返回语法错误
+    ///
+    fn check_targets(&self, commands: &[Command]) -> Result<(), Error> {
+        let mut result = Ok(());
+        for command in commands {
+            let targets = match &command.ctype {
+                // 按权重随机转移时检查每个目标阶段
+                CommandType::NEXT(next_stage) | CommandType::GOTO(next_stage)
+                    if next_stage.contains('%') =>
+                {
+                    parse_weighted_targets(next_stage)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|(target, _)| target)
+                        .collect()
+                }
+                CommandType::NEXT(next_stage)
+                | CommandType::GOTO(next_stage)
+                | CommandType::CASE(_, next_stage)
+                | CommandType::MAXTRIES(_, next_stage)
+                | CommandType::TIMEOUT(_, next_stage)
+                | CommandType::WHILE(_, next_stage, _) => vec![next_stage.clone()],
+                CommandType::CONFIRM(yes_stage, no_stage) => {
+                    vec![yes_stage.clone(), no_stage.clone()]
+                }
+                _ => continue,
+            };
+            for target in targets {
+                if target != "EXIT" && !self.stages.contains_key(&target) {
+                    result = Err(self.argument_error(
+                        "E201",
+                        command,
+                        &command.to_string(),
+                        &format!("Stage '{}' not found", target),
+                    ));
+                }
+            }
+        }
+        result
+    }
+}
+
+///
+/// 获取命令出现的位置有误时的提示，说明该命令应出现的位置
+///
+fn context_hint(ctype: &CommandType) -> &'static str {
+    match ctype {
+        CommandType::STAGE(_) => "the previous stage must end with a transition",
+        CommandType::SPEAK(_) | CommandType::SPEAKLOCALE(_, _) => "SPEAK must follow STAGE",
+        CommandType::MATCH(_) | CommandType::FUZZYMATCH(_) => {
+            "MATCH must follow SPEAK or the NEXT of another MATCH"
+        }
+        CommandType::INTENT(_) => "INTENT must follow SPEAK or the NEXT of another INTENT",
+        CommandType::DEFAULT => "DEFAULT must follow the NEXT of a MATCH or INTENT",
+        CommandType::NEXT(_) => "NEXT must follow MATCH, DEFAULT, INTENT, INPUT or FIELD",
+        CommandType::INPUT(_)
+        | CommandType::SECRETINPUT(_)
+        | CommandType::SWITCH(_)
+        | CommandType::FORM
+        | CommandType::GOTO(_)
+        | CommandType::CONFIRM(_, _) => "a transition must follow SPEAK",
+        CommandType::CASE(_, _) => "CASE must follow SWITCH",
+        CommandType::FIELD(_, _) => "FIELD must follow FORM",
+        CommandType::PRIORITY(_) => "PRIORITY must follow MATCH or DEFAULT",
+        CommandType::CLEAR(_)
+        | CommandType::SAVE(_)
+        | CommandType::LOAD(_)
+        | CommandType::TAG(_) => "actions and TAG must follow SPEAK",
+        CommandType::MAXTRIES(_, _) | CommandType::TIMEOUT(_, _) | CommandType::WHILE(_, _, _) => {
+            "this clause must follow the transition of a stage"
+        }
+        CommandType::NAME(_)
+        | CommandType::VERSION(_)
+        | CommandType::AUTHOR(_)
+        | CommandType::DSLVERSION(_)
+        | CommandType::MATCHMODE(_)
+        | CommandType::PATTERN(_, _)
+        | CommandType::ENTRY(_)
+        | CommandType::ONERROR(_)
+        | CommandType::PROMPT(_)
+        | CommandType::ROBOTPREFIX(_) => "header commands must come before the first STAGE",
+    }
+}
+
+///
+/// 将同义词列表展开为匹配其中任意一项的正则表达式
+/// 例如 ["yes","ok"] 展开为 "(?:yes|ok)"，列表项中的正则元字符会被转义
+///
+/// # 参数
+/// * list: JSON格式的字符串列表
+///
+/// # 返回值
+/// * 成功返回带双引号的匹配表达式，列表格式错误或为空时返回None
+///
+fn expand_synonyms(list: &str) -> Option<String> {
+    let synonyms: Vec<String> = serde_json::from_str(list).ok()?;
+    if synonyms.is_empty() {
+        return None;
+    }
+    let alternatives: Vec<String> = synonyms.iter().map(|s| regex::escape(s)).collect();
+    Some(format!("\"(?:{})\"", alternatives.join("|")))
+}
+
+///
+/// 拆分以CONTAINS或EXACT开头的匹配表达式
+///
+/// # 参数
+/// * pattern: 匹配表达式
+///
+/// # 返回值
+/// * 带有前缀时返回Some((pattern, 是否为子串匹配))，否则返回None
+///
+fn split_match_mode(pattern: &str) -> Option<(&str, bool)> {
+    [("CONTAINS", true), ("EXACT", false)]
+        .into_iter()
+        .find_map(|(prefix, contains)| {
+            pattern
+                .strip_prefix(prefix)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .map(|rest| (rest.trim_start(), contains))
+        })
+}
+
+///
+/// 拆分/pattern/flags形式的匹配表达式
+///
+/// # 参数
+/// * pattern: 匹配表达式
+///
+/// # 返回值
+/// * 以/开头且包含结尾的/时返回Some((pattern, flags))，否则返回None
+///
+fn split_regex_flags(pattern: &str) -> Option<(&str, &str)> {
+    let (pattern, flags) = pattern.strip_prefix('/')?.rsplit_once('/')?;
+    Some((pattern, flags))
+}
+
+///
+/// 解析按权重随机转移的目标
+/// 例如 "70% stageA, 30% stageB"，每个权重必须为正数且总和为100
+///
+/// # 参数
+/// * targets: 以逗号分隔的带百分比的阶段列表
+///
+/// # 返回值
+/// * 成功返回(阶段, 百分比)列表，格式错误或权重总和不为100时返回None
+///
+pub fn parse_weighted_targets(targets: &str) -> Option<Vec<(String, u32)>> {
+    let targets = targets
+        .split(',')
+        .map(|target| {
+            let caps = WEIGHTED_TARGET.captures(target.trim())?;
+            let weight = caps[1].parse::<u32>().ok().filter(|weight| *weight > 0)?;
+            Some((caps[2].to_string(), weight))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if targets.iter().map(|(_, weight)| weight).sum::<u32>() != 100 {
+        return None;
+    }
+    Some(targets)
+}
+
+///
+/// 解析意图的关键词权重
+/// 例如 {"退款": 3, "refund": 3, "钱": 1}，权重必须为正数
+///
+/// # 参数
+/// * keywords: JSON格式的关键词权重表
+///
+/// # 返回值
+/// * 成功返回按关键词排序的(关键词, 权重)列表，格式错误或为空时返回None
+///
+fn parse_keywords(keywords: &str) -> Option<Vec<(String, f64)>> {
+    let keywords: HashMap<String, f64> = serde_json::from_str(keywords).ok()?;
+    if keywords.is_empty() || keywords.values().any(|weight| *weight <= 0.0) {
+        return None;
+    }
+    let mut keywords: Vec<(String, f64)> = keywords.into_iter().collect();
+    keywords.sort_by(|a, b| a.0.cmp(&b.0));
+    Some(keywords)
+}
+
+impl Default for DSLParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for DSLParser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for block in self.stages.values() {
+            write!(f, "{}", block)?;
+        }
+        Ok(())
+    }
+}
+
+impl DSLParser {
+    ///
+    /// 生成便于阅读的阶段表，用于检查源码被解析成了哪些状态
+    /// 各阶段按定义所在的行排序并编号，标出定义所在的行及入口阶段；
+    /// 从编译后的脚本或JSON载入时没有行信息，按阶段名排序
+    ///
+    pub fn to_table(&self) -> String {
+        let mut stages: Vec<(&String, &StageBlock)> = self.stages.iter().collect();
+        stages.sort_by_key(|(name, _)| (self.stage_lines.get(*name).copied(), *name));
+        let mut table = String::new();
+        let _ = writeln!(table, "Entry: {}", self.entry_stage());
+        if let Some(on_error) = &self.on_error {
+            let _ = writeln!(table, "On error: {}", on_error);
+        }
+        for (index, (name, block)) in stages.into_iter().enumerate() {
+            let _ = write!(table, "\n#{}", index + 1);
+            if let Some(line) = self.stage_lines.get(name) {
+                let _ = write!(table, " line {}", line);
+            }
+            if name == self.entry_stage() {
+                let _ = write!(table, " (entry)");
+            }
+            let _ = writeln!(table);
+            let _ = write!(table, "{}", block);
+        }
+        table
+    }
+}
+
+///
+/// 扫描并解析测试用的脚本，供各模块的测试共用，出错时panic
+///
+#[cfg(test)]
+pub(crate) fn parse_source(source: &str) -> DSLParser {
+    let commands = crate::scanner::Scanner::new(source.to_string())
+        .scan()
+        .unwrap();
+    let mut parser = DSLParser::new();
+    parser.parse(commands).unwrap();
+    parser
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::match_like_matches_macro)]
+mod parser_tests {
+    use super::*;
+    use crate::command::CommandType;
+
+    #[test]
+    fn test_dsl_parser() {
+        let mut parser = DSLParser::new();
+        println!();
+        let commands = vec![
+            Command::new(CommandType::ENTRY("stage1".to_string()), 0),
+            Command::new(CommandType::STAGE("stage1".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
+            Command::new(CommandType::NEXT("stage2".to_string()), 4),
+            Command::new(CommandType::MATCH("pattern2".to_string()), 5),
+            Command::new(CommandType::NEXT("stage3".to_string()), 6),
+            Command::new(CommandType::STAGE("stage2".to_string()), 7),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 8),
+            Command::new(CommandType::MATCH("pattern3".to_string()), 9),
+            Command::new(CommandType::NEXT("stage1".to_string()), 10),
+            Command::new(CommandType::DEFAULT, 11),
+            Command::new(CommandType::NEXT("stage1".to_string()), 12),
+            Command::new(CommandType::STAGE("stage3".to_string()), 13),
+            Command::new(CommandType::SPEAK("speak3".to_string()), 14),
+            Command::new(CommandType::INPUT("input1".to_string()), 15),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 16),
+        ];
+        parser.parse(commands).unwrap();
+        println!("{}", parser);
+        // test if equals to the expected result
+        let mut expected = DSLParser::new();
+        expected.stages.insert(
+            "stage1".to_string(),
+            StageBlock::new(
+                "stage1",
+                "speak1",
+                Transition::Match(vec![
+                    MatchBlock {
+                        pattern: "pattern1".to_string(),
+                        next_stage: "stage2".to_string(),
+                        ..Default::default()
+                    },
+                    MatchBlock {
+                        pattern: "pattern2".to_string(),
+                        next_stage: "stage3".to_string(),
+                        ..Default::default()
+                    },
+                ]),
+            ),
+        );
+        expected.stages.insert(
+            "stage2".to_string(),
+            StageBlock::new(
+                "stage2",
+                "speak2",
+                Transition::Match(vec![
+                    MatchBlock {
+                        pattern: "pattern3".to_string(),
+                        next_stage: "stage1".to_string(),
+                        ..Default::default()
+                    },
+                    MatchBlock {
+                        pattern: ".*".to_string(),
+                        next_stage: "stage1".to_string(),
+                        ..Default::default()
+                    },
+                ]),
+            ),
+        );
+        expected.stages.insert(
+            "stage3".to_string(),
+            StageBlock::new(
+                "stage3",
+                "speak3",
+                Transition::Input(InputBlock {
+                    input_var: "input1".to_string(),
+                    next_stage: "EXIT".to_string(),
+                    secret: false,
+                }),
+            ),
+        );
+        // ENTRY指定的阶段在状态迁移表中标记为入口
+        expected.stages.get_mut("stage1").unwrap().entry = true;
+        assert_eq!(parser.stages, expected.stages);
+    }
+
+    #[test]
+    fn test_dsl_parser_error() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("stage1".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
+            Command::new(CommandType::NEXT("stage2".to_string()), 4),
+            Command::new(CommandType::MATCH("pattern2".to_string()), 5),
+            Command::new(CommandType::NEXT("stage3".to_string()), 6),
+            Command::new(CommandType::STAGE("stage2".to_string()), 7),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 8),
+            Command::new(CommandType::MATCH("pattern3".to_string()), 9),
+            Command::new(CommandType::NEXT("stage1".to_string()), 10),
+            Command::new(CommandType::STAGE("stage3".to_string()), 11),
+            Command::new(CommandType::SPEAK("speak3".to_string()), 12),
+            Command::new(CommandType::INPUT("input1".to_string()), 13),
+            Command::new(CommandType::NEXT("stage1".to_string()), 14),
+            Command::new(CommandType::SPEAK("speak4".to_string()), 15),
+        ];
+        println!();
+        let result = parser.parse(commands);
+        let ans = if let Err(Error::Parse(_)) = result {
+            true
+        } else {
+            false
+        };
+        assert_eq!(ans, true);
+    }
+
+    #[test]
+    fn test_dsl_parser_header() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::NAME("客服机器人".to_string()), 1),
+            Command::new(CommandType::VERSION("1.2.0".to_string()), 2),
+            Command::new(CommandType::AUTHOR("floation".to_string()), 3),
+            Command::new(CommandType::DSLVERSION(1), 4),
+            Command::new(CommandType::STAGE("initial".to_string()), 5),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 6),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 7),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 8),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.meta,
+            ScriptMeta {
+                name: Some("客服机器人".to_string()),
+                version: Some("1.2.0".to_string()),
+                author: Some("floation".to_string()),
+                dsl_version: Some(1),
+            }
+        );
+        assert_eq!(parser.meta.to_string(), "客服机器人 1.2.0 by floation");
+    }
+
+    #[test]
+    fn test_dsl_parser_deprecated_syntax() {
+        let commands = |version: Option<u32>| {
+            let mut commands = vec![
+                Command::new(CommandType::STAGE("initial".to_string()), 2),
+                Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+                Command::new(CommandType::MATCH("EMPTY".to_string()), 4),
+                Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+            ];
+            if let Some(version) = version {
+                commands.insert(0, Command::new(CommandType::DSLVERSION(version), 1));
+            }
+            commands
+        };
+        // 未声明版本或声明旧版本的脚本不产生警告
+        let mut parser = DSLParser::new();
+        parser.parse(commands(None)).unwrap();
+        assert_eq!(parser.dsl_version(), LEGACY_DSL_VERSION);
+        assert!(parser.warnings().is_empty());
+        let mut parser = DSLParser::new();
+        parser.parse(commands(Some(1))).unwrap();
+        assert!(parser.warnings().is_empty());
+        // 声明新版本的脚本使用旧语法时产生警告，但不影响解析结果
+        println!();
+        let mut parser = DSLParser::new();
+        parser.parse(commands(Some(2))).unwrap();
+        assert_eq!(parser.warnings().len(), 1);
+        assert_eq!(parser.warnings()[0].line, 4);
+        assert!(parser.warnings()[0]
+            .to_string()
+            .starts_with("[line 4] Warning"));
+        assert!(parser.stages.contains_key("initial"));
+        // 跟在其他匹配块之后作为兜底分支的EMPTY不产生警告
+        let mut commands = commands(Some(2));
+        commands.insert(
+            3,
+            Command::new(CommandType::MATCH("\"hello\"".to_string()), 4),
+        );
+        commands.insert(4, Command::new(CommandType::NEXT("EXIT".to_string()), 5));
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+        assert!(parser.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_dsl_parser_header_error() {
+        // 需要更新的DSL版本
+        let mut parser = DSLParser::new();
+        let commands = vec![Command::new(CommandType::DSLVERSION(DSL_VERSION + 1), 1)];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+
+        // 脚本头出现在STAGE之后
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::NAME("bot".to_string()), 2),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+
+        // 重复的脚本头
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::NAME("bot".to_string()), 1),
+            Command::new(CommandType::NAME("bot".to_string()), 2),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_entry() {
+        // 未指定ENTRY时默认为initial
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(parser.entry_stage(), "initial");
+        assert!(parser.stages["initial"].entry);
+
+        // ENTRY指定的阶段不存在
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::ENTRY("welcome".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 4),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+
+        // 既没有ENTRY也没有initial阶段
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("welcome".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_on_error() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::ONERROR("oops".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 4),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+            Command::new(CommandType::STAGE("oops".to_string()), 6),
+            Command::new(CommandType::SPEAK("error_message".to_string()), 7),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 8),
+            Command::new(CommandType::NEXT("initial".to_string()), 9),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(parser.on_error, Some("oops".to_string()));
+
+        // ON_ERROR指定的阶段不存在
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::ONERROR("oops".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 4),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_prompt() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::PROMPT("你> ".to_string()), 1),
+            Command::new(CommandType::ROBOTPREFIX("🤖 ".to_string()), 2),
+            Command::new(CommandType::STAGE("initial".to_string()), 3),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 4),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 5),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(parser.prompt.as_deref(), Some("你> "));
+        assert_eq!(parser.robot_prefix.as_deref(), Some("🤖 "));
+
+        // 提示符只能在脚本头中设置一次
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::PROMPT("> ".to_string()), 1),
+            Command::new(CommandType::PROMPT("你> ".to_string()), 2),
+            Command::new(CommandType::STAGE("initial".to_string()), 3),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 4),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 5),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_max_tries() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+            Command::new(CommandType::MAXTRIES(3, "handoff".to_string()), 5),
+            Command::new(CommandType::STAGE("handoff".to_string()), 6),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 7),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 8),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 9),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].max_tries,
+            Some(MaxTries {
+                tries: 3,
+                next_stage: "handoff".to_string(),
+            })
+        );
+        assert_eq!(parser.stages["handoff"].max_tries, None);
+
+        // MAXTRIES不能出现在输入块中
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::INPUT("name".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+            Command::new(CommandType::MAXTRIES(3, "handoff".to_string()), 5),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    ///
+    /// 在命令序列末尾定义直接结束对话的阶段，作为转移的目标阶段
+    ///
+    fn define_stages(commands: &mut Vec<Command>, names: &[&str]) {
+        let line = commands.last().map_or(0, |command| command.line);
+        for (i, name) in names.iter().enumerate() {
+            let line = line + 3 * i as i32;
+            commands.extend([
+                Command::new(CommandType::STAGE(name.to_string()), line + 1),
+                Command::new(CommandType::SPEAK(format!("\"{}\"", name)), line + 2),
+                Command::new(CommandType::GOTO("EXIT".to_string()), line + 3),
+            ]);
+        }
+    }
+
+    #[test]
+    fn test_dsl_parser_undefined_target() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::CONFIRM("billing".to_string(), "EXIT".to_string()),
+                3,
+            ),
+            Command::new(CommandType::MAXTRIES(2, "human".to_string()), 4),
+        ];
+        let mut parser = DSLParser::new();
+        println!();
+        assert!(matches!(
+            parser.parse(commands.clone()),
+            Err(Error::Parse(_))
+        ));
+        let mut commands = commands;
+        define_stages(&mut commands, &["billing", "human"]);
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+    }
+
+    #[test]
+    fn test_dsl_parser_to_json() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("\"你好\"".to_string()), 2),
+            Command::new(CommandType::MATCH("\"退款\"".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&parser.to_json().unwrap()).unwrap();
+        let stage = &json["stages"]["initial"];
+        assert_eq!(stage["speak"], "\"你好\"");
+        let block = &stage["transition"]["Match"][0];
+        assert_eq!(block["pattern"], "\"退款\"");
+        assert_eq!(block["next_stage"], "EXIT");
+        assert!(block.get("regex").is_none());
+        assert!(json.get("patterns").is_none());
+    }
+
+    #[test]
+    fn test_dsl_parser_no_exit() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("loop".to_string()), 3),
+            Command::new(CommandType::STAGE("loop".to_string()), 4),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 5),
+            Command::new(CommandType::INPUT("name".to_string()), 6),
+            Command::new(CommandType::NEXT("initial".to_string()), 7),
+        ];
+        let mut parser = DSLParser::new();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+        let errors = parser.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+        assert_eq!(
+            errors[0].message,
+            "No path from the entry stage reaches EXIT"
+        );
+    }
+
+    #[test]
+    fn test_dsl_parser_from_json() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("\"你好\"".to_string()), 2),
+            Command::new(CommandType::MATCH("\"退款\"".to_string()), 3),
+            Command::new(CommandType::NEXT("refund".to_string()), 4),
+            Command::new(CommandType::MATCH("\"退出\"".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+            Command::new(CommandType::STAGE("refund".to_string()), 7),
+            Command::new(CommandType::SPEAK("\"请稍候\"".to_string()), 8),
+            Command::new(CommandType::FORM, 9),
+            Command::new(
+                CommandType::FIELD("order".to_string(), Some("\"\\d+\"".to_string())),
+                10,
+            ),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 11),
+        ];
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+        let loaded = DSLParser::from_json(&parser.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.stages, parser.stages);
+        assert_eq!(loaded.entry_stage(), "initial");
+        let Transition::Match(blocks) = &loaded.stages["initial"].transition else {
+            panic!("expected match transition");
+        };
+        assert!(blocks[0].regex.as_ref().unwrap().is_match("退款"));
+        assert!(loaded.stages["initial"].match_set.is_some());
+        let Transition::Form(form) = &loaded.stages["refund"].transition else {
+            panic!("expected form transition");
+        };
+        assert!(form.fields[0].regex.as_ref().unwrap().is_match("42"));
+
+        let json = parser.to_json().unwrap().replace("\"EXIT\"", "\"missing\"");
+        assert!(matches!(DSLParser::from_json(&json), Err(Error::Parse(_))));
+        assert!(matches!(DSLParser::from_json("{"), Err(Error::Parse(_))));
+
+        let json = parser
+            .to_json()
+            .unwrap()
+            .replace("\"stage\": \"refund\"", "\"stage\": \"renamed\"");
+        let Err(Error::Parse(error)) = DSLParser::from_json(&json) else {
+            panic!("expected stage name mismatch");
+        };
+        assert_eq!(error.code, Some("E301"));
+    }
+
+    #[test]
+    fn test_dsl_parser_error_columns() {
+        let errors = |source: &str| {
+            let commands = crate::scanner::Scanner::new(source.to_string())
+                .scan()
+                .unwrap();
+            let mut parser = DSLParser::new();
+            assert!(parser.parse(commands).is_err());
+            parser.errors()
+        };
+        println!();
+        let undefined = errors("STAGE initial\nSPEAK \"你好\"\nMATCH \"退款\"\n  NEXT refund\n");
+        assert_eq!(undefined[0].line, 4);
+        assert_eq!(undefined[0].columns, Some(8..14));
+        let context = errors("  SPEAK \"你好\"\n");
+        assert_eq!(context[0].line, 1);
+        assert_eq!(context[0].columns, Some(3..8));
+    }
+
+    #[test]
+    fn test_dsl_parser_codes() {
+        let scan = |source: &str| {
+            crate::scanner::Scanner::new(source.to_string())
+                .scan()
+                .unwrap()
+        };
+        let mut parser = DSLParser::new();
+        assert!(parser.parse(scan("  SPEAK \"你好\"\n")).is_err());
+        assert_eq!(parser.errors()[0].code, Some("E102"));
+        let mut parser = DSLParser::new();
+        let source = "STAGE initial\nSPEAK \"你好\"\nMATCH \"退款\"\nNEXT refund\n";
+        assert!(parser.parse(scan(source)).is_err());
+        assert_eq!(parser.errors()[0].code, Some("E201"));
+        let source = "STAGE initial\nSPEAK \"\"\nGOTO EXIT\n";
+        let mut parser = DSLParser::new();
+        parser.parse(scan(source)).unwrap();
+        assert_eq!(parser.warnings()[0].code, Some("W004"));
+        let mut parser = DSLParser::new();
+        parser.allow("W004");
+        parser.parse(scan(source)).unwrap();
+        assert!(parser.warnings().is_empty());
+        let json = parser.diagnostics_json().unwrap();
+        assert_eq!(json, "[]");
+    }
+
+    #[test]
+    fn test_dsl_parser_analyze() {
+        let mut commands = vec![
+            Command::new(CommandType::ONERROR("oops".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("\"yes\"".to_string()), 4),
+            Command::new(CommandType::NEXT("70% stageA, 30% stageB".to_string()), 5),
+            Command::new(CommandType::DEFAULT, 6),
+            Command::new(CommandType::NEXT("initial".to_string()), 7),
+            Command::new(CommandType::MAXTRIES(2, "human".to_string()), 8),
+        ];
+        define_stages(
+            &mut commands,
+            &["stageA", "stageB", "human", "oops", "legacy"],
+        );
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].next_stages(),
+            vec!["stageA", "stageB", "initial", "human"]
+        );
+        assert_eq!(parser.analyze().unreachable, vec!["legacy".to_string()]);
+    }
+
+    #[test]
+    fn test_dsl_parser_warnings() {
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("\"你叫什么名字\"".to_string()), 2),
+            Command::new(CommandType::INPUT("name".to_string()), 3),
+            Command::new(CommandType::NEXT("menu".to_string()), 4),
+            Command::new(CommandType::STAGE("menu".to_string()), 5),
+            Command::new(CommandType::SPEAK("\"你好\" + name".to_string()), 6),
+            Command::new(CommandType::INPUT("order".to_string()), 7),
+            Command::new(CommandType::NEXT("confirm".to_string()), 8),
+            Command::new(CommandType::STAGE("confirm".to_string()), 9),
+            Command::new(CommandType::SPEAK("\"确认吗\"".to_string()), 10),
+            Command::new(CommandType::DEFAULT, 11),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 12),
+            Command::new(CommandType::MATCH("\"yes\"".to_string()), 13),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 14),
+        ];
+        define_stages(&mut commands, &["legacy"]);
+        let mut parser = DSLParser::new();
+        println!();
+        parser.parse(commands).unwrap();
+        let warnings: Vec<(i32, &str)> = parser
+            .warnings()
+            .iter()
+            .map(|warning| (warning.line, warning.message.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (15, "Stage is unreachable from 'initial'"),
+                (9, "Match patterns after DEFAULT are never tried"),
+                (7, "Variable 'order' is never used"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dsl_parser_empty_speak() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("\"\"".to_string()), 2),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 3),
+        ];
+        let mut parser = DSLParser::new();
+        println!();
+        parser.parse(commands).unwrap();
+        assert_eq!(parser.warnings().len(), 1);
+        assert_eq!(parser.warnings()[0].line, 1);
+        assert_eq!(parser.warnings()[0].message, "SPEAK is empty");
+    }
+
+    #[test]
+    fn test_dsl_parser_multiple_errors() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 3),
+            Command::new(CommandType::STAGE("menu".to_string()), 4),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 5),
+            Command::new(CommandType::STAGE("refund".to_string()), 6),
+            Command::new(CommandType::SPEAK("speak3".to_string()), 7),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 8),
+            Command::new(CommandType::STAGE("bye".to_string()), 9),
+            Command::new(CommandType::SPEAK("speak4".to_string()), 10),
+        ];
+        let mut parser = DSLParser::new();
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+        let lines: Vec<i32> = parser.errors().iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![3, 6, 10]);
+        assert!(parser.errors()[2]
+            .to_string()
+            .starts_with("[line 10] Error"));
+    }
+
+    #[test]
+    fn test_dsl_parser_header_after_recovery() {
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 3),
+            Command::new(CommandType::STAGE("menu".to_string()), 4),
+            Command::new(CommandType::ENTRY("menu".to_string()), 5),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 6),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 7),
+        ];
+        let mut parser = DSLParser::new();
+        assert!(parser.parse(commands).is_err());
+        let errors: Vec<_> = parser
+            .errors()
+            .iter()
+            .map(|error| (error.line, error.code))
+            .collect();
+        assert_eq!(errors, vec![(3, Some("E102")), (5, Some("E102"))]);
+        assert!(parser.entry.is_none());
+    }
+
+    #[test]
+    fn test_dsl_parser_duplicate_stage() {
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("welcome".to_string()), 3),
+        ];
+        define_stages(&mut commands, &["welcome", "welcome"]);
+        let mut parser = DSLParser::new();
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_timeout() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::TIMEOUT(60, "bye".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::INPUT("name".to_string()), 4),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+            Command::new(CommandType::TIMEOUT(10, "prompt".to_string()), 6),
+            Command::new(CommandType::STAGE("prompt".to_string()), 7),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 8),
+            Command::new(CommandType::GOTO("initial".to_string()), 9),
+        ];
+        define_stages(&mut commands, &["bye"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.timeout,
+            Some(InputTimeout {
+                duration: Duration::from_secs(60),
+                next_stage: "bye".to_string(),
+            })
+        );
+        assert_eq!(
+            parser.stages["initial"].timeout,
+            Some(InputTimeout {
+                duration: Duration::from_secs(10),
+                next_stage: "prompt".to_string(),
+            })
+        );
+        assert_eq!(parser.stages["prompt"].timeout, None);
+
+        // GOTO不读取输入，不能设置超时
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 3),
+            Command::new(CommandType::TIMEOUT(10, "initial".to_string()), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_while() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::INPUT("more".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+            Command::new(
+                CommandType::WHILE("more == \"y\"".to_string(), "initial".to_string(), Some(5)),
+                5,
+            ),
+            Command::new(CommandType::STAGE("other".to_string()), 6),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 7),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 8),
+            Command::new(
+                CommandType::WHILE("count < 3".to_string(), "other".to_string(), None),
+                9,
+            ),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].while_loop,
+            Some(WhileLoop {
+                condition: Condition::parse("more == \"y\"").unwrap(),
+                next_stage: "initial".to_string(),
+                max_iterations: 5,
+            })
+        );
+        assert_eq!(
+            parser.stages["other"]
+                .while_loop
+                .as_ref()
+                .unwrap()
+                .max_iterations,
+            DEFAULT_MAX_ITERATIONS
+        );
+
+        // WHILE必须跟在完整的转移之后，且条件格式正确
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::WHILE("count < 3".to_string(), "initial".to_string(), None),
+                3,
+            ),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 3),
+            Command::new(
+                CommandType::WHILE("count".to_string(), "initial".to_string(), None),
+                4,
+            ),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_speak_locales() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("\"您好\"".to_string()), 2),
+            Command::new(
+                CommandType::SPEAKLOCALE("en".to_string(), "\"Hello\"".to_string()),
+                3,
+            ),
+            Command::new(CommandType::MATCH("EMPTY".to_string()), 4),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 5),
+        ];
+        parser.parse(commands).unwrap();
+        let stage = &parser.stages["initial"];
+        assert_eq!(stage.speak_for(Some("en")), "\"Hello\"");
+        assert_eq!(stage.speak_for(Some("fr")), "\"您好\"");
+        assert_eq!(stage.speak_for(None), "\"您好\"");
+
+        // 多语言输出必须跟在默认的SPEAK之后
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(
+                CommandType::SPEAKLOCALE("en".to_string(), "\"Hello\"".to_string()),
+                2,
+            ),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_goto() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("next".to_string()), 3),
+            Command::new(CommandType::STAGE("next".to_string()), 4),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 5),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 6),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Goto("next".to_string())
+        );
+        assert_eq!(
+            parser.stages["next"].transition,
+            Transition::Goto("EXIT".to_string())
+        );
+
+        // GOTO不能与匹配块混用
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("next".to_string()), 3),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_clear() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::CLEAR("name".to_string()), 3),
+            Command::new(CommandType::CLEAR("ALL".to_string()), 4),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 5),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].actions,
+            vec![Action::Clear("name".to_string()), Action::ClearAll]
+        );
+
+        // 动作不能出现在迁移条件之后
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 3),
+            Command::new(CommandType::CLEAR("name".to_string()), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_match_any() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::MATCH(r#"ANY ["yes", "ok", "好的."]"#.to_string()),
+                3,
+            ),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Match(vec![MatchBlock {
+                pattern: r#""(?:yes|ok|好的\.)""#.to_string(),
+                next_stage: "EXIT".to_string(),
+                ..Default::default()
+            }])
+        );
+
+        // 同义词列表为空或格式错误
+        for list in [r#"ANY []"#, r#"ANY ["yes", ok]"#] {
+            let mut parser = DSLParser::new();
+            let commands = vec![
+                Command::new(CommandType::STAGE("initial".to_string()), 1),
+                Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+                Command::new(CommandType::MATCH(list.to_string()), 3),
+            ];
+            println!();
+            assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+        }
+    }
+
+    #[test]
+    fn test_dsl_parser_fuzzy_match() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::FUZZYMATCH("\"refund\"".to_string()), 3),
+            Command::new(CommandType::NEXT("refund".to_string()), 4),
+            Command::new(CommandType::DEFAULT, 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        define_stages(&mut commands, &["refund"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Match(vec![
+                MatchBlock {
+                    pattern: "\"refund\"".to_string(),
+                    next_stage: "refund".to_string(),
+                    fuzzy: true,
+                    ..Default::default()
+                },
+                MatchBlock {
+                    pattern: ".*".to_string(),
+                    next_stage: "EXIT".to_string(),
+                    ..Default::default()
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dsl_parser_intent() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::INTENT(r#"{"退款": 3, "钱": 1}"#.to_string()),
+                3,
+            ),
+            Command::new(CommandType::NEXT("refund".to_string()), 4),
+            Command::new(CommandType::DEFAULT, 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+            Command::new(CommandType::MAXTRIES(2, "EXIT".to_string()), 7),
+        ];
+        define_stages(&mut commands, &["refund"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Intent(vec![
+                IntentBlock {
+                    keywords: vec![("退款".to_string(), 3.0), ("钱".to_string(), 1.0)],
+                    next_stage: "refund".to_string(),
+                },
+                IntentBlock {
+                    keywords: vec![],
+                    next_stage: "EXIT".to_string(),
+                },
+            ])
+        );
+
+        // 意图块不能与匹配块混用
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::INTENT(r#"{"退款": 3}"#.to_string()), 3),
+            Command::new(CommandType::NEXT("refund".to_string()), 4),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 5),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+
+        // 权重必须为正数
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::INTENT(r#"{"退款": 0}"#.to_string()), 3),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_intent_score() {
+        let block = IntentBlock {
+            keywords: vec![("退款".to_string(), 3.0), ("Refund".to_string(), 2.0)],
+            next_stage: "refund".to_string(),
+        };
+        assert_eq!(block.score("我要退款, refund please"), 5.0);
+        assert_eq!(block.score("查询订单"), 0.0);
+    }
+
+    #[test]
+    fn test_dsl_parser_priority() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
+            Command::new(CommandType::NEXT("stage1".to_string()), 4),
+            Command::new(CommandType::MATCH("pattern2".to_string()), 5),
+            Command::new(CommandType::PRIORITY(2), 6),
+            Command::new(CommandType::NEXT("stage2".to_string()), 7),
+            Command::new(CommandType::DEFAULT, 8),
+            Command::new(CommandType::NEXT("stage3".to_string()), 9),
+            Command::new(CommandType::MATCH("pattern3".to_string()), 10),
+            Command::new(CommandType::PRIORITY(1), 11),
+            Command::new(CommandType::NEXT("stage4".to_string()), 12),
+        ];
+        define_stages(&mut commands, &["stage1", "stage2", "stage3", "stage4"]);
+        parser.parse(commands).unwrap();
+        let Transition::Match(blocks) = &parser.stages["initial"].transition else {
+            panic!("expected match transition");
+        };
+        let order: Vec<&str> = blocks.iter().map(|b| b.next_stage.as_str()).collect();
+        assert_eq!(order, vec!["stage4", "stage2", "stage1", "stage3"]);
+
+        // 同一阶段中优先级重复
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
+            Command::new(CommandType::PRIORITY(1), 4),
+            Command::new(CommandType::NEXT("stage1".to_string()), 5),
+            Command::new(CommandType::MATCH("pattern2".to_string()), 6),
+            Command::new(CommandType::PRIORITY(1), 7),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+
+        // 优先级必须在MATCH与NEXT之间
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::PRIORITY(1), 3),
+        ];
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_match_not() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("NOT \"\\d+\"".to_string()), 3),
+            Command::new(CommandType::NEXT("clarify".to_string()), 4),
+            Command::new(CommandType::MATCH("NOTHING".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        define_stages(&mut commands, &["clarify"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Match(vec![
+                MatchBlock {
+                    pattern: "\"\\d+\"".to_string(),
+                    next_stage: "clarify".to_string(),
+                    negated: true,
+                    ..Default::default()
                 },
-            }
-        }
-        // 最后一个阶段保存
-        if let Some(stage) = current_stage {
-            if let Some(speak) = current_speak {
-                self.stages.insert(
-                    stage.clone(),
-                    StageBlock::new(&stage, &speak, current_transition.unwrap()),
-                );
-            }
+                MatchBlock {
+                    pattern: "NOTHING".to_string(),
+                    next_stage: "EXIT".to_string(),
+                    ..Default::default()
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dsl_parser_match_flags() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("NOT /a.*b/is".to_string()), 3),
+            Command::new(CommandType::NEXT("clarify".to_string()), 4),
+            Command::new(CommandType::MATCH("/a\\/b/".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        define_stages(&mut commands, &["clarify"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Match(vec![
+                MatchBlock {
+                    pattern: "a.*b".to_string(),
+                    next_stage: "clarify".to_string(),
+                    negated: true,
+                    flags: Some("is".to_string()),
+                    ..Default::default()
+                },
+                MatchBlock {
+                    pattern: "a\\/b".to_string(),
+                    next_stage: "EXIT".to_string(),
+                    flags: Some(String::new()),
+                    ..Default::default()
+                },
+            ])
+        );
+
+        // 未知的标志和MATCH~中的标志均为语法错误
+        for ctype in [
+            CommandType::MATCH("/abc/g".to_string()),
+            CommandType::FUZZYMATCH("/abc/i".to_string()),
+        ] {
+            let mut parser = DSLParser::new();
+            let commands = vec![
+                Command::new(CommandType::STAGE("initial".to_string()), 1),
+                Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+                Command::new(ctype, 3),
+            ];
+            println!();
+            assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
         }
+    }
 
-        Ok(())
+    #[test]
+    fn test_dsl_parser_completions() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("\"退款|退货\"".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+            Command::new(CommandType::MATCH("order-\\d+|退货".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+            Command::new(CommandType::FUZZYMATCH("hello".to_string()), 7),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 8),
+            Command::new(CommandType::MATCH("$expected".to_string()), 9),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 10),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].completions(),
+            vec!["退款", "退货", "hello"]
+        );
     }
-}
 
-impl fmt::Display for DSLParser {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (k, v) in &self.stages {
-            write!(f, "Stage: {}\n", k)?;
-            write!(f, "  Speak: {}\n", v.speak)?;
-            match &v.transition {
-                Transition::Match(blocks) => {
-                    for block in blocks {
-                        write!(f, "  Match: {} -> {}\n", block.pattern, block.next_stage)?;
-                    }
-                }
-                Transition::Input(block) => {
-                    write!(f, "  Input: {} -> {}\n", block.input_var, block.next_stage)?;
-                }
-            }
-        }
-        Ok(())
+    #[test]
+    fn test_dsl_parser_compile_regex() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("\"yes|y\"".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+            Command::new(CommandType::MATCH("$expected".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+            Command::new(CommandType::FUZZYMATCH("hello".to_string()), 7),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 8),
+        ];
+        parser.parse(commands).unwrap();
+        let Transition::Match(blocks) = &parser.stages["initial"].transition else {
+            panic!("expected match blocks");
+        };
+        let regex = blocks[0].regex.as_ref().unwrap();
+        assert!(regex.is_match("YES"));
+        assert!(!regex.is_match("no"));
+        // $var和模糊匹配在匹配时才能确定
+        assert!(blocks[1].regex.is_none());
+        assert!(blocks[2].regex.is_none());
+
+        // 不合法的正则表达式在解析时报告
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("\"(unclosed\"".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
     }
-}
 
-#[cfg(test)]
-mod parser_tests {
-    use super::*;
-    use crate::command::CommandType;
+    #[test]
+    fn test_dsl_parser_match_mode() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::MATCHMODE("CONTAINS".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("\"refund\"".to_string()), 4),
+            Command::new(CommandType::NEXT("refund".to_string()), 5),
+            Command::new(CommandType::MATCH("NOT EXACT \"\\d+\"".to_string()), 6),
+            Command::new(CommandType::NEXT("clarify".to_string()), 7),
+            Command::new(CommandType::FUZZYMATCH("\"help\"".to_string()), 8),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 9),
+        ];
+        define_stages(&mut commands, &["refund", "clarify"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(parser.match_contains, Some(true));
+        let Transition::Match(blocks) = &parser.stages["initial"].transition else {
+            panic!("expected match blocks");
+        };
+        assert!(blocks[0].contains);
+        assert!(!blocks[1].contains && blocks[1].negated);
+        assert_eq!(blocks[1].pattern, "\"\\d+\"");
+        assert!(!blocks[2].contains);
+
+        // 未指定MATCH_MODE时默认整体匹配，MATCH~不支持匹配方式前缀
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("CONTAINS \"refund\"".to_string()), 3),
+            Command::new(CommandType::NEXT("refund".to_string()), 4),
+            Command::new(CommandType::MATCH("\"help\"".to_string()), 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        define_stages(&mut commands, &["refund"]);
+        parser.parse(commands).unwrap();
+        let Transition::Match(blocks) = &parser.stages["initial"].transition else {
+            panic!("expected match blocks");
+        };
+        assert!(blocks[0].contains && !blocks[1].contains);
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::FUZZYMATCH("CONTAINS help".to_string()), 3),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
 
     #[test]
-    fn test_dsl_parser() {
+    fn test_parse_weighted_targets() {
+        assert_eq!(
+            parse_weighted_targets("70% stageA, 30% stageB"),
+            Some(vec![("stageA".to_string(), 70), ("stageB".to_string(), 30)])
+        );
+        assert_eq!(parse_weighted_targets("70% stageA, 20% stageB"), None);
+        assert_eq!(parse_weighted_targets("100% stageA, 0% stageB"), None);
+        assert_eq!(parse_weighted_targets("70% stageA 30% stageB"), None);
+    }
+
+    #[test]
+    fn test_dsl_parser_weighted_next() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("\"yes\"".to_string()), 3),
+            Command::new(CommandType::NEXT("70% stageA, 30% stageB".to_string()), 4),
+            Command::new(CommandType::DEFAULT, 5),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        define_stages(&mut commands, &["stageA", "stageB"]);
+        parser.parse(commands).unwrap();
+        let stage = &parser.stages["initial"];
+        assert_eq!(stage.weighted_next.len(), 1);
+        assert_eq!(
+            stage.weighted_next["70% stageA, 30% stageB"],
+            vec![("stageA".to_string(), 70), ("stageB".to_string(), 30)]
+        );
+
         let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("50% stageA, 30% stageB".to_string()), 3),
+        ];
         println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_tags() {
+        let mut parser = DSLParser::new();
         let commands = vec![
-            Command::new(CommandType::STAGE("stage1".to_string()), 1),
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
             Command::new(CommandType::SPEAK("speak1".to_string()), 2),
-            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
-            Command::new(CommandType::NEXT("stage2".to_string()), 4),
-            Command::new(CommandType::MATCH("pattern2".to_string()), 5),
-            Command::new(CommandType::NEXT("stage3".to_string()), 6),
-            Command::new(CommandType::STAGE("stage2".to_string()), 7),
-            Command::new(CommandType::SPEAK("speak2".to_string()), 8),
-            Command::new(CommandType::MATCH("pattern3".to_string()), 9),
-            Command::new(CommandType::NEXT("stage1".to_string()), 10),
-            Command::new(CommandType::DEFAULT, 11),
-            Command::new(CommandType::NEXT("stage1".to_string()), 12),
-            Command::new(CommandType::STAGE("stage3".to_string()), 13),
-            Command::new(CommandType::SPEAK("speak3".to_string()), 14),
-            Command::new(CommandType::INPUT("input1".to_string()), 15),
-            Command::new(CommandType::NEXT("stage1".to_string()), 16),
+            Command::new(
+                CommandType::TAG(vec!["billing".to_string(), "escalation".to_string()]),
+                3,
+            ),
+            Command::new(CommandType::TAG(vec!["billing".to_string()]), 4),
+            Command::new(CommandType::GOTO("refund".to_string()), 5),
+            Command::new(CommandType::STAGE("refund".to_string()), 6),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 7),
+            Command::new(CommandType::TAG(vec!["billing".to_string()]), 8),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 9),
         ];
         parser.parse(commands).unwrap();
-        println!("{}", parser);
-        // test if equals to the expected result
-        let mut expected = DSLParser::new();
-        expected.stages.insert(
-            "stage1".to_string(),
-            StageBlock::new(
-                "stage1",
-                "speak1",
-                Transition::Match(vec![
-                    MatchBlock {
-                        pattern: "pattern1".to_string(),
-                        next_stage: "stage2".to_string(),
+        assert_eq!(parser.stages["initial"].tags, ["billing", "escalation"]);
+        let stages: Vec<&str> = parser
+            .stages_with_tag("billing")
+            .iter()
+            .map(|stage| stage.stage.as_str())
+            .collect();
+        assert_eq!(stages, ["initial", "refund"]);
+        assert_eq!(parser.stages_with_tag("escalation").len(), 1);
+
+        // 标签不能出现在迁移条件之后
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 3),
+            Command::new(CommandType::TAG(vec!["billing".to_string()]), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_form() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::FORM, 3),
+            Command::new(CommandType::FIELD("name".to_string(), None), 4),
+            Command::new(
+                CommandType::FIELD("phone".to_string(), Some("\"\\d{11}\"".to_string())),
+                5,
+            ),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 6),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Form(FormBlock {
+                fields: vec![
+                    FormField {
+                        name: "name".to_string(),
+                        validation: None,
+                        regex: None,
                     },
-                    MatchBlock {
-                        pattern: "pattern2".to_string(),
-                        next_stage: "stage3".to_string(),
+                    FormField {
+                        name: "phone".to_string(),
+                        validation: Some("\"\\d{11}\"".to_string()),
+                        regex: None,
                     },
-                ]),
+                ],
+                next_stage: "EXIT".to_string(),
+            })
+        );
+
+        // 表单至少包含一个字段
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::FORM, 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_confirm() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::CONFIRM("EXIT".to_string(), "initial".to_string()),
+                3,
             ),
+            Command::new(CommandType::MAXTRIES(2, "EXIT".to_string()), 4),
+        ];
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Confirm("EXIT".to_string(), "initial".to_string())
         );
-        expected.stages.insert(
-            "stage2".to_string(),
-            StageBlock::new(
-                "stage2",
-                "speak2",
-                Transition::Match(vec![
+        assert!(parser.stages["initial"].max_tries.is_some());
+
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::MATCH("\"yes\"".to_string()), 3),
+            Command::new(
+                CommandType::CONFIRM("EXIT".to_string(), "initial".to_string()),
+                4,
+            ),
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dsl_parser_named_patterns() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(
+                CommandType::PATTERN("order_id".to_string(), "\"[A-Z]{2}\\d{6}\"".to_string()),
+                1,
+            ),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::MATCH("NOT @order_id".to_string()), 4),
+            Command::new(CommandType::NEXT("initial".to_string()), 5),
+            Command::new(CommandType::MATCH("CONTAINS @email".to_string()), 6),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 7),
+            Command::new(CommandType::STAGE("form".to_string()), 8),
+            Command::new(CommandType::SPEAK("speak2".to_string()), 9),
+            Command::new(CommandType::FORM, 10),
+            Command::new(
+                CommandType::FIELD("when".to_string(), Some("@date".to_string())),
+                11,
+            ),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 12),
+        ];
+        parser.parse(commands).unwrap();
+        let Transition::Match(blocks) = &parser.stages["initial"].transition else {
+            panic!("expected match blocks");
+        };
+        assert_eq!(blocks[0].pattern, "\"[A-Z]{2}\\d{6}\"");
+        assert!(blocks[0].negated);
+        assert_eq!(blocks[1].pattern, parser.patterns.get("email").unwrap());
+        assert!(blocks[1].contains);
+        let Transition::Form(form) = &parser.stages["form"].transition else {
+            panic!("expected form block");
+        };
+        assert_eq!(
+            form.fields[0].validation.as_deref(),
+            parser.patterns.get("date")
+        );
+
+        // 未定义的表达式和重复定义均为语法错误
+        for commands in [
+            vec![
+                Command::new(CommandType::STAGE("initial".to_string()), 1),
+                Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+                Command::new(CommandType::MATCH("@unknown".to_string()), 3),
+            ],
+            vec![
+                Command::new(CommandType::PATTERN("a".to_string(), "a".to_string()), 1),
+                Command::new(CommandType::PATTERN("a".to_string(), "b".to_string()), 2),
+            ],
+        ] {
+            let mut parser = DSLParser::new();
+            println!();
+            assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
+        }
+    }
+
+    #[test]
+    fn test_dsl_parser_invalid_named_pattern() {
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(
+                CommandType::PATTERN("order_id".to_string(), "\"[A-Z]{2\"".to_string()),
+                1,
+            ),
+            Command::new(CommandType::STAGE("initial".to_string()), 2),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 3),
+            Command::new(CommandType::NEXT("EXIT".to_string()), 4),
+        ];
+        let Err(Error::Parse(diagnostic)) = parser.parse(commands) else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(diagnostic.code, Some("E106"));
+        assert_eq!(diagnostic.line, 1);
+        assert_eq!(diagnostic.message, "Invalid regex pattern");
+    }
+
+    #[test]
+    fn test_dsl_parser_switch() {
+        let mut parser = DSLParser::new();
+        let mut commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(CommandType::SWITCH("category".to_string()), 3),
+            Command::new(
+                CommandType::CASE("\"billing.\"".to_string(), "billing".to_string()),
+                4,
+            ),
+            Command::new(
+                CommandType::CASE("DEFAULT".to_string(), "other".to_string()),
+                5,
+            ),
+        ];
+        define_stages(&mut commands, &["billing", "other"]);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.stages["initial"].transition,
+            Transition::Switch(
+                "category".to_string(),
+                vec![
                     MatchBlock {
-                        pattern: "pattern3".to_string(),
-                        next_stage: "stage1".to_string(),
+                        pattern: "billing\\.".to_string(),
+                        next_stage: "billing".to_string(),
+                        ..Default::default()
                     },
                     MatchBlock {
                         pattern: ".*".to_string(),
-                        next_stage: "stage1".to_string(),
+                        next_stage: "other".to_string(),
+                        ..Default::default()
                     },
-                ]),
-            ),
+                ]
+            )
         );
-        expected.stages.insert(
-            "stage3".to_string(),
-            StageBlock::new(
-                "stage3",
-                "speak3",
-                Transition::Input(InputBlock {
-                    input_var: "input1".to_string(),
-                    next_stage: "stage1".to_string(),
-                }),
+
+        // CASE必须跟在SWITCH之后
+        let mut parser = DSLParser::new();
+        let commands = vec![
+            Command::new(CommandType::STAGE("initial".to_string()), 1),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
+            Command::new(
+                CommandType::CASE("billing".to_string(), "billing".to_string()),
+                3,
             ),
-        );
-        assert_eq!(parser.stages, expected.stages);
+        ];
+        println!();
+        assert!(matches!(parser.parse(commands), Err(Error::Parse(_))));
     }
 
     #[test]
-    fn test_dsl_parser_error() {
+    fn test_dsl_parser_table() {
         let mut parser = DSLParser::new();
         let commands = vec![
-            Command::new(CommandType::STAGE("stage1".to_string()), 1),
-            Command::new(CommandType::SPEAK("speak1".to_string()), 2),
-            Command::new(CommandType::MATCH("pattern1".to_string()), 3),
-            Command::new(CommandType::NEXT("stage2".to_string()), 4),
-            Command::new(CommandType::MATCH("pattern2".to_string()), 5),
-            Command::new(CommandType::NEXT("stage3".to_string()), 6),
-            Command::new(CommandType::STAGE("stage2".to_string()), 7),
+            Command::new(CommandType::ENTRY("start".to_string()), 1),
+            Command::new(CommandType::STAGE("start".to_string()), 3),
+            Command::new(CommandType::SPEAK("speak1".to_string()), 4),
+            Command::new(CommandType::GOTO("bye".to_string()), 5),
+            Command::new(CommandType::STAGE("bye".to_string()), 7),
             Command::new(CommandType::SPEAK("speak2".to_string()), 8),
-            Command::new(CommandType::MATCH("pattern3".to_string()), 9),
-            Command::new(CommandType::NEXT("stage1".to_string()), 10),
-            Command::new(CommandType::STAGE("stage3".to_string()), 11),
-            Command::new(CommandType::SPEAK("speak3".to_string()), 12),
-            Command::new(CommandType::INPUT("input1".to_string()), 13),
-            Command::new(CommandType::NEXT("stage1".to_string()), 14),
-            Command::new(CommandType::SPEAK("speak4".to_string()), 15),
+            Command::new(CommandType::GOTO("EXIT".to_string()), 9),
         ];
-        println!();
-        let result = parser.parse(commands);
-        let ans = if let Err(Error::Parse) = result {
-            true
-        } else {
-            false
-        };
-        assert_eq!(ans, true);
+        parser.parse(commands).unwrap();
+        assert_eq!(
+            parser.to_table(),
+            "Entry: start\n\n#1 line 3 (entry)\nStage: start\n  Speak: speak1\n  Goto: bye\n\n#2 line 7\nStage: bye\n  Speak: speak2\n  Goto: EXIT\n"
+        );
     }
 }
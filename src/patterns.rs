@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+///
+/// 内置的命名匹配表达式，可在MATCH和FIELD中以@name的形式引用
+///
+/// - email: 电子邮箱
+/// - phone_cn: 中国大陆手机号，可带+86前缀
+/// - number: 整数或小数，可带正负号
+/// - date: 年-月-日形式的日期，分隔符可以是-、/或.
+///
+pub const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("email", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("phone_cn", r"(?:\+?86[- ]?)?1[3-9]\d{9}"),
+    ("number", r"[+-]?\d+(?:\.\d+)?"),
+    (
+        "date",
+        r"\d{4}[-/.](?:0?[1-9]|1[0-2])[-/.](?:0?[1-9]|[12]\d|3[01])",
+    ),
+];
+
+///
+/// 命名匹配表达式库
+/// 脚本中PATTERN定义的表达式优先于同名的内置表达式
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PatternLibrary {
+    /// 脚本定义的匹配表达式
+    pub custom: HashMap<String, String>,
+}
+
+impl PatternLibrary {
+    ///
+    /// 定义匹配表达式
+    ///
+    /// # 参数
+    /// * name: 表达式名(不含@)
+    /// * pattern: 匹配表达式
+    ///
+    /// # 返回值
+    /// * 脚本中已定义过同名表达式时返回true
+    ///
+    pub fn define(&mut self, name: &str, pattern: &str) -> bool {
+        self.custom
+            .insert(name.to_string(), pattern.to_string())
+            .is_some()
+    }
+
+    ///
+    /// 查找匹配表达式
+    ///
+    /// # 参数
+    /// * name: 表达式名(不含@)
+    ///
+    /// # 返回值
+    /// * 找到时返回Some(匹配表达式)，否则返回None
+    ///
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.custom.get(name).map(String::as_str).or_else(|| {
+            BUILTIN_PATTERNS
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, pattern)| *pattern)
+        })
+    }
+
+    ///
+    /// 展开@name形式的匹配表达式
+    ///
+    /// # 参数
+    /// * pattern: 匹配表达式
+    ///
+    /// # 返回值
+    /// * 不是@name形式时返回Ok(原表达式)，找到时返回Ok(展开后的表达式)，
+    ///   表达式未定义时返回Err(表达式名)
+    ///
+    pub fn expand<'a>(&'a self, pattern: &'a str) -> Result<&'a str, &'a str> {
+        match pattern.trim().strip_prefix('@').filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }) {
+            Some(name) => self.get(name).ok_or(name),
+            None => Ok(pattern),
+        }
+    }
+}
+
+#[cfg(test)]
+mod patterns_tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_builtin_patterns() {
+        let library = PatternLibrary::default();
+        let is_match = |name: &str, input: &str| {
+            let pattern = library.get(name).unwrap();
+            Regex::new(&format!("^{}$", pattern))
+                .unwrap()
+                .is_match(input)
+        };
+        assert!(is_match("email", "floation@example.com"));
+        assert!(!is_match("email", "floation@example"));
+        assert!(is_match("phone_cn", "13800138000"));
+        assert!(is_match("phone_cn", "+86 13800138000"));
+        assert!(!is_match("phone_cn", "12800138000"));
+        assert!(is_match("number", "-3.14"));
+        assert!(!is_match("number", "3."));
+        assert!(is_match("date", "2024-05-01"));
+        assert!(is_match("date", "2024/5/1"));
+        assert!(!is_match("date", "2024-13-01"));
+    }
+
+    #[test]
+    fn test_expand_and_override() {
+        let mut library = PatternLibrary::default();
+        assert_eq!(library.expand("\"hello\""), Ok("\"hello\""));
+        assert_eq!(library.expand("@number"), Ok(BUILTIN_PATTERNS[2].1));
+        assert_eq!(library.expand("@order_id"), Err("order_id"));
+        assert!(!library.define("order_id", "\"[A-Z]{2}\\d{6}\""));
+        assert!(!library.define("number", "\\d+"));
+        assert!(library.define("number", "\\d+"));
+        assert_eq!(library.expand("@order_id"), Ok("\"[A-Z]{2}\\d{6}\""));
+        assert_eq!(library.expand("@number"), Ok("\\d+"));
+    }
+}
@@ -1,72 +1,1007 @@
-use crate::command::{Command, CommandType};
-use crate::error::{error, Error};
+use crate::command::{Columns, Command, CommandType, Token, TokenKind};
+use crate::error::{Diagnostic, Error, Severity};
 use regex::Regex;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Cursor};
+use std::ops::Range;
+use std::sync::LazyLock;
+///
+/// 阶段名需满足的正则表达式
+///
+static STAGE_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.-]+$").unwrap());
+
+///
+/// 变量名需满足的正则表达式
+///
+static VARIABLE_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\w+$").unwrap());
+
+///
+/// 宏引用${NAME}的正则表达式
+///
+static MACRO_REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$\{(\w+)\}").unwrap());
+
+///
+/// FIELD的参数：字段名及可选的正则表达式
+///
+static FIELD_ARGUMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\w+)(?:\s+(.+))?$").unwrap());
+
+///
+/// CONFIRM的参数：NEXT <stage> ELSE <stage>
+///
+static CONFIRM_ARGUMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^NEXT\s+(\S+)\s+ELSE\s+(\S+)$").unwrap());
+
+///
+/// CASE的参数：<value> NEXT <stage>
+///
+static CASE_ARGUMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.+?)\s+NEXT\s+(\S+)$").unwrap());
+
+///
+/// WHILE的参数：<condition> NEXT <stage> [MAX <n>]
+///
+static WHILE_ARGUMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.+?)\s+NEXT\s+(\S+)(?:\s+MAX\s+(\S+))?$").unwrap());
+
+///
+/// PATTERN的参数：@<name> <regex>
+///
+static PATTERN_ARGUMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^@(\w+)\s+(.+)$").unwrap());
+
+///
+/// 命令关键字，SPEAK.<lang>除外
+///
+const KEYWORDS: &[&str] = &[
+    "MATCH",
+    "MATCH~",
+    "INTENT",
+    "INPUT",
+    "SPEAK",
+    "NEXT",
+    "STAGE",
+    "DEFAULT",
+    "FORM",
+    "FIELD",
+    "CONFIRM",
+    "GOTO",
+    "CLEAR",
+    "SAVE",
+    "LOAD",
+    "TAG",
+    "SWITCH",
+    "CASE",
+    "PRIORITY",
+    "MAXTRIES",
+    "TIMEOUT",
+    "WHILE",
+    "NAME",
+    "VERSION",
+    "AUTHOR",
+    "ENTRY",
+    "ON_ERROR",
+    "PROMPT",
+    "ROBOT_PREFIX",
+    "PATTERN",
+    "MATCH_MODE",
+    "DSLVERSION",
+    "DEFINE",
+];
+
+///
+/// 参数中带有子句关键字的命令及子句关键字，忽略大小写时子句关键字也转换为大写
+///
+const CLAUSE_COMMANDS: &[&str] = &[
+    "INPUT",
+    "CONFIRM",
+    "CASE",
+    "MAXTRIES",
+    "TIMEOUT",
+    "WHILE",
+    "MATCH_MODE",
+];
+const CLAUSE_KEYWORDS: &[&str] = &["NEXT", "ELSE", "MAX", "SECRET", "EXACT", "CONTAINS"];
+
 ///
 /// scan input strings into commands
 ///
+/// - source 源代码，按行读取，扫描后为空
 /// - current 当前解析的位置
+/// - lenient 宽松模式，出错的行作为警告跳过而不中断扫描
+/// - ignore_case 忽略关键字的大小写，如stage、Speak
+/// - warnings 宽松模式下收集的警告
+/// - errors 扫描过程中产生的错误，出错后继续扫描后面的行，故可能有多个错误
+/// - macros DEFINE定义的宏，扫描时将${NAME}替换为宏的内容
+/// - columns 当前行中命令关键字及参数的列范围
+/// - line 当前行的原始内容，用于在诊断信息中显示
 ///
-pub struct Scanner {
-    source: String,
+pub struct Scanner<'r> {
+    source: Box<dyn BufRead + 'r>,
     current: usize,
+    lenient: bool,
+    ignore_case: bool,
+    warnings: RefCell<Vec<String>>,
+    errors: RefCell<Vec<Diagnostic>>,
+    macros: HashMap<String, String>,
+    columns: RefCell<Columns>,
+    line: RefCell<String>,
 }
 
-impl Scanner {
+impl<'r> Scanner<'r> {
     pub fn new(source: String) -> Self {
-        Self { source, current: 0 }
+        Self::from_reader(Cursor::new(source))
     }
+
+    ///
+    /// 由带缓冲的读取器创建扫描器，扫描时逐行读取，不将整个源代码读入内存，
+    /// 适用于生成的大型脚本及通过网络传输的脚本
+    ///
+    /// # 参数
+    /// * reader: 源代码的读取器，如BufReader<File>
+    ///
+    pub fn from_reader(reader: impl BufRead + 'r) -> Self {
+        Self {
+            source: Box::new(reader),
+            current: 0,
+            lenient: false,
+            ignore_case: false,
+            warnings: RefCell::new(Vec::new()),
+            errors: RefCell::new(Vec::new()),
+            macros: HashMap::new(),
+            columns: RefCell::new(Columns::default()),
+            line: RefCell::new(String::new()),
+        }
+    }
+
+    ///
+    /// 设置宽松模式
+    /// 宽松模式下未知命令和格式错误的行作为警告收集并跳过，以便一次报告所有问题
+    ///
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    ///
+    /// 设置是否忽略关键字的大小写
+    /// 忽略时stage、Speak、match等与STAGE、SPEAK、MATCH相同，MAXTRIES 2 next human等子句中的关键字也一样；
+    /// SPEAK.<lang>中的语言代码及参数中的其余内容保持原样
+    ///
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.ignore_case = ignore_case;
+    }
+
+    ///
+    /// 预先定义宏，与脚本中的DEFINE相同，可用于在多个脚本间共享宏
+    ///
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.macros.insert(name.to_string(), value.to_string());
+    }
+
+    ///
+    /// 获取已定义的宏，包括扫描时遇到的DEFINE
+    ///
+    pub fn macros(&self) -> &HashMap<String, String> {
+        &self.macros
+    }
+
+    ///
+    /// 获取宽松模式下收集的警告
+    ///
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.borrow().clone()
+    }
+
+    ///
+    /// 获取扫描过程中产生的所有错误，按行号排列
+    ///
+    pub fn errors(&self) -> Vec<Diagnostic> {
+        self.errors.borrow().clone()
+    }
+
     ///
     /// scan input strings into commands
+    /// 出错的行不中断扫描，扫描完所有行后一并报告，以便一次修正脚本中的所有错误
+    /// 行尾(注释之前)为\时命令在下一行继续：去掉\及下一行开头的空白后拼接，命令的行数为第一行的行数
     /// ## 返回值
-    /// - 成功返回命令向量，失败返回错误
+    /// - 成功返回命令向量，有错误时返回第一个错误，所有错误可由errors获取；
+    ///   读取源代码失败(包括不是UTF-8编码)时返回Error::Io
     pub fn scan(&mut self) -> Result<Vec<Command>, Error> {
         let mut commands: Vec<Command> = Vec::new();
-        for line in self.source.lines() {
-            self.current += 1;
-            if let Some(command) = self.scan_line(line) {
-                match command {
-                    Ok(cmd) => commands.push(Command::new(cmd, self.current as i32)),
-                    Err(e) => return Err(e),
+        // 尚未结束的块注释开始的行数及该行的内容
+        let mut comment: Option<(usize, String)> = None;
+        // 尚未结束的续行开始的行数及已拼接的内容
+        let mut continued: Option<(usize, String)> = None;
+        let mut reader = std::mem::replace(&mut self.source, Box::new(io::empty()));
+        let mut buffer = String::new();
+        let mut index = 0;
+        loop {
+            buffer.clear();
+            if reader.read_line(&mut buffer)? == 0 {
+                break;
+            }
+            index += 1;
+            let line = buffer.strip_suffix('\n').unwrap_or(&buffer);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let mut in_comment = comment.is_some();
+            let code = strip_block_comments(line, &mut in_comment);
+            if !in_comment {
+                comment = None;
+            } else if comment.is_none() {
+                comment = Some((index, line.to_string()));
+            }
+            let (start, code) = match continued.take() {
+                Some((start, mut joined)) => {
+                    joined.push_str(code.trim_start());
+                    (start, joined)
                 }
+                None => (index, code),
+            };
+            if let Some(head) = continuation(&code) {
+                continued = Some((start, head.to_string()));
+                continue;
             }
+            // 续行的诊断信息显示拼接后的内容
+            let source = if start == index { line } else { &code };
+            self.scan_command(&mut commands, start, &code, source);
+        }
+        if let Some((start, joined)) = continued {
+            self.scan_command(&mut commands, start, &joined, &joined);
+        }
+        if let Some((current, line)) = comment {
+            let start = line.rfind("/*").unwrap_or_default();
+            self.current = current;
+            *self.line.borrow_mut() = line;
+            *self.columns.borrow_mut() = Columns {
+                keyword: start + 1..start + 3,
+                argument: start + 1..start + 3,
+            };
+            self.error("E004", "/*", "Unterminated block comment");
+        }
+        match self.errors.borrow().first() {
+            Some(error) => Err(Error::Scan(Box::new(error.clone()))),
+            None => Ok(commands),
         }
-        Ok(commands)
     }
 
-    fn scan_line(&self, line: &str) -> Option<Result<CommandType, Error>> {
-        let line = line.trim();
-        if line.is_empty() {
+    ///
+    /// 扫描一条命令，成功时加入命令向量；出错的行已记录为错误(宽松模式下为警告)，跳过后继续扫描
+    ///
+    /// # 参数
+    /// * commands: 命令向量
+    /// * line: 命令所在的行数
+    /// * code: 去除块注释后的命令
+    /// * source: 诊断信息中显示的源码
+    ///
+    fn scan_command(&mut self, commands: &mut Vec<Command>, line: usize, code: &str, source: &str) {
+        self.current = line;
+        *self.line.borrow_mut() = source.to_string();
+        let Ok(code) = self.expand_macros(code) else {
+            return;
+        };
+        if let Some(define) = self.scan_define(&code) {
+            if let Ok((name, value)) = define {
+                self.macros.insert(name, value);
+            }
+            return;
+        }
+        if let Some(Ok(cmd)) = self.scan_line(&code) {
+            let mut tokens = tokenize(&code).unwrap_or_default();
+            if self.ignore_case {
+                for token in &mut tokens {
+                    if token.kind == TokenKind::Identifier
+                        && is_keyword(&token.text.to_ascii_uppercase())
+                    {
+                        token.kind = TokenKind::Keyword;
+                    }
+                }
+            }
+            commands.push(
+                Command::new(cmd, self.current as i32)
+                    .with_columns(self.columns.borrow().clone())
+                    .with_tokens(tokens),
+            );
+        }
+    }
+
+    ///
+    /// 将行中的宏引用${NAME}替换为宏的内容
+    ///
+    /// # 返回值
+    /// * 引用了未定义的宏时返回词法错误，诊断信息指向第一个未定义的引用
+    ///
+    fn expand_macros<'a>(&self, code: &'a str) -> Result<Cow<'a, str>, Error> {
+        let undefined = MACRO_REFERENCE
+            .captures_iter(code)
+            .find(|caps| !self.macros.contains_key(&caps[1]));
+        if let Some(caps) = undefined {
+            let reference = caps.get(0).unwrap();
+            *self.columns.borrow_mut() = Columns {
+                keyword: reference.start() + 1..reference.end() + 1,
+                argument: reference.start() + 1..reference.end() + 1,
+            };
+            let message = format!("Undefined macro '{}'", &caps[1]);
+            return Err(self.error("E006", code.trim(), &message));
+        }
+        Ok(MACRO_REFERENCE
+            .replace_all(code, |caps: &regex::Captures| self.macros[&caps[1]].clone()))
+    }
+
+    ///
+    /// 扫描宏定义DEFINE <NAME> <value>，value为一个带引号的字符串时去掉两端的引号
+    ///
+    /// # 返回值
+    /// * 不是DEFINE时返回None，否则返回宏的名称及内容，格式错误或重复定义时返回词法错误
+    ///
+    fn scan_define(&self, code: &str) -> Option<Result<(String, String), Error>> {
+        let tokens = tokenize(code).ok()?;
+        let words = words(&tokens);
+        let (keyword, last) = (words.first()?, words.last()?);
+        if self.keyword(&code[keyword.clone()]) != "DEFINE" {
             return None;
         }
-        let re = Regex::new(r"\s+").unwrap(); // 正则表达式
-        let mut parts = re.splitn(line, 2);
-        let command = parts.next().unwrap();
-        let argument = parts.next().unwrap_or("");
+        *self.columns.borrow_mut() = columns(&words);
+        let line = &code[keyword.start..last.end];
+        let (name, value) = match &words[..] {
+            [_, name, first, ..] => (&code[name.clone()], &code[first.start..last.end]),
+            _ => {
+                let message = "Expected 'DEFINE <name> <value>'";
+                return Some(Err(self.error("E002", line, message)));
+            }
+        };
+        let name = match self.expect_name(line, name, &VARIABLE_NAME, "macro name") {
+            Ok(name) => name,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.macros.contains_key(&name) {
+            let message = format!("Macro '{}' already defined", name);
+            return Some(Err(self.error("E005", line, &message)));
+        }
+        // 值为一个字符串时去掉两端的引号
+        let quoted = tokens.last().filter(|token| {
+            token.kind == TokenKind::String
+                && token.span.start == words[2].start
+                && words.len() == 3
+        });
+        let value = match quoted {
+            Some(token) => &token.text[1..token.text.len() - 1],
+            None => value,
+        };
+        Some(Ok((name, value.to_string())))
+    }
+
+    fn scan_line(&self, line: &str) -> Option<Result<CommandType, Error>> {
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(quote) => {
+                let end = line.trim_end().len();
+                *self.columns.borrow_mut() = Columns {
+                    keyword: quote + 1..quote + 2,
+                    argument: quote + 1..end + 1,
+                };
+                return Some(Err(self.error("E003", line.trim(), "Unterminated string")));
+            }
+        };
+        // 空行及只有注释的行
+        let words = words(&tokens);
+        let (keyword, arguments) = words.split_first()?;
+        *self.columns.borrow_mut() = columns(&words);
+        let command = self.keyword(&line[keyword.clone()]);
+        let command = command.as_ref();
+        // 参数为关键字之后到注释之前的原文，保留其中的空白
+        let end = words.last().map_or(keyword.end, |word| word.end);
+        let argument = arguments
+            .first()
+            .map_or("", |first| &line[first.start..end]);
+        let normalized;
+        let argument = if self.ignore_case && CLAUSE_COMMANDS.contains(&command) {
+            normalized = uppercase_clause_keywords(argument);
+            normalized.as_str()
+        } else {
+            argument
+        };
+        let line = &line[keyword.start..end];
         // 同时加上判断argument是否为空的条件
         match command {
             "MATCH" => Some(Ok(CommandType::MATCH(argument.to_string()))),
-            "INPUT" => Some(Ok(CommandType::INPUT(argument.to_string()))),
-            "SPEAK" => Some(Ok(CommandType::SPEAK(argument.to_string()))),
-            "NEXT" => Some(Ok(CommandType::NEXT(argument.to_string()))),
-            "STAGE" => Some(Ok(CommandType::STAGE(argument.to_string()))),
+            "MATCH~" => Some(Ok(CommandType::FUZZYMATCH(argument.to_string()))),
+            "INTENT" => Some(Ok(CommandType::INTENT(argument.to_string()))),
+            "INPUT" => {
+                // INPUT <var> SECRET 为敏感输入
+                let (var, modifier) = argument
+                    .split_once(char::is_whitespace)
+                    .map_or((argument, ""), |(var, modifier)| (var, modifier.trim()));
+                Some(match modifier {
+                    "" => self
+                        .expect_name(line, var, &VARIABLE_NAME, "variable name")
+                        .map(CommandType::INPUT),
+                    "SECRET" => self
+                        .expect_name(line, var, &VARIABLE_NAME, "variable name")
+                        .map(CommandType::SECRETINPUT),
+                    _ => Err(self.error("E002", line, "Expected 'INPUT <variable> [SECRET]'")),
+                })
+            }
+            "SPEAK" => {
+                if argument.is_empty() {
+                    Some(Err(self.error("E002", line, "Expected text to speak")))
+                } else {
+                    Some(Ok(CommandType::SPEAK(argument.to_string())))
+                }
+            }
+            "NEXT" => Some(self.expect_target(line, argument).map(CommandType::NEXT)),
+            "STAGE" => Some(
+                self.expect_name(line, argument, &STAGE_NAME, "stage name")
+                    .map(CommandType::STAGE),
+            ),
             "DEFAULT" => {
                 if argument.is_empty() {
                     Some(Ok(CommandType::DEFAULT))
                 } else {
-                    Some(Err(self.error(line, "Unexpected argument")))
+                    Some(Err(self.error("E002", line, "Unexpected argument")))
+                }
+            }
+            "FORM" => {
+                if argument.is_empty() {
+                    Some(Ok(CommandType::FORM))
+                } else {
+                    Some(Err(self.error("E002", line, "Unexpected argument")))
+                }
+            }
+            "FIELD" => match FIELD_ARGUMENT.captures(argument) {
+                Some(caps) => Some(Ok(CommandType::FIELD(
+                    caps[1].to_string(),
+                    caps.get(2)
+                        .map(|validation| validation.as_str().to_string()),
+                ))),
+                None => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'FIELD <name> [validation]'",
+                ))),
+            },
+            "CONFIRM" => match CONFIRM_ARGUMENT.captures(argument) {
+                Some(caps) => Some(Ok(CommandType::CONFIRM(
+                    caps[1].to_string(),
+                    caps[2].to_string(),
+                ))),
+                None => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'CONFIRM NEXT <stage> ELSE <stage>'",
+                ))),
+            },
+            "GOTO" => Some(self.expect_target(line, argument).map(CommandType::GOTO)),
+            "CLEAR" => {
+                if argument.is_empty() {
+                    Some(Err(self.error(
+                        "E002",
+                        line,
+                        "Expected variable name or ALL",
+                    )))
+                } else {
+                    Some(Ok(CommandType::CLEAR(argument.to_string())))
+                }
+            }
+            "SAVE" | "LOAD" => {
+                let path = unquote(argument);
+                if path.is_empty() {
+                    Some(Err(self.error("E002", line, "Expected file path")))
+                } else if command == "SAVE" {
+                    Some(Ok(CommandType::SAVE(path)))
+                } else {
+                    Some(Ok(CommandType::LOAD(path)))
+                }
+            }
+            "TAG" => {
+                let tags: Vec<String> = argument
+                    .split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .collect();
+                if tags.iter().any(|tag| tag.is_empty()) {
+                    Some(Err(self.error(
+                        "E002",
+                        line,
+                        "Expected comma-separated tags",
+                    )))
+                } else {
+                    Some(Ok(CommandType::TAG(tags)))
+                }
+            }
+            "SWITCH" => Some(
+                self.expect_name(line, argument, &VARIABLE_NAME, "variable name")
+                    .map(CommandType::SWITCH),
+            ),
+            "CASE" => match CASE_ARGUMENT.captures(argument) {
+                Some(caps) => Some(Ok(CommandType::CASE(
+                    caps[1].to_string(),
+                    caps[2].to_string(),
+                ))),
+                None => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'CASE <value> NEXT <stage>'",
+                ))),
+            },
+            "PRIORITY" => match argument.parse::<u32>() {
+                Ok(priority) => Some(Ok(CommandType::PRIORITY(priority))),
+                Err(_) => Some(Err(self.error("E002", line, "Invalid priority"))),
+            },
+            "MAXTRIES" => match argument.split_whitespace().collect::<Vec<_>>()[..] {
+                [tries, "NEXT", next_stage] => match tries.parse::<u32>() {
+                    Ok(tries) if tries > 0 => {
+                        Some(Ok(CommandType::MAXTRIES(tries, next_stage.to_string())))
+                    }
+                    _ => Some(Err(self.error("E002", line, "Invalid retry count"))),
+                },
+                _ => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'MAXTRIES <n> NEXT <stage>'",
+                ))),
+            },
+            "TIMEOUT" => match argument.split_whitespace().collect::<Vec<_>>()[..] {
+                [seconds, "NEXT", next_stage] => match seconds.parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => {
+                        Some(Ok(CommandType::TIMEOUT(seconds, next_stage.to_string())))
+                    }
+                    _ => Some(Err(self.error("E002", line, "Invalid timeout"))),
+                },
+                _ => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'TIMEOUT <seconds> NEXT <stage>'",
+                ))),
+            },
+            "WHILE" => match WHILE_ARGUMENT.captures(argument) {
+                Some(caps) => match caps
+                    .get(3)
+                    .map(|max| max.as_str().parse::<u32>())
+                    .transpose()
+                {
+                    Ok(Some(0)) | Err(_) => {
+                        Some(Err(self.error("E002", line, "Invalid iteration cap")))
+                    }
+                    Ok(max) => Some(Ok(CommandType::WHILE(
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        max,
+                    ))),
+                },
+                None => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'WHILE <condition> NEXT <stage> [MAX <n>]'",
+                ))),
+            },
+            "NAME" => Some(Ok(CommandType::NAME(unquote(argument)))),
+            "VERSION" => Some(Ok(CommandType::VERSION(unquote(argument)))),
+            "AUTHOR" => Some(Ok(CommandType::AUTHOR(unquote(argument)))),
+            "ENTRY" => Some(Ok(CommandType::ENTRY(argument.to_string()))),
+            "ON_ERROR" => Some(Ok(CommandType::ONERROR(argument.to_string()))),
+            "PROMPT" => Some(Ok(CommandType::PROMPT(unquote(argument)))),
+            "ROBOT_PREFIX" => Some(Ok(CommandType::ROBOTPREFIX(unquote(argument)))),
+            "PATTERN" => match PATTERN_ARGUMENT.captures(argument) {
+                Some(caps) => Some(Ok(CommandType::PATTERN(
+                    caps[1].to_string(),
+                    caps[2].to_string(),
+                ))),
+                None => Some(Err(self.error(
+                    "E002",
+                    line,
+                    "Expected 'PATTERN @<name> <regex>'",
+                ))),
+            },
+            "MATCH_MODE" => match argument {
+                "EXACT" | "CONTAINS" => Some(Ok(CommandType::MATCHMODE(argument.to_string()))),
+                _ => Some(Err(self.error("E002", line, "Expected EXACT or CONTAINS"))),
+            },
+            "DSLVERSION" => match argument.parse::<u32>() {
+                Ok(version) if version > 0 => Some(Ok(CommandType::DSLVERSION(version))),
+                _ => Some(Err(self.error("E002", line, "Invalid DSL version number"))),
+            },
+            _ => match command.strip_prefix("SPEAK.") {
+                Some(lang) if !lang.is_empty() => Some(Ok(CommandType::SPEAKLOCALE(
+                    lang.to_string(),
+                    argument.to_string(),
+                ))),
+                _ => {
+                    // 关键字只是大小写不对时提示正确的写法
+                    let upper = command.to_uppercase();
+                    let hint = KEYWORDS.contains(&upper.as_str()).then(|| {
+                        format!(
+                            "keywords are case-sensitive; write {} or pass --ignore-case",
+                            upper
+                        )
+                    });
+                    Some(Err(self.keyword_error(
+                        "E001",
+                        line,
+                        "Unknown command",
+                        hint.as_deref(),
+                    )))
+                }
+            },
+        }
+    }
+
+    ///
+    /// 获取命令关键字，忽略大小写时转换为大写，SPEAK.<lang>中的语言代码保持原样
+    ///
+    fn keyword<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.ignore_case {
+            return Cow::Borrowed(text);
+        }
+        match text.split_once('.') {
+            Some((command, lang)) => Cow::Owned(format!("{}.{}", command.to_uppercase(), lang)),
+            None => Cow::Owned(text.to_uppercase()),
+        }
+    }
+
+    ///
+    /// 检查参数是否为合法的名称
+    ///
+    /// # 参数
+    /// * line: 当前行
+    /// * argument: 参数
+    /// * pattern: 名称需满足的正则表达式
+    /// * kind: 名称的种类，用于报错信息
+    ///
+    /// # 返回值
+    /// * 合法时返回名称，否则返回词法错误
+    ///
+    fn expect_name(
+        &self,
+        line: &str,
+        argument: &str,
+        pattern: &Regex,
+        kind: &str,
+    ) -> Result<String, Error> {
+        if argument.is_empty() {
+            return Err(self.error("E002", line, &format!("Expected {}", kind)));
+        }
+        if !pattern.is_match(argument) {
+            return Err(self.error("E002", line, &format!("Invalid {} '{}'", kind, argument)));
+        }
+        Ok(argument.to_string())
+    }
+
+    ///
+    /// 检查转移目标，目标为阶段名或按权重随机转移的阶段列表(由语法分析检查)
+    ///
+    fn expect_target(&self, line: &str, argument: &str) -> Result<String, Error> {
+        if argument.contains('%') {
+            Ok(argument.to_string())
+        } else {
+            self.expect_name(line, argument, &STAGE_NAME, "stage name")
+        }
+    }
+
+    ///
+    /// 报告参数有误的错误，诊断信息指向参数
+    ///
+    fn error(&self, code: &'static str, what_: &str, message: &str) -> Error {
+        let columns = self.columns.borrow().argument.clone();
+        self.report(code, columns, what_, message, None)
+    }
+
+    ///
+    /// 报告命令关键字有误的错误，诊断信息指向关键字
+    ///
+    fn keyword_error(
+        &self,
+        code: &'static str,
+        what_: &str,
+        message: &str,
+        hint: Option<&str>,
+    ) -> Error {
+        let columns = self.columns.borrow().keyword.clone();
+        self.report(code, columns, what_, message, hint)
+    }
+
+    fn report(
+        &self,
+        code: &'static str,
+        columns: Range<usize>,
+        what_: &str,
+        message: &str,
+        hint: Option<&str>,
+    ) -> Error {
+        // 宽松模式下跳过出错的行，诊断信息降为警告，使用警告代码W006
+        let (severity, code) = if self.lenient {
+            (Severity::Warning, "W006")
+        } else {
+            (Severity::Error, code)
+        };
+        let diagnostic = Diagnostic::new(severity, self.current as i32, what_, message)
+            .with_code(code)
+            .with_columns(columns)
+            .with_source(Some(self.line.borrow().as_str()).filter(|line| !line.is_empty()))
+            .with_hint(hint)
+            .emit();
+        if self.lenient {
+            self.warnings.borrow_mut().push(diagnostic.to_string());
+        } else {
+            self.errors.borrow_mut().push(diagnostic.clone());
+        }
+        Error::Scan(Box::new(diagnostic))
+    }
+}
+
+///
+/// 将行中块注释(/* ... */)的内容替换为等长的空白，使其余内容的列位置不变
+/// 双引号包裹的字符串及#开始的行尾注释中的/*不开始块注释
+///
+/// # 参数
+/// * line: 一行源码
+/// * in_comment: 行首是否处于块注释中，返回时更新为行尾是否仍处于块注释中
+///
+/// # 返回值
+/// * 去除块注释后的行
+///
+fn strip_block_comments(line: &str, in_comment: &mut bool) -> String {
+    let mut code = String::with_capacity(line.len());
+    let mut quoted = false;
+    let mut chars = line.char_indices();
+    while let Some((index, c)) = chars.next() {
+        let rest = &line[index..];
+        if *in_comment {
+            if rest.starts_with("*/") {
+                chars.next();
+                code.push_str("  ");
+                *in_comment = false;
+            } else {
+                code.extend(std::iter::repeat_n(' ', c.len_utf8()));
+            }
+            continue;
+        }
+        if quoted {
+            if c == '\\' {
+                code.push(c);
+                if let Some((_, c)) = chars.next() {
+                    code.push(c);
+                }
+                continue;
+            }
+            quoted = c != '"';
+        } else if c == '"' {
+            quoted = true;
+        } else if c == '#' && code.chars().last().is_none_or(char::is_whitespace) {
+            code.push_str(rest);
+            break;
+        } else if rest.starts_with("/*") {
+            chars.next();
+            code.push_str("  ");
+            *in_comment = true;
+            continue;
+        }
+        code.push(c);
+    }
+    code
+}
+
+///
+/// 不是命令关键字或子句关键字的保留字
+///
+const RESERVED_WORDS: &[&str] = &["EMPTY", "NOT", "ALL"];
+
+///
+/// 由两个字符组成的运算符
+///
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">="];
+
+///
+/// 判断字符是否为运算符或标点
+///
+fn is_operator(c: char) -> bool {
+    "+-*/%=!<>,()[]{}|&\\~:;@?^$.'".contains(c)
+}
+
+///
+/// 判断字符是否可以组成标识符
+///
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+///
+/// 判断词是否为关键字
+///
+fn is_keyword(text: &str) -> bool {
+    KEYWORDS.contains(&text)
+        || CLAUSE_KEYWORDS.contains(&text)
+        || RESERVED_WORDS.contains(&text)
+        || text.starts_with("SPEAK.")
+}
+
+///
+/// 将一行拆分为词，空白不属于任何词
+/// - 双引号包裹的字符串(其中可用\"转义引号)为一个词，保留其中的空白
+/// - 空白之后以/开头且后面还有/的部分为正则表达式/pattern/flags，保留其中的空白
+/// - 数字开头的为数字，字母、汉字或@开头的为标识符，其中可包含.和-，如SPEAK.en、order-status
+/// - 运算符为==、!=、<=、>=及单个标点
+/// - 行首或空白之后以#开头的部分为注释，不产生词
+///
+/// # 参数
+/// * line: 一行源码
+///
+/// # 返回值
+/// * 成功返回各词，字符串没有结束的引号时返回Err(起始引号的字节位置)
+///
+pub fn tokenize(line: &str) -> Result<Vec<Token>, usize> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    // 上一个字符是否为空白(行首视为空白)
+    let mut spaced = true;
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            spaced = true;
+            continue;
+        }
+        if c == '#' && spaced {
+            break;
+        }
+        let rest = &line[start + c.len_utf8()..];
+        let mut end = start + c.len_utf8();
+        let kind = if c == '"' || (c == '/' && spaced && rest.contains('/')) {
+            // 读到配对的分隔符为止，\转义下一个字符
+            let mut closed = false;
+            while let Some((index, next)) = chars.next() {
+                end = index + next.len_utf8();
+                if next == '\\' {
+                    if let Some((index, escaped)) = chars.next() {
+                        end = index + escaped.len_utf8();
+                    }
+                } else if next == c {
+                    closed = true;
+                    break;
+                }
+            }
+            if c == '"' {
+                if !closed {
+                    return Err(start);
                 }
+                TokenKind::String
+            } else {
+                // 正则表达式的标志
+                while let Some((index, flag)) = chars.next_if(|&(_, flag)| is_word(flag)) {
+                    end = index + flag.len_utf8();
+                }
+                TokenKind::Regex
+            }
+        } else if c.is_ascii_digit() {
+            while let Some((index, digit)) = chars.next_if(|&(_, digit)| digit.is_ascii_digit()) {
+                end = index + digit.len_utf8();
+            }
+            let mut fraction = line[end..].chars();
+            if fraction.next() == Some('.') && fraction.next().is_some_and(|c| c.is_ascii_digit()) {
+                chars.next();
+                while let Some((index, digit)) = chars.next_if(|&(_, digit)| digit.is_ascii_digit())
+                {
+                    end = index + digit.len_utf8();
+                }
+            }
+            TokenKind::Number
+        } else if is_word(c) || c == '@' {
+            loop {
+                let mut next = line[end..].chars();
+                match (next.next(), next.next()) {
+                    (Some(c), _) if is_word(c) => {}
+                    // .和-后面还有字母时属于标识符，如SPEAK.en
+                    (Some('.' | '-'), Some(c)) if is_word(c) => {}
+                    _ => break,
+                }
+                let (index, c) = chars.next().unwrap();
+                end = index + c.len_utf8();
             }
-            _ => Some(Err(self.error(line, "Unknown command"))),
+            if line[start..end].eq_ignore_ascii_case("MATCH") && line[end..].starts_with('~') {
+                chars.next();
+                end += 1;
+            }
+            if is_keyword(&line[start..end]) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Identifier
+            }
+        } else if is_operator(c) {
+            if OPERATORS
+                .iter()
+                .any(|operator| line[start..].starts_with(operator))
+            {
+                let (index, c) = chars.next().unwrap();
+                end = index + c.len_utf8();
+            }
+            TokenKind::Operator
+        } else {
+            while let Some((index, c)) = chars
+                .next_if(|&(_, c)| !c.is_whitespace() && !is_word(c) && !is_operator(c) && c != '"')
+            {
+                end = index + c.len_utf8();
+            }
+            TokenKind::Text
+        };
+        spaced = false;
+        tokens.push(Token {
+            kind,
+            text: line[start..end].to_string(),
+            span: start..end,
+        });
+    }
+    Ok(tokens)
+}
+
+///
+/// 将相邻的词合并为以空白分隔的单词，返回各单词的字节范围
+///
+fn words(tokens: &[Token]) -> Vec<Range<usize>> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    for token in tokens {
+        match words.last_mut() {
+            Some(word) if word.end == token.span.start => word.end = token.span.end,
+            _ => words.push(token.span.clone()),
         }
     }
+    words
+}
 
-    fn error(&self, what_: &str, message: &str) -> Error {
-        error(self.current as i32, what_, message);
-        Error::Scan
+///
+/// 判断命令是否在下一行继续
+///
+/// # 返回值
+/// * 行尾(注释之前)为\时返回去掉\及注释后的内容，否则返回None
+///
+fn continuation(code: &str) -> Option<&str> {
+    let end = match tokenize(code) {
+        Ok(tokens) => tokens.last()?.span.end,
+        // 字符串可以在下一行继续
+        Err(_) => code.trim_end().len(),
+    };
+    code[..end].strip_suffix('\\')
+}
+
+///
+/// 将参数中引号外的子句关键字(NEXT、ELSE等)转换为大写，其余内容保持原样
+///
+fn uppercase_clause_keywords(argument: &str) -> String {
+    let mut normalized = argument.to_string();
+    for token in tokenize(argument).unwrap_or_default() {
+        if CLAUSE_KEYWORDS
+            .iter()
+            .any(|keyword| keyword.eq_ignore_ascii_case(&token.text))
+        {
+            normalized.replace_range(token.span, &token.text.to_ascii_uppercase());
+        }
     }
+    normalized
+}
+
+///
+/// 计算一行中命令关键字及参数的列范围，参数从关键字后的第一个词到注释之前
+///
+fn columns(words: &[Range<usize>]) -> Columns {
+    let Some((keyword, arguments)) = words.split_first() else {
+        return Columns::default();
+    };
+    let argument = match (arguments.first(), arguments.last()) {
+        (Some(first), Some(last)) => first.start + 1..last.end + 1,
+        _ => keyword.end + 1..keyword.end + 1,
+    };
+    Columns {
+        keyword: keyword.start + 1..keyword.end + 1,
+        argument,
+    }
+}
+
+///
+/// 去除参数两端的双引号
+///
+fn unquote(argument: &str) -> String {
+    argument.trim_matches('"').to_string()
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::match_like_matches_macro)]
 mod scanner_tests {
     use super::*;
 
@@ -118,7 +1053,7 @@ mod scanner_tests {
         let placeholder = String::new();
         let scanr = Scanner::new(placeholder);
         println!();
-        let ans = if let Some(Err(Error::Scan)) = scanr.scan_line("DEFAULT shouldn't be here") {
+        let ans = if let Some(Err(Error::Scan(_))) = scanr.scan_line("DEFAULT shouldn't be here") {
             true
         } else {
             false
@@ -131,7 +1066,7 @@ mod scanner_tests {
         let placeholder = String::new();
         let scanr = Scanner::new(placeholder);
         println!();
-        let ans = if let Some(Err(Error::Scan)) = scanr.scan_line("COMMAND THAT WE DON'T KNOW") {
+        let ans = if let Some(Err(Error::Scan(_))) = scanr.scan_line("COMMAND THAT WE DON'T KNOW") {
             true
         } else {
             false
@@ -167,6 +1102,18 @@ mod scanner_tests {
         assert!(cmds[5].line == 7);
     }
 
+    #[test]
+    fn test_scan_columns() {
+        let source = "STAGE initial\n    SPEAK   \"你好\"\nDEFAULT\n";
+        let cmds = Scanner::new(source.to_string()).scan().unwrap();
+        assert_eq!(cmds[0].columns.keyword, 1..6);
+        assert_eq!(cmds[0].columns.argument, 7..14);
+        assert_eq!(cmds[1].columns.keyword, 5..10);
+        assert_eq!(cmds[1].columns.argument, 13..21);
+        assert_eq!(cmds[2].columns.argument, 8..8);
+        assert!(!Command::new(CommandType::DEFAULT, 1).columns.is_known());
+    }
+
     #[test]
     fn test_scan_to_error() {
         let source = r#"
@@ -185,4 +1132,576 @@ mod scanner_tests {
         }
         assert_eq!(cmds.is_err(), true);
     }
+
+    #[test]
+    fn test_scan_line_max_tries() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("MAXTRIES 3 NEXT human_handoff"),
+            Some(Ok(CommandType::MAXTRIES(3, s))) if s == "human_handoff"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("MAXTRIES 0 NEXT handoff"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("MAXTRIES 3 handoff"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_prompt() {
+        let scanr = Scanner::new(String::new());
+        assert!(matches!(
+            scanr.scan_line("PROMPT \"你> \""),
+            Some(Ok(CommandType::PROMPT(s))) if s == "你> "
+        ));
+        assert!(matches!(
+            scanr.scan_line("ROBOT_PREFIX \"🤖 \""),
+            Some(Ok(CommandType::ROBOTPREFIX(s))) if s == "🤖 "
+        ));
+    }
+
+    #[test]
+    fn test_scan_line_timeout() {
+        let scanr = Scanner::new(String::new());
+        assert!(matches!(
+            scanr.scan_line("TIMEOUT 30 NEXT silence"),
+            Some(Ok(CommandType::TIMEOUT(30, s))) if s == "silence"
+        ));
+        assert!(matches!(
+            scanr.scan_line("TIMEOUT 0 NEXT silence"),
+            Some(Err(Error::Scan(_)))
+        ));
+        assert!(matches!(
+            scanr.scan_line("TIMEOUT soon NEXT silence"),
+            Some(Err(Error::Scan(_)))
+        ));
+        assert!(matches!(
+            scanr.scan_line("TIMEOUT 30"),
+            Some(Err(Error::Scan(_)))
+        ));
+    }
+
+    #[test]
+    fn test_scan_line_speak_locale() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("SPEAK.en \"Hello\" + name"),
+            Some(Ok(CommandType::SPEAKLOCALE(lang, s))) if lang == "en" && s == "\"Hello\" + name"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("SPEAK. \"Hello\""),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_case() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("CASE \"tech support\" NEXT tech"),
+            Some(Ok(CommandType::CASE(v, s))) if v == "\"tech support\"" && s == "tech"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(scanr.scan_line("CASE billing"), Some(Err(Error::Scan(_))));
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("CASE NEXT billing"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("CASE a NEXT NEXTSTAGE"),
+            Some(Ok(CommandType::CASE(v, s))) if v == "a" && s == "NEXTSTAGE"
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_while() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("WHILE more == \"y\" NEXT add_item MAX 5"),
+            Some(Ok(CommandType::WHILE(c, s, Some(5)))) if c == "more == \"y\"" && s == "add_item"
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("WHILE count < 3 NEXT add_item"),
+            Some(Ok(CommandType::WHILE(c, s, None))) if c == "count < 3" && s == "add_item"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("WHILE count < 3 NEXT add_item MAX 0"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("WHILE count < 3"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_match_mode() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("MATCH_MODE CONTAINS"),
+            Some(Ok(CommandType::MATCHMODE(s))) if s == "CONTAINS"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("MATCH_MODE partial"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_tag() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("TAG billing, escalation"),
+            Some(Ok(CommandType::TAG(tags))) if tags == ["billing", "escalation"]
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(scanr.scan_line("TAG"), Some(Err(Error::Scan(_))));
+        assert!(ans);
+        let ans = matches!(scanr.scan_line("TAG billing,,"), Some(Err(Error::Scan(_))));
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_form() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(scanr.scan_line("FORM"), Some(Ok(CommandType::FORM)));
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("FIELD phone \"\\d{11}\""),
+            Some(Ok(CommandType::FIELD(name, Some(v)))) if name == "phone" && v == "\"\\d{11}\""
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("FIELD name"),
+            Some(Ok(CommandType::FIELD(name, None))) if name == "name"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(scanr.scan_line("FIELD"), Some(Err(Error::Scan(_))));
+        assert!(ans);
+        let ans = matches!(scanr.scan_line("FORM name"), Some(Err(Error::Scan(_))));
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_confirm() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("CONFIRM NEXT pay ELSE cart"),
+            Some(Ok(CommandType::CONFIRM(y, n))) if y == "pay" && n == "cart"
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("CONFIRM NEXT pay"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_pattern() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("PATTERN @order_id \"[A-Z]{2}\\d{6}\""),
+            Some(Ok(CommandType::PATTERN(name, s))) if name == "order_id" && s == "\"[A-Z]{2}\\d{6}\""
+        );
+        assert!(ans);
+        println!();
+        let ans = matches!(
+            scanr.scan_line("PATTERN order_id \\d+"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_secret_input() {
+        let scanr = Scanner::new(String::new());
+        let ans = matches!(
+            scanr.scan_line("INPUT password SECRET"),
+            Some(Ok(CommandType::SECRETINPUT(s))) if s == "password"
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("INPUT pass-word SECRET"),
+            Some(Err(Error::Scan(_)))
+        );
+        assert!(ans);
+    }
+
+    #[test]
+    fn test_scan_line_switch() {
+        let scanr = Scanner::new(String::new());
+        let ans = matches!(
+            scanr.scan_line("SWITCH category"),
+            Some(Ok(CommandType::SWITCH(var))) if var == "category"
+        );
+        assert!(ans);
+        println!();
+        // 缺少变量名或变量名中有空格时在扫描时报告
+        for line in ["SWITCH", "SWITCH lang code"] {
+            let ans = matches!(scanr.scan_line(line), Some(Err(Error::Scan(_))));
+            assert!(ans, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_scan_line_argument_validation() {
+        let placeholder = String::new();
+        let scanr = Scanner::new(placeholder);
+        let ans = matches!(
+            scanr.scan_line("STAGE get-name"),
+            Some(Ok(CommandType::STAGE(s))) if s == "get-name"
+        );
+        assert!(ans);
+        let ans = matches!(
+            scanr.scan_line("NEXT 70% a, 30% b"),
+            Some(Ok(CommandType::NEXT(s))) if s == "70% a, 30% b"
+        );
+        assert!(ans);
+        println!();
+        for line in [
+            "STAGE",
+            "STAGE two words",
+            "NEXT",
+            "NEXT two words",
+            "GOTO",
+            "INPUT",
+            "INPUT user-name",
+            "INPUT password HIDDEN",
+            "SPEAK",
+        ] {
+            let ans = matches!(scanr.scan_line(line), Some(Err(Error::Scan(_))));
+            assert!(ans, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_scan_lenient() {
+        let source = r#"
+            STAGE initial
+            SPEAK "hello"
+            UNKNOWN command
+            MAXTRIES 0 NEXT handoff
+            GOTO EXIT
+        "#;
+        println!();
+        let mut scanr = Scanner::new(source.to_string());
+        scanr.set_lenient(true);
+        let cmds = scanr.scan().unwrap();
+        assert_eq!(cmds.len(), 3);
+        assert!(cmds[2].ctype == CommandType::GOTO("EXIT".to_string()));
+        let warnings = scanr.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("[line 4] Warning[W006] (UNKNOWN command): "));
+        assert!(warnings[1].starts_with("[line 5] Warning[W006] "));
+        // 警告与错误一样带有出错的行及列标记
+        assert!(warnings[0].ends_with("\n4 |             UNKNOWN command\n  |             ^^^^^^^"));
+        assert!(warnings[1].contains("\n5 |             MAXTRIES 0 NEXT handoff\n"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let texts = |line: &str| -> Vec<String> {
+            tokenize(line)
+                .unwrap()
+                .into_iter()
+                .map(|token| token.text)
+                .collect()
+        };
+        assert_eq!(
+            texts(r#"  SPEAK "你好，  欢迎" + name # 问候"#),
+            vec!["SPEAK", "\"你好，  欢迎\"", "+", "name"]
+        );
+        assert_eq!(
+            texts(r#"MATCH "say \"hi there\"""#),
+            vec!["MATCH", r#""say \"hi there\"""#]
+        );
+        assert_eq!(
+            texts("MATCH /a b # comment/x NEXT c#"),
+            vec!["MATCH", "/a b # comment/x", "NEXT", "c", "#"]
+        );
+        assert!(texts("# 只有注释").is_empty());
+        let kinds = |line: &str| -> Vec<TokenKind> {
+            tokenize(line)
+                .unwrap()
+                .into_iter()
+                .map(|token| token.kind)
+                .collect()
+        };
+        assert_eq!(
+            kinds("WHILE count >= 3 NEXT loop MAX 5"),
+            vec![
+                TokenKind::Keyword,
+                TokenKind::Identifier,
+                TokenKind::Operator,
+                TokenKind::Number,
+                TokenKind::Keyword,
+                TokenKind::Identifier,
+                TokenKind::Keyword,
+                TokenKind::Number
+            ]
+        );
+        assert_eq!(
+            kinds(r#"SPEAK.en "Hi " + name"#),
+            vec![
+                TokenKind::Keyword,
+                TokenKind::String,
+                TokenKind::Operator,
+                TokenKind::Identifier
+            ]
+        );
+        assert_eq!(
+            kinds("MATCH~ /^\\d+$/i NEXT order-status"),
+            vec![
+                TokenKind::Keyword,
+                TokenKind::Regex,
+                TokenKind::Keyword,
+                TokenKind::Identifier
+            ]
+        );
+        assert_eq!(
+            kinds("SPEAK total = price * 1.5"),
+            vec![
+                TokenKind::Keyword,
+                TokenKind::Identifier,
+                TokenKind::Operator,
+                TokenKind::Identifier,
+                TokenKind::Operator,
+                TokenKind::Number
+            ]
+        );
+        assert_eq!(texts("SPEAK total = price*1.5")[3..], ["price", "*", "1.5"]);
+        assert_eq!(tokenize("SPEAK \"你好").unwrap_err(), 6);
+    }
+
+    #[test]
+    fn test_scan_comments_and_quotes() {
+        let source = "# 问候\nSTAGE initial # 入口\nSPEAK \"a  # b\"   # 输出\nGOTO EXIT\n";
+        let cmds = Scanner::new(source.to_string()).scan().unwrap();
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(cmds[0].ctype, CommandType::STAGE("initial".to_string()));
+        assert_eq!(cmds[1].ctype, CommandType::SPEAK("\"a  # b\"".to_string()));
+        assert_eq!(cmds[1].columns.argument, 7..15);
+        assert_eq!(cmds[1].tokens.len(), 2);
+        assert_eq!(cmds[1].tokens[1].kind, TokenKind::String);
+        println!();
+        let mut scanr = Scanner::new("SPEAK \"你好\n".to_string());
+        assert!(scanr.scan().is_err());
+        let errors = scanr.errors();
+        assert_eq!(errors[0].code, Some("E003"));
+        assert_eq!(errors[0].columns, Some(7..14));
+    }
+
+    #[test]
+    fn test_scan_block_comments() {
+        let source = r#"
+            STAGE initial /* 入口 */ # 行尾注释
+            SPEAK "/* 不是注释 */"
+            /* 暂时停用
+            MATCH "退款"
+            NEXT refund
+            */ DEFAULT
+            NEXT EXIT
+        "#;
+        let cmds = Scanner::new(source.to_string()).scan().unwrap();
+        let types: Vec<CommandType> = cmds.into_iter().map(|cmd| cmd.ctype).collect();
+        assert_eq!(
+            types,
+            vec![
+                CommandType::STAGE("initial".to_string()),
+                CommandType::SPEAK("\"/* 不是注释 */\"".to_string()),
+                CommandType::DEFAULT,
+                CommandType::NEXT("EXIT".to_string()),
+            ]
+        );
+        let cmds = Scanner::new("SPEAK /* 你 */ \"好\"".to_string())
+            .scan()
+            .unwrap();
+        assert_eq!(cmds[0].columns.argument, 17..22);
+        println!();
+        let mut scanr = Scanner::new("STAGE a\n/* 未结束\nSPEAK \"b\"\n".to_string());
+        assert!(scanr.scan().is_err());
+        let errors = scanr.errors();
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].code, Some("E004"));
+    }
+
+    #[test]
+    fn test_scan_ignore_case() {
+        let source =
+            "stage initial\nSpeak.en \"Next\"\nmaxtries 2 next human\nconfirm next a else b\n";
+        println!();
+        let mut scanr = Scanner::new(source.to_string());
+        assert!(scanr.scan().is_err());
+        let errors = scanr.errors();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(
+            errors[0].hint.as_deref(),
+            Some("keywords are case-sensitive; write STAGE or pass --ignore-case")
+        );
+        let mut scanr = Scanner::new(source.to_string());
+        scanr.set_ignore_case(true);
+        let types: Vec<CommandType> = scanr
+            .scan()
+            .unwrap()
+            .into_iter()
+            .map(|cmd| cmd.ctype)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                CommandType::STAGE("initial".to_string()),
+                CommandType::SPEAKLOCALE("en".to_string(), "\"Next\"".to_string()),
+                CommandType::MAXTRIES(2, "human".to_string()),
+                CommandType::CONFIRM("a".to_string(), "b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_line_continuation() {
+        let source = r#"
+            STAGE initial
+            SPEAK "您好，" + \
+                  name + "，欢迎\
+                  致电" # 问候
+            NEXT 70% a, \ # 续行
+                 30% b
+        "#;
+        let cmds = Scanner::new(source.to_string()).scan().unwrap();
+        assert_eq!(
+            cmds[1].ctype,
+            CommandType::SPEAK(r#""您好，" + name + "，欢迎致电""#.to_string())
+        );
+        assert_eq!(cmds[1].line, 3);
+        assert_eq!(cmds[2].ctype, CommandType::NEXT("70% a, 30% b".to_string()));
+        assert_eq!(cmds[2].line, 6);
+        println!();
+        let mut scanr =
+            Scanner::new("STAGE a\nSPEAK \\\n \"b\"\nMAXTRIES 0 \\\n NEXT b\n".to_string());
+        assert!(scanr.scan().is_err());
+        let errors = scanr.errors();
+        assert_eq!(errors[0].line, 4);
+        assert_eq!(errors[0].message, "Invalid retry count");
+    }
+
+    #[test]
+    fn test_scan_define() {
+        let source = r#"
+            DEFINE GREETING "您好，欢迎致电"
+            DEFINE ORDER 订单\d+
+            DEFINE HOTLINE "${GREETING}客服热线"
+            STAGE initial
+            SPEAK "${HOTLINE}" + name
+            MATCH ".*${ORDER}.*"
+            NEXT EXIT
+        "#;
+        let mut scanr = Scanner::new(source.to_string());
+        let cmds = scanr.scan().unwrap();
+        assert_eq!(cmds.len(), 4);
+        assert_eq!(
+            cmds[1].ctype,
+            CommandType::SPEAK(r#""您好，欢迎致电客服热线" + name"#.to_string())
+        );
+        assert_eq!(
+            cmds[2].ctype,
+            CommandType::MATCH(r#"".*订单\d+.*""#.to_string())
+        );
+        assert_eq!(scanr.macros()["ORDER"], r"订单\d+");
+        println!();
+        let mut scanr = Scanner::new("DEFINE A 1\nDEFINE A 2\nDEFINE B\n".to_string());
+        assert!(scanr.scan().is_err());
+        let codes: Vec<_> = scanr.errors().iter().map(|error| error.code).collect();
+        assert_eq!(codes, vec![Some("E005"), Some("E002")]);
+    }
+
+    #[test]
+    fn test_scan_undefined_macro() {
+        let source =
+            "DEFINE ORDER 订单\\d+\nSTAGE initial\nMATCH \"${ORDER}${UNKNOWN}\"\nNEXT EXIT\n";
+        let mut scanr = Scanner::new(source.to_string());
+        assert!(scanr.scan().is_err());
+        let errors = scanr.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, Some("E006"));
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[0].message, "Undefined macro 'UNKNOWN'");
+        assert_eq!(errors[0].columns, Some(16..26));
+        // 宽松模式下跳过该行，其余命令照常扫描
+        let mut scanr = Scanner::new(source.to_string());
+        scanr.set_lenient(true);
+        assert_eq!(scanr.scan().unwrap().len(), 2);
+        assert!(scanr.warnings()[0].starts_with("[line 3] Warning[W006] "));
+    }
+
+    #[test]
+    fn test_scan_from_reader() {
+        let source = "STAGE initial\r\nSPEAK \"你好\" + \\\r\n  name\r\nGOTO EXIT";
+        let reader = io::BufReader::with_capacity(4, source.as_bytes());
+        let cmds = Scanner::from_reader(reader).scan().unwrap();
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(
+            cmds[1].ctype,
+            CommandType::SPEAK("\"你好\" + name".to_string())
+        );
+        assert_eq!(cmds[2].line, 4);
+        let invalid: &[u8] = b"STAGE initial\n\xff\n";
+        assert!(matches!(
+            Scanner::from_reader(invalid).scan(),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_scan_collects_errors() {
+        let source = r#"
+            STAGE initial
+            SPEAK "hello"
+            UNKNOWN command
+            MAXTRIES 0 NEXT handoff
+            GOTO EXIT
+        "#;
+        println!();
+        let mut scanr = Scanner::new(source.to_string());
+        let error = scanr.scan().unwrap_err();
+        assert_eq!(error.diagnostic().unwrap().line, 4);
+        let errors = scanr.errors();
+        let lines: Vec<i32> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![4, 5]);
+        assert_eq!(errors[0].code, Some("E001"));
+    }
 }
@@ -0,0 +1,150 @@
+use crate::env::GlobalEnvironment;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, SessionState, StepResult};
+use crate::parser::{DSLParser, InputTimeout, Stages};
+use std::sync::Arc;
+use std::time::Duration;
+
+///
+/// 解析完成的脚本，可在多个会话之间共享
+/// - stages: 共享的DFA状态迁移表
+/// - on_error: 错误处理阶段
+/// - timeout: 全局的等待输入超时设置
+///
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub stages: Arc<Stages>,
+    pub on_error: Option<String>,
+    pub timeout: Option<InputTimeout>,
+}
+
+impl Script {
+    ///
+    /// 由解析完成的DSLParser生成脚本
+    ///
+    pub fn new(parser: DSLParser) -> Self {
+        Self {
+            on_error: parser.on_error,
+            timeout: parser.timeout,
+            stages: Arc::new(parser.stages),
+        }
+    }
+
+    ///
+    /// 创建一个从入口阶段开始的新会话
+    /// 会话之间共享DFA状态迁移表，但各自拥有独立的环境变量和当前阶段
+    ///
+    pub fn session(&self) -> Session {
+        let mut interpreter = Interpreter::builder()
+            .on_error(self.on_error.clone())
+            .input_timeout(self.timeout.clone())
+            .build();
+        interpreter.load_shared(Arc::clone(&self.stages));
+        Session { interpreter }
+    }
+}
+
+///
+/// 单个对话会话，由调用者通过step驱动
+///
+pub struct Session {
+    interpreter: Interpreter,
+}
+
+impl Session {
+    ///
+    /// 单步解释，参见Interpreter::step
+    ///
+    pub fn step(&mut self, input: Option<&str>) -> Result<StepResult, Error> {
+        self.interpreter.step(input)
+    }
+
+    ///
+    /// 通知等待输入超时，参见Interpreter::step_timeout
+    ///
+    pub fn step_timeout(&mut self) -> Result<StepResult, Error> {
+        self.interpreter.step_timeout()
+    }
+
+    ///
+    /// 通知输入已结束，参见Interpreter::step_end
+    ///
+    pub fn step_end(&mut self) -> Result<StepResult, Error> {
+        self.interpreter.step_end()
+    }
+
+    ///
+    /// 获取等待输入的超时时间，参见Interpreter::pending_timeout
+    ///
+    pub fn pending_timeout(&self) -> Option<Duration> {
+        self.interpreter.pending_timeout()
+    }
+
+    ///
+    /// 获取会话的环境变量
+    ///
+    pub fn env(&self) -> &GlobalEnvironment {
+        &self.interpreter.global_env
+    }
+
+    ///
+    /// 保存会话状态
+    ///
+    pub fn snapshot(&self) -> SessionState {
+        self.interpreter.snapshot()
+    }
+
+    ///
+    /// 恢复会话状态
+    ///
+    pub fn restore(&mut self, state: SessionState) {
+        self.interpreter.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_sessions_share_script() {
+        let commands = Scanner::new(
+            r#"
+            ENTRY ask
+            STAGE ask
+            SPEAK "你叫什么名字"
+            INPUT name
+            NEXT greet
+            STAGE greet
+            SPEAK "你好, " + name
+            GOTO EXIT
+            "#
+            .to_string(),
+        )
+        .scan()
+        .unwrap();
+        let mut parser = DSLParser::new();
+        parser.parse(commands).unwrap();
+        let script = Script::new(parser);
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let mut session = script.session();
+                scope.spawn(move || {
+                    let name = format!("user{}", i);
+                    session.step(None).unwrap();
+                    session.step(None).unwrap();
+                    assert_eq!(
+                        session.step(Some(&name)).unwrap(),
+                        StepResult::Speak(format!("你好, {}", name))
+                    );
+                    assert_eq!(session.step(None).unwrap(), StepResult::Finished);
+                    assert_eq!(session.env().get("name").unwrap().stringify(), name);
+                });
+            }
+        });
+        // 会话结束后只剩脚本持有状态迁移表
+        assert_eq!(Arc::strong_count(&script.stages), 1);
+    }
+}
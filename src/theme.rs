@@ -0,0 +1,117 @@
+use crossterm::style::{Attribute, Color, ContentStyle, Stylize};
+
+///
+/// 终端输出的配色方案
+/// - robot: 机器人输出(SPEAK、确认提示语等)的样式
+/// - prompt: 输入提示符的样式
+///
+/// 默认不设置任何样式，输出与普通文本相同
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Theme {
+    pub robot: ContentStyle,
+    pub prompt: ContentStyle,
+}
+
+impl Theme {
+    ///
+    /// 不设置任何样式的配色方案，用于不支持颜色的终端或输出被重定向时
+    ///
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// 默认的彩色配色方案: 机器人输出为青色，提示符为粗体绿色
+    ///
+    pub fn colored() -> Self {
+        Self {
+            robot: ContentStyle::new().cyan(),
+            prompt: ContentStyle::new().green().bold(),
+        }
+    }
+
+    ///
+    /// 按机器人输出的样式格式化文本
+    ///
+    pub fn robot(&self, text: &str) -> String {
+        apply(self.robot, text)
+    }
+
+    ///
+    /// 按提示符的样式格式化文本
+    ///
+    pub fn prompt(&self, text: &str) -> String {
+        apply(self.prompt, text)
+    }
+}
+
+///
+/// 按样式格式化文本，文本为空或没有设置样式时原样返回，不输出多余的控制序列
+///
+fn apply(style: ContentStyle, text: &str) -> String {
+    if text.is_empty() || style == ContentStyle::default() {
+        text.to_string()
+    } else {
+        style.apply(text).to_string()
+    }
+}
+
+///
+/// 解析样式描述，描述由逗号分隔的颜色名(如cyan、dark_grey)及bold、italic、underline组成
+///
+/// # 参数
+/// * spec: 样式描述，如"bold,cyan"
+///
+/// # 返回值
+/// * 成功返回样式，含有无法识别的项时返回Err(该项)
+///
+pub fn parse_style(spec: &str) -> Result<ContentStyle, String> {
+    let mut style = ContentStyle::new();
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        style = match item {
+            "bold" => style.attribute(Attribute::Bold),
+            "italic" => style.attribute(Attribute::Italic),
+            "underline" => style.attribute(Attribute::Underlined),
+            color => match Color::try_from(color) {
+                Ok(color) => style.with(color),
+                Err(()) => return Err(item.to_string()),
+            },
+        };
+    }
+    Ok(style)
+}
+
+#[cfg(test)]
+mod theme_tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_theme() {
+        let theme = Theme::plain();
+        assert_eq!(theme.robot("你好"), "你好");
+        assert_eq!(theme.prompt("> "), "> ");
+    }
+
+    #[test]
+    fn test_colored_theme() {
+        let theme = Theme::colored();
+        let robot = theme.robot("你好");
+        assert!(robot.starts_with('\u{1b}'));
+        assert!(robot.contains("你好"));
+        assert_eq!(theme.prompt(""), "");
+    }
+
+    #[test]
+    fn test_parse_style() {
+        let style = parse_style("bold, dark_cyan").unwrap();
+        assert_eq!(style.foreground_color, Some(Color::DarkCyan));
+        assert!(style.attributes.has(Attribute::Bold));
+        assert_eq!(parse_style("").unwrap(), ContentStyle::new());
+        assert_eq!(parse_style("bold,sparkly"), Err("sparkly".to_string()));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::listener::EventListener;
+use crate::parser::MatchBlock;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+///
+/// 跟踪解释器的运行过程: 进入的阶段、尝试及匹配成功的匹配块、定义的变量和运行时错误，
+/// 每个事件输出一行，用于在不重新编译的情况下排查线上对话的问题
+/// 注册为解释器的事件监听器后生效
+///
+pub struct Tracer {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Tracer {
+    ///
+    /// 创建输出到指定位置的跟踪器
+    ///
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    ///
+    /// 创建输出到标准错误的跟踪器
+    ///
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    ///
+    /// 创建输出到文件的跟踪器，文件已存在时覆盖
+    ///
+    /// # 返回值
+    /// * 成功返回跟踪器，无法创建文件时返回io::Error
+    ///
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+
+    ///
+    /// 输出一行跟踪信息，输出失败时忽略，不影响对话
+    ///
+    fn trace(&self, line: &str) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "[trace] {}", line);
+            let _ = out.flush();
+        }
+    }
+}
+
+impl EventListener for Tracer {
+    fn on_stage_enter(&self, stage: &str) {
+        self.trace(&format!("enter {}", stage));
+    }
+
+    fn on_pattern(&self, stage: &str, block: &MatchBlock, matched: bool) {
+        let result = if matched { "matched" } else { "no match" };
+        self.trace(&format!("{}: try {} -> {}", stage, block.pattern, result));
+    }
+
+    fn on_match(&self, stage: &str, block: &MatchBlock) {
+        self.trace(&format!("{}: next {}", stage, block.next_stage));
+    }
+
+    fn on_variable(&self, stage: &str, name: &str, value: &str) {
+        self.trace(&format!("{}: set {} = {:?}", stage, name, value));
+    }
+
+    fn on_error(&self, stage: &str, message: &str) {
+        self.trace(&format!("{}: error {}", stage, message));
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::parse_source;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracer() {
+        let source = r#"
+            STAGE initial
+            SPEAK "你叫什么名字"
+            INPUT name
+            NEXT menu
+            STAGE menu
+            SPEAK "需要什么服务"
+            MATCH "退款"
+            NEXT EXIT
+            MATCH "查询"
+            NEXT EXIT
+            "#;
+        let parser = parse_source(source);
+        let buffer = Buffer::default();
+        let mut interpreter = Interpreter::builder()
+            .listener(Tracer::new(buffer.clone()))
+            .build();
+        interpreter.load(parser.stages);
+        interpreter.step(None).unwrap();
+        interpreter.step(None).unwrap();
+        interpreter.step(Some("Alice")).unwrap();
+        interpreter.step(None).unwrap();
+        interpreter.step(Some("查询")).unwrap();
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            trace,
+            "[trace] enter initial\n\
+             [trace] initial: set name = \"Alice\"\n\
+             [trace] enter menu\n\
+             [trace] menu: try \"退款\" -> no match\n\
+             [trace] menu: try \"查询\" -> matched\n\
+             [trace] menu: next EXIT\n"
+        );
+    }
+}
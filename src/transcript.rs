@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///
+/// 对话记录中的发言方
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Speaker {
+    /// 机器人的输出
+    Robot,
+    /// 用户的输入
+    User,
+}
+
+impl fmt::Display for Speaker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speaker::Robot => write!(f, "Robot"),
+            Speaker::User => write!(f, "User"),
+        }
+    }
+}
+
+///
+/// 对话记录中的一条发言
+/// - timestamp: 发言时间，自UNIX纪元起的毫秒数
+/// - stage: 发言时所处的阶段
+/// - speaker: 发言方
+/// - text: 发言内容
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub timestamp: u64,
+    pub stage: String,
+    pub speaker: Speaker,
+    pub text: String,
+}
+
+///
+/// 解释器与其他线程(如Ctrl-C处理函数)共享的对话记录，为None时不记录
+///
+pub type SharedTranscript = Arc<Mutex<Option<Transcript>>>;
+
+///
+/// 对话记录，按时间顺序保存机器人的输出和用户的输入，用于质检和纠纷核查
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    ///
+    /// 创建一个空的对话记录
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// 以当前时间追加一条发言
+    ///
+    /// # 参数
+    /// * stage: 发言时所处的阶段
+    /// * speaker: 发言方
+    /// * text: 发言内容
+    ///
+    pub fn record(&mut self, stage: &str, speaker: Speaker, text: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.entries.push(TranscriptEntry {
+            timestamp,
+            stage: stage.to_string(),
+            speaker,
+            text: text.to_string(),
+        });
+    }
+
+    ///
+    /// 将对话记录保存为JSON文件
+    ///
+    /// # 参数
+    /// * path: 文件路径
+    ///
+    /// # 返回值
+    /// * 成功返回Ok，IO或序列化失败返回io::Error
+    ///
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "[{}] {} {}: {}",
+                entry.timestamp, entry.stage, entry.speaker, entry.text
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod transcript_tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_save() {
+        let mut transcript = Transcript::new();
+        transcript.record("initial", Speaker::Robot, "你叫什么名字");
+        transcript.record("initial", Speaker::User, "floation");
+        assert_eq!(transcript.entries.len(), 2);
+        assert_eq!(transcript.entries[1].speaker, Speaker::User);
+        assert!(transcript.entries[0].timestamp <= transcript.entries[1].timestamp);
+        assert!(transcript.to_string().ends_with("initial User: floation\n"));
+
+        let path = std::env::temp_dir().join("service_robot_transcript_test.json");
+        transcript.save(&path).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let loaded: Transcript = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, transcript);
+    }
+}
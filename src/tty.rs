@@ -0,0 +1,52 @@
+use crossterm::{cursor, terminal, ExecutableCommand};
+use std::io;
+use std::panic;
+
+///
+/// 终端原始模式的守卫
+/// 创建时启用原始模式并隐藏光标，离开作用域(包括panic展开)时恢复终端
+///
+pub struct RawModeGuard {
+    _private: (),
+}
+
+impl RawModeGuard {
+    ///
+    /// 启用原始模式并隐藏光标
+    ///
+    /// # 返回值
+    /// * 成功返回守卫，终端不支持时返回io::Error
+    ///
+    pub fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // 先创建守卫，隐藏光标失败时也能恢复原始模式
+        let guard = Self { _private: () };
+        io::stdout().execute(cursor::Hide)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+///
+/// 恢复终端: 显示光标并退出原始模式，终端已处于正常模式时不产生影响
+///
+pub fn restore() {
+    let _ = io::stdout().execute(cursor::Show);
+    let _ = terminal::disable_raw_mode();
+}
+
+///
+/// 安装panic钩子，在输出panic信息之前恢复终端，以免原始模式下的信息错乱且终端无法使用
+///
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        default_hook(info);
+    }));
+}
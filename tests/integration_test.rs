@@ -1,3 +1,5 @@
+#![allow(clippy::assertions_on_constants, clippy::upper_case_acronyms)]
+
 use service_robot::{error::Error, interpreter::Interpreter, parser::DSLParser, scanner::Scanner};
 
 struct DSL {
@@ -26,6 +28,7 @@ impl DSL {
         let commands = scanner.scan()?;
         let mut parser = DSLParser::new();
         parser.parse(commands)?;
+        self.interpreter.on_error = parser.on_error.clone();
         self.interpreter.interpret(&parser.stages)
     }
 }
@@ -40,19 +43,32 @@ fn test_run() {
 #[test]
 fn test_run_error() {
     let mut dsl = DSL::new();
-    let path = "scripts/script_unknown_stage.txt";
-    if let Err(Error::Runtime) = dsl.run(path) {
+    let path = "scripts/script_unknown_var.txt";
+    if let Err(Error::Runtime { .. }) = dsl.run(path) {
         assert!(true);
     } else {
         assert!(false);
     }
 }
 
+#[test]
+fn test_unknown_stage() {
+    // 转移到未定义的阶段在解析时报告
+    let mut dsl = DSL::new();
+    let path = "scripts/script_unknown_stage.txt";
+    let error = dsl.run(path).unwrap_err();
+    assert_eq!(error.to_string(), "ParseError");
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.line, 4);
+    assert_eq!(diagnostic.code, Some("E201"));
+    assert_eq!(diagnostic.message, "Stage 'stage_out_of_nowhere' not found");
+}
+
 #[test]
 fn test_parse_error() {
     let mut dsl = DSL::new();
     let path = "scripts/script_incomplete_block.txt";
-    if let Err(Error::Parse) = dsl.run(path) {
+    if let Err(Error::Parse(_)) = dsl.run(path) {
         assert!(true);
     } else {
         assert!(false);
@@ -63,9 +79,302 @@ fn test_parse_error() {
 fn test_scan_error() {
     let mut dsl = DSL::new();
     let path = "scripts/script_nonexist_grammar.txt";
-    if let Err(Error::Scan) = dsl.run(path) {
+    if let Err(Error::Scan(_)) = dsl.run(path) {
         assert!(true);
     } else {
         assert!(false);
     }
 }
+
+#[test]
+fn test_run_on_error() {
+    let mut dsl = DSL::new();
+    let path = "scripts/script_on_error.txt";
+    assert!(dsl.run(path).is_ok());
+}
+
+#[test]
+fn test_run_goto() {
+    let mut dsl = DSL::new();
+    let path = "scripts/script_goto.txt";
+    assert!(dsl.run(path).is_ok());
+}
+
+#[test]
+fn test_run_entry() {
+    // 从ENTRY声明的阶段开始，不经过initial
+    let mut dsl = DSL::new();
+    let path = "scripts/script_entry.txt";
+    assert!(dsl.run(path).is_ok());
+}
+
+#[test]
+fn test_piped_input() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .arg("scripts/script_input.txt")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all("打个招呼\nfloation\n".as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("你叫什么名字\nfloation\n你好floation\n"));
+    // 输入结束时先按DEFAULT分支转移，再次需要输入时正常结束对话
+    assert!(stdout.ends_with("请问你有什么需要帮忙的\n听不懂命令\n请问你有什么需要帮忙的\n"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_env_file() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let path = std::env::temp_dir().join("service_robot_env_file_test.json");
+    let _ = std::fs::remove_file(&path);
+    let run = |input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+            .arg("scripts/script_input.txt")
+            .arg(format!("--env-file={}", path.display()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+    assert!(run("打个招呼\nfloation\n").status.success());
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("\"name\": \"floation\""));
+    // 再次运行时读取上次保存的变量，未重新输入的变量保持不变
+    assert!(run("再见\n").status.success());
+    let saved = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(saved.contains("\"name\": \"floation\""));
+}
+
+#[test]
+fn test_var_option() {
+    use std::process::Command;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args([
+            "--var",
+            "name=Alice",
+            "--var=$store=A12",
+            "scripts/script_var.txt",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "欢迎光临A12门店，Alice\n");
+    assert!(output.status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args([
+            "--env-prefix=BOT_",
+            "--var=$store=A12",
+            "scripts/script_var.txt",
+        ])
+        .env("BOT_NAME", "Bob")
+        .env("BOT_$STORE", "B34")
+        .output()
+        .unwrap();
+    // 环境变量不能导入受保护的变量
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "欢迎光临A12门店，Bob\n"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args(["--var", "name", "scripts/script_var.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn test_check_command() {
+    use std::process::Command;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args([
+            "check",
+            "scripts/script_goto.txt",
+            "scripts/script_regex.txt",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "scripts/script_goto.txt: ok\nscripts/script_regex.txt: ok\n"
+    );
+    assert!(output.status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args([
+            "check",
+            "scripts/script_goto.txt",
+            "scripts/script_unknown_stage.txt",
+        ])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with("scripts/script_unknown_stage.txt: failed\n"));
+    assert_eq!(output.status.code(), Some(65));
+    // --check只检查脚本，不进入对话
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args(["--check", "scripts/script_input.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "scripts/script_input.txt: ok\n"
+    );
+    assert!(output.status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args(["--check", "scripts/script_nonexist_grammar.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(67));
+    // --lint与--check相同，可以检查多个脚本
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args([
+            "--lint",
+            "scripts/script_goto.txt",
+            "scripts/script_unknown_stage.txt",
+        ])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with("scripts/script_goto.txt: ok\nscripts/script_unknown_stage.txt: failed\n"));
+    assert_eq!(output.status.code(), Some(65));
+    // 没有脚本路径时报告用法错误，不提示输入路径
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .arg("--check")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_stdin_script() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let script = std::fs::read("scripts/script_goto.txt").unwrap();
+    for args in [&["-"][..], &["--stdin"][..]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&script).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "欢迎光临\n本店营业时间为9:00-21:00\n"
+        );
+        assert!(output.status.success());
+    }
+}
+
+#[test]
+fn test_answers_file() {
+    use std::process::Command;
+
+    let path = std::env::temp_dir().join("service_robot_answers_test.txt");
+    std::fs::write(&path, "打个招呼\nfloation\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .arg("--answers")
+        .arg(&path)
+        .arg("scripts/script_input.txt")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("你叫什么名字\nfloation\n你好floation\n"));
+    assert!(output.status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args(["--answers=missing.txt", "scripts/script_input.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(74));
+}
+
+#[test]
+fn test_interrupt_saves_transcript() {
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join("service_robot_interrupt_test.json");
+    let env_path = std::env::temp_dir().join("service_robot_interrupt_env.json");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&env_path);
+    let child = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .arg("scripts/script_goto.txt")
+        .arg("--typewriter=150")
+        .arg(format!("--transcript={}", path.display()))
+        .arg(format!("--env-file={}", env_path.display()))
+        .arg("--var=city=北京")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // 第一句输出完成后，在逐字输出第二句时中断
+    std::thread::sleep(Duration::from_millis(1200));
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    let saved = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(saved.contains("欢迎光临"));
+    // 已注入的变量同样保存到环境变量文件
+    let saved = std::fs::read_to_string(&env_path).unwrap();
+    std::fs::remove_file(&env_path).unwrap();
+    assert!(saved.contains("北京"));
+}
+
+#[test]
+fn test_emit_json_describes_script() {
+    use std::process::Command;
+
+    // --emit-json输出脚本的结构(阶段、转移、输出内容及元信息)，供网页流程图及管理面板使用
+    let output = Command::new(env!("CARGO_BIN_EXE_service-robot"))
+        .args(["--emit-json", "scripts/script_meta.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["meta"]["name"], "营业时间查询");
+    assert_eq!(json["meta"]["version"], "1.2");
+    assert_eq!(json["meta"]["author"], "floation");
+    let initial = &json["stages"]["initial"];
+    assert_eq!(initial["speak"], "\"欢迎光临\"");
+    assert_eq!(initial["transition"]["Goto"], "notice");
+    assert_eq!(initial["entry"], true);
+    assert_eq!(json["stages"]["notice"]["transition"]["Goto"], "EXIT");
+}